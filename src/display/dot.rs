@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+
+use crate::graph::collection::ExchangeCompleteGraph;
+use crate::symbol::{CurrencyId, ExchangeId};
+
+const HIGHLIGHT: &str = "color=red, penwidth=2";
+
+/// Renders the graph in the Graphviz DOT format. The nodes are clustered by exchange and
+/// labeled by currency, the trade edges show their rate and the age of their quote
+/// at `now`, the transfers are dashed. The edges along the path of (exchange, currency)
+/// are highlighted
+pub fn render(
+    exchange_graph: &ExchangeCompleteGraph,
+    now: DateTime<Utc>,
+    highlighted_path: &[(String, String)],
+) -> String {
+    let index_map = &exchange_graph.index_map;
+    let path_nodes: Vec<usize> = highlighted_path
+        .iter()
+        .filter_map(|(exchange, currency)| {
+            let key = (ExchangeId::get(exchange)?, CurrencyId::get(currency)?);
+
+            index_map.get(&key).cloned()
+        })
+        .collect();
+    let is_highlighted = |from_node: usize, to_node: usize| {
+        path_nodes
+            .windows(2)
+            .any(|edge| edge == [from_node, to_node])
+    };
+
+    let mut exchanges: BTreeMap<ExchangeId, Vec<(usize, CurrencyId)>> = BTreeMap::new();
+    for (&(exchange, currency), &node) in index_map.iter() {
+        exchanges
+            .entry(exchange)
+            .or_default()
+            .push((node, currency));
+    }
+
+    let mut dot = String::from("digraph exchanges {\n");
+    for (exchange, mut nodes) in exchanges {
+        nodes.sort();
+
+        writeln!(
+            dot,
+            "    subgraph {} {{",
+            quoted(&format!("cluster_{}", exchange))
+        )
+        .unwrap();
+        writeln!(dot, "        label={};", quoted(exchange.as_str())).unwrap();
+        for (node, currency) in nodes {
+            let style = if path_nodes.contains(&node) {
+                format!(", {}", HIGHLIGHT)
+            } else {
+                String::new()
+            };
+
+            writeln!(
+                dot,
+                "        {} [label={}{}];",
+                node,
+                quoted(currency.as_str()),
+                style
+            )
+            .unwrap();
+        }
+        dot.push_str("    }\n");
+    }
+
+    let mut edges: Vec<(usize, usize, f64)> = exchange_graph
+        .get_edges()
+        .map(|(from_node, to_node, &weight)| (from_node, to_node, weight))
+        .collect();
+    edges.sort_by_key(|&(from_node, to_node, _)| (from_node, to_node));
+
+    for (from_node, to_node, weight) in edges {
+        let mut attributes = match exchange_graph.quotes.get(&(from_node, to_node)) {
+            Some(quote) => {
+                let age = now.signed_duration_since(quote.timestamp).num_seconds();
+                format!("label={}", quoted(&format!("{} ({}s)", weight, age)))
+            }
+            None => format!("label={}, style=dashed", quoted(&weight.to_string())),
+        };
+        if is_highlighted(from_node, to_node) {
+            attributes.push_str(", ");
+            attributes.push_str(HIGHLIGHT);
+        }
+
+        writeln!(dot, "    {} -> {} [{}];", from_node, to_node, attributes).unwrap();
+    }
+    dot.push_str("}\n");
+
+    dot
+}
+
+/// A DOT string, with the quotes and the backslashes escaped
+fn quoted(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::command::PriceUpdate;
+
+    #[test]
+    fn it_clusters_the_nodes_by_exchange_and_highlights_the_path() {
+        let now = Utc::now();
+        let mut exchange_graph = ExchangeCompleteGraph::default();
+        exchange_graph.add(&PriceUpdate::new(
+            now - Duration::seconds(30),
+            "KRAKEN",
+            "BTC",
+            "USD",
+            1000.0,
+            0.0009,
+        ));
+        exchange_graph.add(&PriceUpdate::new(now, "GDAX", "BTC", "EUR", 900.0, 0.001));
+
+        let path = vec![
+            ("GDAX".to_owned(), "EUR".to_owned()),
+            ("GDAX".to_owned(), "BTC".to_owned()),
+            ("KRAKEN".to_owned(), "BTC".to_owned()),
+        ];
+        let dot = render(&exchange_graph, now, &path);
+
+        let expected = r#"digraph exchanges {
+    subgraph "cluster_GDAX" {
+        label="GDAX";
+        2 [label="BTC", color=red, penwidth=2];
+        3 [label="EUR", color=red, penwidth=2];
+    }
+    subgraph "cluster_KRAKEN" {
+        label="KRAKEN";
+        0 [label="BTC", color=red, penwidth=2];
+        1 [label="USD"];
+    }
+    0 -> 1 [label="1000 (30s)"];
+    0 -> 2 [label="1", style=dashed];
+    1 -> 0 [label="0.0009 (30s)"];
+    2 -> 0 [label="1", style=dashed, color=red, penwidth=2];
+    2 -> 3 [label="900 (0s)"];
+    3 -> 2 [label="0.001 (0s)", color=red, penwidth=2];
+}
+"#;
+        assert_eq!(expected, dot);
+        assert_eq!("\"a \\\"b\\\" \\\\\"", quoted("a \"b\" \\"));
+    }
+}
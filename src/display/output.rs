@@ -1,18 +1,30 @@
 use std::fmt::*;
 
-struct BestRates {
-    source_exchange: String,
-    source_currency: String,
-    destination_exchange: String,
-    destination_currency: String,
-    rate: f64,
-    path: Vec<(String, String)>,
+use chrono::{DateTime, Utc};
+
+/// The amount of the source currency and the amount of the destination currency it's exchanged for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Amounts {
+    pub input: f64,
+    pub output: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestRates {
+    pub source_exchange: String,
+    pub source_currency: String,
+    pub destination_exchange: String,
+    pub destination_currency: String,
+    pub rate: f64,
+    pub path: Vec<(String, String)>,
+    /// Set when the rate is for exchanging a given amount
+    pub amounts: Option<Amounts>,
 }
 
 impl Display for BestRates {
     fn fmt(&self, f: &mut Formatter) -> Result {
         let mut lines = Vec::new();
-        let best_rates_begin = format!(
+        let mut best_rates_begin = format!(
             "BEST_RATES_BEGIN {source_exchange} {source_currency} {destination_exchange} {destination_currency} {rate}",
             source_exchange = self.source_exchange,
             source_currency = self.source_currency,
@@ -20,6 +32,9 @@ impl Display for BestRates {
             destination_currency = self.destination_currency,
             rate = self.rate,
         );
+        if let Some(amounts) = self.amounts {
+            best_rates_begin.push_str(&format!(" {} {}", amounts.input, amounts.output));
+        }
 
         lines.push(best_rates_begin);
 
@@ -39,6 +54,145 @@ impl Display for BestRates {
     }
 }
 
+/// An amount split over several paths, each of them with the part of the amount sent over it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitRoute {
+    pub source_exchange: String,
+    pub source_currency: String,
+    pub destination_exchange: String,
+    pub destination_currency: String,
+    /// What is sent over the routes and what they give
+    pub amounts: Amounts,
+    /// The part of the amount which no route could take
+    pub unfilled: f64,
+    pub routes: Vec<BestRates>,
+}
+
+impl Display for SplitRoute {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        writeln!(
+            f,
+            "SPLIT_ROUTE_BEGIN {} {} {} {} {} {} {}",
+            self.source_exchange,
+            self.source_currency,
+            self.destination_exchange,
+            self.destination_currency,
+            self.amounts.input,
+            self.amounts.output,
+            self.unfilled
+        )?;
+
+        for route in &self.routes {
+            writeln!(f, "{}", route)?;
+        }
+
+        write!(f, "SPLIT_ROUTE_END")
+    }
+}
+
+/// A trade edge left out of the searches, since its quote is older than the max age
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleEdge {
+    pub exchange: String,
+    pub source_currency: String,
+    pub destination_currency: String,
+    pub timestamp: DateTime<Utc>,
+    /// The age of the quote in seconds
+    pub age: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StaleEdges {
+    pub edges: Vec<StaleEdge>,
+}
+
+impl Display for StaleEdges {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        writeln!(f, "STALE_EDGES_BEGIN {}", self.edges.len())?;
+
+        for edge in &self.edges {
+            writeln!(
+                f,
+                "{} {} {} {} {}",
+                edge.exchange,
+                edge.source_currency,
+                edge.destination_currency,
+                edge.timestamp.to_rfc3339(),
+                edge.age
+            )?;
+        }
+
+        write!(f, "STALE_EDGES_END")
+    }
+}
+
+/// The size and the health of the graph and of the feed updating it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphStats {
+    pub exchanges: usize,
+    pub currencies: usize,
+    pub nodes: usize,
+    pub trade_edges: usize,
+    pub transfer_edges: usize,
+    pub strongly_connected_components: usize,
+    /// The timestamps of the oldest and the newest of the latest updates of the pairs
+    pub oldest_quote: Option<DateTime<Utc>>,
+    pub newest_quote: Option<DateTime<Utc>>,
+    /// The exchanges with the most pairs and their pairs count, the most first
+    pub top_exchanges: Vec<(String, usize)>,
+}
+
+impl Display for GraphStats {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let timestamp = |timestamp: Option<DateTime<Utc>>| {
+            timestamp.map_or_else(|| "NONE".to_string(), |timestamp| timestamp.to_rfc3339())
+        };
+
+        writeln!(f, "GRAPH_STATS_BEGIN")?;
+        writeln!(f, "EXCHANGES {}", self.exchanges)?;
+        writeln!(f, "CURRENCIES {}", self.currencies)?;
+        writeln!(f, "NODES {}", self.nodes)?;
+        writeln!(f, "TRADE_EDGES {}", self.trade_edges)?;
+        writeln!(f, "TRANSFER_EDGES {}", self.transfer_edges)?;
+        writeln!(
+            f,
+            "STRONGLY_CONNECTED_COMPONENTS {}",
+            self.strongly_connected_components
+        )?;
+        writeln!(f, "OLDEST_QUOTE {}", timestamp(self.oldest_quote))?;
+        writeln!(f, "NEWEST_QUOTE {}", timestamp(self.newest_quote))?;
+        writeln!(f, "TOP_EXCHANGES {}", self.top_exchanges.len())?;
+
+        for (exchange, pairs_count) in &self.top_exchanges {
+            writeln!(f, "{} {}", exchange, pairs_count)?;
+        }
+
+        write!(f, "GRAPH_STATS_END")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ProfitableCycle {
+    /// The gross multiplier of going once around the cycle
+    pub rate: f64,
+    /// The (exchange, currency) hops of the cycle, the first and the last are the same
+    pub path: Vec<(String, String)>,
+    /// The most edges of the cycles searched, the longer ones are not listed
+    pub max_length: usize,
+}
+
+impl Display for ProfitableCycle {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        writeln!(f, "ARBITRAGE_BEGIN {} {}", self.rate, self.max_length)?;
+
+        for (exchange, currency) in &self.path {
+            writeln!(f, "{} {}", exchange, currency)?;
+        }
+
+        write!(f, "ARBITRAGE_END")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -65,6 +219,7 @@ mod test {
                 ("KRAKEN".to_string(), "USD".to_string()),
                 (destination_exchange, destination_currency),
             ],
+            amounts: None,
         };
 
         let expected = "BEST_RATES_BEGIN EXCI EUR GDAX USD 14.4
@@ -77,4 +232,142 @@ GDAX USD
 BEST_RATES_END";
         assert_eq!(expected, format!("{}", best_rates));
     }
+
+    #[test]
+    fn it_displays_the_amounts_of_the_best_rates() {
+        let best_rates = BestRates {
+            source_exchange: "KRAKEN".to_string(),
+            source_currency: "BTC".to_string(),
+            destination_exchange: "KRAKEN".to_string(),
+            destination_currency: "USD".to_string(),
+            rate: 950.0,
+            path: vec![
+                ("KRAKEN".to_string(), "BTC".to_string()),
+                ("KRAKEN".to_string(), "USD".to_string()),
+            ],
+            amounts: Some(Amounts {
+                input: 2.0,
+                output: 1900.0,
+            }),
+        };
+
+        let expected = "BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 950 2 1900
+KRAKEN BTC
+KRAKEN USD
+BEST_RATES_END";
+        assert_eq!(expected, format!("{}", best_rates));
+    }
+
+    #[test]
+    fn it_displays_the_routes_of_the_split_route() {
+        let route = |exchange: &str, input, output| BestRates {
+            source_exchange: "KRAKEN".to_string(),
+            source_currency: "BTC".to_string(),
+            destination_exchange: exchange.to_string(),
+            destination_currency: "USD".to_string(),
+            rate: output / input,
+            path: vec![
+                ("KRAKEN".to_string(), "BTC".to_string()),
+                (exchange.to_string(), "USD".to_string()),
+            ],
+            amounts: Some(Amounts { input, output }),
+        };
+        let split_route = SplitRoute {
+            source_exchange: "KRAKEN".to_string(),
+            source_currency: "BTC".to_string(),
+            destination_exchange: "KRAKEN".to_string(),
+            destination_currency: "USD".to_string(),
+            amounts: Amounts {
+                input: 3.0,
+                output: 2900.0,
+            },
+            unfilled: 0.5,
+            routes: vec![route("KRAKEN", 2.0, 2000.0), route("GDAX", 1.0, 900.0)],
+        };
+
+        let expected = "SPLIT_ROUTE_BEGIN KRAKEN BTC KRAKEN USD 3 2900 0.5
+BEST_RATES_BEGIN KRAKEN BTC KRAKEN USD 1000 2 2000
+KRAKEN BTC
+KRAKEN USD
+BEST_RATES_END
+BEST_RATES_BEGIN KRAKEN BTC GDAX USD 900 1 900
+KRAKEN BTC
+GDAX USD
+BEST_RATES_END
+SPLIT_ROUTE_END";
+        assert_eq!(expected, format!("{}", split_route));
+    }
+
+    #[test]
+    fn it_displays_the_stale_edges_with_their_age() {
+        let stale_edges = StaleEdges {
+            edges: vec![StaleEdge {
+                exchange: "KRAKEN".to_string(),
+                source_currency: "BTC".to_string(),
+                destination_currency: "USD".to_string(),
+                timestamp: "2017-11-01T09:42:23+00:00".parse().unwrap(),
+                age: 3600,
+            }],
+        };
+
+        let expected = "STALE_EDGES_BEGIN 1
+KRAKEN BTC USD 2017-11-01T09:42:23+00:00 3600
+STALE_EDGES_END";
+        assert_eq!(expected, format!("{}", stale_edges));
+    }
+
+    #[test]
+    fn it_displays_the_graph_stats() {
+        let graph_stats = GraphStats {
+            exchanges: 2,
+            currencies: 3,
+            nodes: 4,
+            trade_edges: 4,
+            transfer_edges: 2,
+            strongly_connected_components: 1,
+            oldest_quote: Some("2017-11-01T09:42:23+00:00".parse().unwrap()),
+            newest_quote: Some("2017-11-01T09:43:23+00:00".parse().unwrap()),
+            top_exchanges: vec![("GDAX".to_string(), 1), ("KRAKEN".to_string(), 1)],
+        };
+
+        let expected = "GRAPH_STATS_BEGIN
+EXCHANGES 2
+CURRENCIES 3
+NODES 4
+TRADE_EDGES 4
+TRANSFER_EDGES 2
+STRONGLY_CONNECTED_COMPONENTS 1
+OLDEST_QUOTE 2017-11-01T09:42:23+00:00
+NEWEST_QUOTE 2017-11-01T09:43:23+00:00
+TOP_EXCHANGES 2
+GDAX 1
+KRAKEN 1
+GRAPH_STATS_END";
+        assert_eq!(expected, format!("{}", graph_stats));
+        assert!(format!("{}", GraphStats::default()).contains("OLDEST_QUOTE NONE\n"));
+    }
+
+    #[test]
+    fn it_displays_correctly_the_profitable_cycle() {
+        let profitable_cycle = ProfitableCycle {
+            rate: 1.08,
+            path: vec![
+                ("KRAKEN".to_string(), "USD".to_string()),
+                ("KRAKEN".to_string(), "BTC".to_string()),
+                ("GDAX".to_string(), "BTC".to_string()),
+                ("GDAX".to_string(), "USD".to_string()),
+                ("KRAKEN".to_string(), "USD".to_string()),
+            ],
+            max_length: 6,
+        };
+
+        let expected = "ARBITRAGE_BEGIN 1.08 6
+KRAKEN USD
+KRAKEN BTC
+GDAX BTC
+GDAX USD
+KRAKEN USD
+ARBITRAGE_END";
+        assert_eq!(expected, format!("{}", profitable_cycle));
+    }
 }
@@ -1,3 +1,10 @@
+pub mod book;
 pub mod collection;
+pub mod fees;
 pub mod handler;
+pub mod history;
+pub mod matrix;
+pub mod snapshot;
+pub mod staleness;
 pub mod trade;
+pub mod wal;
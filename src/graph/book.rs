@@ -0,0 +1,209 @@
+use std::collections::hash_map::Values;
+use std::collections::HashMap;
+
+use crate::command::{BookLevel, OrderBookUpdate};
+use crate::symbol::{CurrencyId, ExchangeId};
+
+/// The latest order book of each (exchange, base currency, quote currency)
+#[derive(Debug, Default)]
+pub struct OrderBooks {
+    books: HashMap<(ExchangeId, CurrencyId, CurrencyId), OrderBookUpdate>,
+}
+
+impl OrderBooks {
+    /// Whether the book is newer than the one kept for the pair, if there is one
+    pub fn is_newer(&self, order_book: &OrderBookUpdate) -> bool {
+        match self.books.get(&book_key(order_book)) {
+            Some(current_book) => current_book.timestamp < order_book.timestamp,
+            None => true,
+        }
+    }
+
+    /// Keeps the book unless there is a newer one for the pair, returns whether it was kept
+    pub fn insert(&mut self, order_book: OrderBookUpdate) -> bool {
+        if !self.is_newer(&order_book) {
+            return false;
+        }
+
+        self.books.insert(book_key(&order_book), order_book);
+        true
+    }
+
+    /// Removes the book of the pair, whichever of the currencies is the base one
+    pub fn remove(
+        &mut self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+    ) {
+        self.books.remove(&(exchange, currency, other_currency));
+        self.books.remove(&(exchange, other_currency, currency));
+    }
+
+    pub fn remove_exchange(&mut self, exchange: ExchangeId) {
+        self.books
+            .retain(|&(book_exchange, _, _), _| book_exchange != exchange);
+    }
+
+    /// The book of the pair, whichever of the currencies is the base one
+    pub fn get(
+        &self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+    ) -> Option<&OrderBookUpdate> {
+        self.books
+            .get(&(exchange, currency, other_currency))
+            .or_else(|| self.books.get(&(exchange, other_currency, currency)))
+    }
+
+    pub fn books(&self) -> Values<'_, (ExchangeId, CurrencyId, CurrencyId), OrderBookUpdate> {
+        self.books.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.books.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+}
+
+/// The books are kept by the exchange and the currencies of the pair
+fn book_key(order_book: &OrderBookUpdate) -> (ExchangeId, CurrencyId, CurrencyId) {
+    (
+        order_book.exchange,
+        order_book.base_currency,
+        order_book.quote_currency,
+    )
+}
+
+/// The amount of the other currency of the book for the `amount` of `from_currency`,
+/// `None` if the book is not deep enough
+pub fn execute(
+    order_book: &OrderBookUpdate,
+    from_currency: CurrencyId,
+    amount: f64,
+) -> Option<f64> {
+    if from_currency == order_book.base_currency {
+        sell(&order_book.bids, amount)
+    } else {
+        buy(&order_book.asks, amount)
+    }
+}
+
+/// Sells the amount of the base currency to the best bids first
+fn sell(bids: &[BookLevel], amount: f64) -> Option<f64> {
+    let mut remaining = amount;
+    let mut output = 0.0;
+
+    for level in bids {
+        let quantity = remaining.min(level.quantity);
+
+        output += quantity * level.price;
+        remaining -= quantity;
+
+        if remaining <= 0.0 {
+            return Some(output);
+        }
+    }
+
+    None
+}
+
+/// Spends the amount of the quote currency on the best asks first
+fn buy(asks: &[BookLevel], amount: f64) -> Option<f64> {
+    let mut remaining = amount;
+    let mut output = 0.0;
+
+    for level in asks {
+        let level_cost = level.quantity * level.price;
+
+        if remaining <= level_cost {
+            return Some(output + remaining / level.price);
+        }
+
+        output += level.quantity;
+        remaining -= level_cost;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::prelude::*;
+
+    use super::*;
+
+    fn order_book(timestamp: DateTime<Utc>) -> OrderBookUpdate {
+        OrderBookUpdate {
+            timestamp,
+            exchange: "KRAKEN".into(),
+            base_currency: "BTC".into(),
+            quote_currency: "USD".into(),
+            bids: vec![
+                BookLevel {
+                    price: 1000.0,
+                    quantity: 1.0,
+                },
+                BookLevel {
+                    price: 900.0,
+                    quantity: 2.0,
+                },
+            ],
+            asks: vec![
+                BookLevel {
+                    price: 1100.0,
+                    quantity: 1.0,
+                },
+                BookLevel {
+                    price: 1250.0,
+                    quantity: 2.0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn it_walks_the_levels_of_the_book() {
+        let order_book = order_book(Utc::now());
+
+        // selling BTC for USD to the bids
+        assert_eq!(Some(500.0), execute(&order_book, "BTC".into(), 0.5));
+        assert_eq!(
+            Some(1000.0 + 900.0),
+            execute(&order_book, "BTC".into(), 2.0)
+        );
+        assert_eq!(None, execute(&order_book, "BTC".into(), 3.5));
+
+        // buying BTC with USD from the asks
+        assert_eq!(Some(0.5), execute(&order_book, "USD".into(), 550.0));
+        assert_eq!(
+            Some(1.0 + 0.2),
+            execute(&order_book, "USD".into(), 1100.0 + 250.0)
+        );
+        assert_eq!(None, execute(&order_book, "USD".into(), 5000.0));
+    }
+
+    #[test]
+    fn it_keeps_the_newest_book_of_the_pair() {
+        let mut order_books = OrderBooks::default();
+        let timestamp = Utc.ymd(2017, 11, 1).and_hms(10, 0, 0);
+
+        assert!(order_books.insert(order_book(timestamp)));
+        assert!(!order_books.insert(order_book(Utc.ymd(2017, 11, 1).and_hms(9, 0, 0))));
+        assert_eq!(1, order_books.len());
+
+        assert_eq!(
+            Some(timestamp),
+            order_books
+                .get("KRAKEN".into(), "USD".into(), "BTC".into())
+                .map(|order_book| order_book.timestamp)
+        );
+        assert!(order_books
+            .get("GDAX".into(), "BTC".into(), "USD".into())
+            .is_none());
+    }
+}
@@ -1,12 +1,15 @@
 use std::collections::hash_map::Iter;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::hash::Hash;
 
+use chrono::{DateTime, Utc};
 use petgraph::graphmap::DiGraphMap;
 
 use crate::command::PriceUpdate;
+use crate::graph::fees::{EdgeCost, TradingFees, TransferCost, TransferCosts, WithdrawalFees};
+use crate::symbol::{CurrencyId, ExchangeId};
 use petgraph::graphmap::AllEdges;
-use petgraph::Directed;
+use petgraph::{Directed, Direction};
 
 #[derive(Debug, PartialEq)]
 pub enum Index {
@@ -23,13 +26,16 @@ impl Index {
     }
 }
 
+/// Dense indices for the keys, the indices of the removed keys are recycled
+/// for the next inserted keys, the smallest one first
 #[derive(Debug)]
 pub struct IndexHashMap<K>
 where
     K: Eq + Hash,
 {
     hash_map: HashMap<K, usize>,
-    indices: Vec<K>,
+    indices: Vec<Option<K>>,
+    free_indices: BTreeSet<usize>,
 }
 
 impl<K> IndexHashMap<K>
@@ -40,21 +46,58 @@ where
         Self {
             hash_map: HashMap::<K, usize>::new(),
             indices: Vec::new(),
+            free_indices: BTreeSet::new(),
         }
     }
 
-    pub fn iter(&self) -> Iter<K, usize> {
+    /// The keys at their indices, `None` for the free indices
+    pub fn from_indices(indices: Vec<Option<K>>) -> Self {
+        let mut hash_map = HashMap::new();
+        let mut free_indices = BTreeSet::new();
+
+        for (index, key) in indices.iter().enumerate() {
+            match key {
+                Some(key) => {
+                    hash_map.insert(key.clone(), index);
+                }
+                None => {
+                    free_indices.insert(index);
+                }
+            }
+        }
+
+        Self {
+            hash_map,
+            indices,
+            free_indices,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, usize> {
         self.hash_map.iter()
     }
 
+    /// The keys by their index, `None` for the free indices
+    pub fn indices(&self) -> &[Option<K>] {
+        &self.indices
+    }
+
     pub fn entry(&mut self, key: K) -> Index {
         let hash_key = key.clone();
 
         match self.hash_map.get(&hash_key) {
             Some(index) => Index::Fetched(*index),
             None => {
-                self.indices.push(key);
-                let index = self.indices.len() - 1;
+                let index = match self.free_indices.pop_first() {
+                    Some(index) => {
+                        self.indices[index] = Some(key);
+                        index
+                    }
+                    None => {
+                        self.indices.push(Some(key));
+                        self.indices.len() - 1
+                    }
+                };
                 self.hash_map.insert(hash_key, index);
 
                 Index::Inserted(index)
@@ -62,6 +105,16 @@ where
         }
     }
 
+    /// Removes the key, its index will be used by the next inserted key
+    pub fn remove(&mut self, key: &K) -> Option<usize> {
+        let index = self.hash_map.remove(key)?;
+
+        self.indices[index] = None;
+        self.free_indices.insert(index);
+
+        Some(index)
+    }
+
     pub fn get(&self, key: &K) -> Option<&usize> {
         self.hash_map.get(key)
     }
@@ -71,71 +124,173 @@ where
     }
 
     pub fn get_index(&self, index: &usize) -> Option<&K> {
-        self.indices.get(*index)
+        self.indices.get(*index).and_then(Option::as_ref)
     }
 
     pub fn contains_index(&self, index: &usize) -> bool {
-        self.indices.get(*index).is_some()
+        self.get_index(index).is_some()
     }
 
     pub fn len(&self) -> usize {
-        self.indices.len()
+        self.hash_map.len()
     }
-}
 
-#[derive(Debug)]
-pub struct ExchangeCompleteGraph {
-    pub graph: DiGraphMap<usize, f64>,
-    pub index_map: IndexHashMap<(String, String)>,
+    pub fn is_empty(&self) -> bool {
+        self.hash_map.is_empty()
+    }
+
+    /// All the indices are below it, the ones of the removed keys as well
+    pub fn index_bound(&self) -> usize {
+        self.indices.len()
+    }
 }
 
-impl Default for ExchangeCompleteGraph {
+impl<K> Default for IndexHashMap<K>
+where
+    K: Eq + Hash + Clone,
+{
     fn default() -> Self {
-        Self {
-            graph: DiGraphMap::default(),
-            index_map: IndexHashMap::new(),
-        }
+        Self::new()
     }
 }
 
+/// The quoted factor of a trade edge before the trading fee, kept for display and audit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quote {
+    pub factor: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The (exchange, currency) keys of the nodes of an edge
+pub type EdgeKey = ((ExchangeId, CurrencyId), (ExchangeId, CurrencyId));
+
+#[derive(Debug, Default)]
+pub struct ExchangeCompleteGraph {
+    pub graph: DiGraphMap<usize, f64>,
+    pub index_map: IndexHashMap<(ExchangeId, CurrencyId)>,
+    pub transfer_costs: TransferCosts,
+    pub trading_fees: TradingFees,
+    pub withdrawal_fees: WithdrawalFees,
+    /// The maximum amount which can be sent over an edge, by the (exchange, currency) of its nodes
+    pub edge_limits: HashMap<EdgeKey, f64>,
+    /// The quotes of the trade edges by (from node, to node)
+    pub quotes: HashMap<(usize, usize), Quote>,
+    /// The nodes of each currency on all the exchanges
+    currency_nodes: HashMap<CurrencyId, BTreeSet<usize>>,
+    /// The nodes of each exchange
+    exchange_nodes: HashMap<ExchangeId, BTreeSet<usize>>,
+}
+
 impl ExchangeCompleteGraph {
     pub fn add(&mut self, price_update: &PriceUpdate) -> (Index, Index) {
-        let source_node = (
-            price_update.exchange.clone(),
-            price_update.source_currency.clone(),
-        );
-        let dest_node = (
-            price_update.exchange.clone(),
-            price_update.destination_currency.clone(),
-        );
+        let source_node = (price_update.exchange, price_update.source_currency);
+        let dest_node = (price_update.exchange, price_update.destination_currency);
 
-        let source_index = self.index_map.entry(source_node);
-        let dest_index = self.index_map.entry(dest_node);
+        let source_index = self.node_entry(source_node);
+        let dest_index = self.node_entry(dest_node);
 
         match (&source_index, &dest_index) {
             (&Index::Fetched(source_index), &Index::Fetched(dest_index)) => {
-                self.graph
-                    .add_edge(source_index, dest_index, price_update.forward_factor);
-                self.graph
-                    .add_edge(dest_index, source_index, price_update.backward_factor);
+                self.add_trade_edge(
+                    source_index,
+                    dest_index,
+                    price_update.forward_factor,
+                    price_update,
+                );
+                self.add_trade_edge(
+                    dest_index,
+                    source_index,
+                    price_update.backward_factor,
+                    price_update,
+                );
             }
             (&Index::Fetched(source_index), &Index::Inserted(dest_index)) => {
-                self.insert_for_exchange(&price_update, dest_index, Some(source_index), false);
+                self.insert_for_exchange(price_update, dest_index, Some(source_index), false);
             }
             (&Index::Inserted(source_index), &Index::Fetched(dest_index)) => {
                 self.insert_for_exchange(price_update, source_index, Some(dest_index), true);
             }
             (&Index::Inserted(source_index), &Index::Inserted(dest_index)) => {
                 // first insert the source node without a source
-                self.insert_for_exchange(&price_update, source_index, None, true);
+                self.insert_for_exchange(price_update, source_index, None, true);
                 // then add the destination node and add the edge & weight between source <-> destination
-                self.insert_for_exchange(&price_update, dest_index, Some(source_index), false);
+                self.insert_for_exchange(price_update, dest_index, Some(source_index), false);
             }
         }
 
         (source_index, dest_index)
     }
 
+    /// A graph without any nodes, with the fees, costs and limits of this one
+    pub fn empty_clone(&self) -> Self {
+        Self {
+            transfer_costs: self.transfer_costs.clone(),
+            trading_fees: self.trading_fees.clone(),
+            withdrawal_fees: self.withdrawal_fees.clone(),
+            edge_limits: self.edge_limits.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Puts the nodes at their indices, linking the same currencies on different exchanges.
+    /// The graph should be empty, so the updates added later find their nodes
+    pub fn restore_nodes(&mut self, nodes: Vec<Option<(ExchangeId, CurrencyId)>>) {
+        self.index_map = IndexHashMap::from_indices(nodes);
+
+        for node in 0..self.index_map.index_bound() {
+            let (exchange, currency) = match self.index_map.get_index(&node) {
+                Some(&key) => key,
+                None => continue,
+            };
+
+            self.graph.add_node(node);
+            self.currency_nodes
+                .entry(currency)
+                .or_default()
+                .insert(node);
+            self.exchange_nodes
+                .entry(exchange)
+                .or_default()
+                .insert(node);
+            self.link_transfers(node, exchange, currency);
+        }
+    }
+
+    /// The index of the node, a new node is added to the currency and exchange indices
+    fn node_entry(&mut self, key: (ExchangeId, CurrencyId)) -> Index {
+        let index = self.index_map.entry(key);
+
+        if let Index::Inserted(node) = index {
+            let (exchange, currency) = key;
+            self.currency_nodes
+                .entry(currency)
+                .or_default()
+                .insert(node);
+            self.exchange_nodes
+                .entry(exchange)
+                .or_default()
+                .insert(node);
+        }
+
+        index
+    }
+
+    /// The nodes of the currency on all the exchanges, ordered
+    pub fn currency_nodes(&self, currency: CurrencyId) -> Vec<usize> {
+        self.currency_nodes
+            .get(&currency)
+            .map(|nodes| nodes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The nodes of all the currencies of the exchange, ordered
+    pub fn exchange_nodes(&self, exchange: ExchangeId) -> Vec<usize> {
+        self.exchange_nodes
+            .get(&exchange)
+            .map(|nodes| nodes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
     /// Inserts a given exchange in the graph
     /// if `is_forward` is `true`, then the factors should be applied from node -> origin forward and origin-> node
     fn insert_for_exchange(
@@ -146,45 +301,301 @@ impl ExchangeCompleteGraph {
         is_forward: bool,
     ) {
         let currency = if is_forward {
-            &price_update.source_currency
+            price_update.source_currency
         } else {
-            &price_update.destination_currency
+            price_update.destination_currency
         };
 
-        let same_exchange =
-            self.index_map
-                .iter()
-                .filter(|((_, exchange_currency), &node_index)| {
-                    currency == exchange_currency && node_index != node
-                });
+        self.link_transfers(node, price_update.exchange, currency);
+
+        if let Some(origin_index) = origin {
+            let forward_factor = if is_forward {
+                price_update.forward_factor
+            } else {
+                price_update.backward_factor
+            };
+            let backward_factor = if is_forward {
+                price_update.backward_factor
+            } else {
+                price_update.forward_factor
+            };
+
+            // Node -> origin is forward if `is_forward` is `true`
+            self.add_trade_edge(node, origin_index, forward_factor, price_update);
+            self.add_trade_edge(origin_index, node, backward_factor, price_update);
+        }
+    }
 
-        for (_, &exchange_node) in same_exchange {
-            self.graph.add_edge(node, exchange_node, 1.0);
-            self.graph.add_edge(exchange_node, node, 1.0);
+    /// Links the node both ways to the nodes of the same currency on the other exchanges
+    fn link_transfers(&mut self, node: usize, exchange: ExchangeId, currency: CurrencyId) {
+        let same_currency = self.currency_nodes.get(&currency).into_iter().flatten();
+
+        for &exchange_node in same_currency.filter(|&&currency_node| currency_node != node) {
+            let other_exchange = match self.index_map.get_index(&exchange_node) {
+                Some(&(other_exchange, _)) => other_exchange,
+                None => continue,
+            };
+            let transfer_costs = &self.transfer_costs;
+
+            self.graph.add_edge(
+                node,
+                exchange_node,
+                transfer_costs
+                    .get(exchange, other_exchange, currency)
+                    .rate(),
+            );
+            self.graph.add_edge(
+                exchange_node,
+                node,
+                transfer_costs
+                    .get(other_exchange, exchange, currency)
+                    .rate(),
+            );
         }
+    }
 
-        match origin {
-            Some(origin_index) => {
-                let forward_factor = if is_forward {
-                    price_update.forward_factor
-                } else {
-                    price_update.backward_factor
-                };
-                let backward_factor = if is_forward {
-                    price_update.backward_factor
-                } else {
-                    price_update.forward_factor
-                };
+    /// Adds the edge of a trade weighted by the quoted factor after the trading fee
+    fn add_trade_edge(
+        &mut self,
+        from_node: usize,
+        to_node: usize,
+        factor: f64,
+        price_update: &PriceUpdate,
+    ) {
+        let fee_rate = self.trading_fees.rate(
+            price_update.exchange,
+            price_update.source_currency,
+            price_update.destination_currency,
+        );
+
+        self.graph.add_edge(from_node, to_node, factor * fee_rate);
+        self.quotes.insert(
+            (from_node, to_node),
+            Quote {
+                factor,
+                timestamp: price_update.timestamp,
+            },
+        );
+    }
+
+    /// Sets the trading fee of the exchange, or only of the pair when given,
+    /// and re-weights the trade edges from their quotes.
+    /// Returns the re-weighted edges with their previous weights
+    pub fn set_trading_fee(
+        &mut self,
+        exchange: ExchangeId,
+        pair: Option<(CurrencyId, CurrencyId)>,
+        percent_fee: f64,
+    ) -> Vec<(usize, usize, f64)> {
+        match pair {
+            Some((currency, other_currency)) => {
+                self.trading_fees
+                    .set_pair_fee(exchange, currency, other_currency, percent_fee)
+            }
+            None => self.trading_fees.set_exchange_fee(exchange, percent_fee),
+        }
+
+        let mut changed_edges = Vec::new();
+        for (&(from_node, to_node), quote) in &self.quotes {
+            let (&(from_exchange, from_currency), &(_, to_currency)) = match (
+                self.index_map.get_index(&from_node),
+                self.index_map.get_index(&to_node),
+            ) {
+                (Some(from_key), Some(to_key)) => (from_key, to_key),
+                _ => continue,
+            };
+
+            let is_pair = pair.is_none_or(|(currency, other_currency)| {
+                (from_currency == currency && to_currency == other_currency)
+                    || (from_currency == other_currency && to_currency == currency)
+            });
+            if from_exchange != exchange || !is_pair {
+                continue;
+            }
 
-                // Node -> origin is forward if `is_forward` is `true`
-                self.graph.add_edge(node, origin_index, forward_factor);
-                self.graph.add_edge(origin_index, node, backward_factor);
+            let weight =
+                quote.factor * self.trading_fees.rate(exchange, from_currency, to_currency);
+            match self.graph.add_edge(from_node, to_node, weight) {
+                Some(previous_weight) if previous_weight != weight => {
+                    changed_edges.push((from_node, to_node, previous_weight))
+                }
+                _ => {}
             }
-            None => {}
         }
+        // the quotes are not ordered
+        changed_edges.sort_by_key(|&(from_node, to_node, _)| (from_node, to_node));
+
+        changed_edges
     }
 
-    pub fn get_edges(&self) -> AllEdges<usize, f64, Directed> {
+    /// Sets the cost of the transfers and re-weights the transfer edge,
+    /// if there is one, returning it with its previous weight
+    pub fn set_transfer_cost(
+        &mut self,
+        from_exchange: ExchangeId,
+        to_exchange: ExchangeId,
+        currency: CurrencyId,
+        cost: TransferCost,
+    ) -> Option<(usize, usize, f64)> {
+        self.transfer_costs
+            .set(from_exchange, to_exchange, currency, cost);
+
+        let from_node = *self.index_map.get(&(from_exchange, currency))?;
+        let to_node = *self.index_map.get(&(to_exchange, currency))?;
+        if from_node == to_node {
+            return None;
+        }
+
+        let previous_weight = self.graph.add_edge(from_node, to_node, cost.rate())?;
+
+        Some((from_node, to_node, previous_weight))
+    }
+
+    /// The cost of the edge with the `weight`, the transfers take the fixed fees
+    /// of the transfer and the withdrawal from the exchange
+    pub fn edge_cost(&self, from_node: usize, to_node: usize, weight: f64) -> EdgeCost {
+        let fixed = match (
+            self.index_map.get_index(&from_node),
+            self.index_map.get_index(&to_node),
+        ) {
+            (Some(&(from_exchange, currency)), Some(&(to_exchange, _)))
+                if from_exchange != to_exchange =>
+            {
+                self.transfer_costs
+                    .get(from_exchange, to_exchange, currency)
+                    .fixed_fee
+                    + self.withdrawal_fees.get(from_exchange, currency)
+            }
+            _ => 0.0,
+        };
+
+        EdgeCost {
+            factor: weight,
+            fixed,
+        }
+    }
+
+    pub fn edge_limit(&self, from_node: usize, to_node: usize) -> Option<f64> {
+        let from_key = *self.index_map.get_index(&from_node)?;
+        let to_key = *self.index_map.get_index(&to_node)?;
+
+        self.edge_limits.get(&(from_key, to_key)).cloned()
+    }
+
+    /// Whether the edge is a transfer of a currency between two exchanges
+    pub fn is_transfer(&self, from_node: usize, to_node: usize) -> bool {
+        match (
+            self.index_map.get_index(&from_node),
+            self.index_map.get_index(&to_node),
+        ) {
+            (Some((from_exchange, _)), Some((to_exchange, _))) => from_exchange != to_exchange,
+            _ => false,
+        }
+    }
+
+    /// Whether the exchange quotes the pair, in either direction
+    pub fn has_pair(
+        &self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+    ) -> bool {
+        match (
+            self.index_map.get(&(exchange, currency)),
+            self.index_map.get(&(exchange, other_currency)),
+        ) {
+            (Some(&node), Some(&other_node)) => {
+                self.graph.contains_edge(node, other_node)
+                    || self.graph.contains_edge(other_node, node)
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the trade edges of the pair and the nodes left without any trade edges,
+    /// along with their transfer edges. Returns the removed nodes,
+    /// `None` if the exchange doesn't have the pair
+    pub fn remove_pair(
+        &mut self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+    ) -> Option<Vec<usize>> {
+        let node = *self.index_map.get(&(exchange, currency))?;
+        let other_node = *self.index_map.get(&(exchange, other_currency))?;
+
+        let forward = self.graph.remove_edge(node, other_node);
+        let backward = self.graph.remove_edge(other_node, node);
+        if forward.is_none() && backward.is_none() {
+            return None;
+        }
+        self.quotes.remove(&(node, other_node));
+        self.quotes.remove(&(other_node, node));
+
+        let orphaned_nodes: Vec<usize> = [node, other_node]
+            .iter()
+            .cloned()
+            .filter(|&node| {
+                !self
+                    .graph
+                    .neighbors(node)
+                    .any(|neighbor| !self.is_transfer(node, neighbor))
+            })
+            .collect();
+
+        for &node in &orphaned_nodes {
+            self.remove_node(node);
+        }
+
+        Some(orphaned_nodes)
+    }
+
+    /// Removes all the nodes of the exchange, returns them
+    pub fn remove_exchange(&mut self, exchange: ExchangeId) -> Vec<usize> {
+        let exchange_nodes = self.exchange_nodes(exchange);
+
+        for &node in &exchange_nodes {
+            self.remove_node(node);
+        }
+
+        exchange_nodes
+    }
+
+    /// Removes the node with all of its edges and quotes, its index is free for a new node
+    fn remove_node(&mut self, node: usize) {
+        // `DiGraphMap::remove_node` keeps the weights of the incoming edges around
+        let incoming: Vec<usize> = self
+            .graph
+            .neighbors_directed(node, Direction::Incoming)
+            .collect();
+        for from_node in incoming {
+            self.graph.remove_edge(from_node, node);
+        }
+        self.graph.remove_node(node);
+        self.quotes
+            .retain(|&(from_node, to_node), _| from_node != node && to_node != node);
+
+        if let Some(&key) = self.index_map.get_index(&node) {
+            self.index_map.remove(&key);
+
+            let (exchange, currency) = key;
+            Self::remove_indexed(&mut self.currency_nodes, currency, node);
+            Self::remove_indexed(&mut self.exchange_nodes, exchange, node);
+        }
+    }
+
+    fn remove_indexed<K: Eq + Hash>(index: &mut HashMap<K, BTreeSet<usize>>, key: K, node: usize) {
+        let is_empty = index.get_mut(&key).is_some_and(|nodes| {
+            nodes.remove(&node);
+            nodes.is_empty()
+        });
+
+        if is_empty {
+            index.remove(&key);
+        }
+    }
+
+    pub fn get_edges(&self) -> AllEdges<'_, usize, f64, Directed> {
         self.graph.all_edges()
     }
 }
@@ -216,12 +627,9 @@ mod test {
             let updated_factors = PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 5000.0, 3.0);
 
             exchange_graph.add(&updated_factors);
-            let source_key = (
-                updated_factors.exchange.clone(),
-                updated_factors.source_currency,
-            );
+            let source_key = (updated_factors.exchange, updated_factors.source_currency);
             let dest_key = (
-                updated_factors.exchange.clone(),
+                updated_factors.exchange,
                 updated_factors.destination_currency,
             );
 
@@ -304,6 +712,9 @@ mod test {
                     .graph
                     .edge_weight(*node_2.get_value(), *node_0.get_value())
             );
+
+            assert!(exchange_graph.is_transfer(*node_0.get_value(), *node_2.get_value()));
+            assert!(!exchange_graph.is_transfer(*node_0.get_value(), *node_1.get_value()));
         }
 
         #[test]
@@ -401,6 +812,283 @@ mod test {
                     .edge_weight(*node_3.get_value(), *node_2.get_value())
             );
         }
+
+        #[test]
+        fn removing_pairs_and_exchanges_drops_the_orphaned_nodes() {
+            let mut exchange_graph = ExchangeCompleteGraph::default();
+
+            exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ));
+            exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "KRAKEN",
+                "USD",
+                "ETH",
+                0.004,
+                240.0,
+            ));
+            exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "GDAX",
+                "BTC",
+                "USD",
+                1001.0,
+                0.0009,
+            ));
+
+            // KRAKEN USD still has the ETH pair
+            assert_eq!(
+                Some(vec![0]),
+                exchange_graph.remove_pair("KRAKEN".into(), "USD".into(), "BTC".into())
+            );
+            assert_eq!(
+                None,
+                exchange_graph.remove_pair("KRAKEN".into(), "BTC".into(), "USD".into())
+            );
+            assert!(!exchange_graph.index_map.contains_index(&0));
+            assert!(!exchange_graph.graph.contains_edge(1, 0));
+            assert!(!exchange_graph.graph.contains_edge(3, 0));
+            assert!(exchange_graph.graph.contains_edge(1, 4));
+            assert!(!exchange_graph.quotes.contains_key(&(0, 1)));
+
+            assert_eq!(vec![3, 4], exchange_graph.remove_exchange("GDAX".into()));
+            assert_eq!(2, exchange_graph.index_map.len());
+            assert_eq!(2, exchange_graph.graph.edge_count());
+            assert_eq!(2, exchange_graph.quotes.len());
+
+            // the nodes of the exchange get the free indices
+            let (btc_node, usd_node) = exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "GDAX",
+                "BTC",
+                "USD",
+                1001.0,
+                0.0009,
+            ));
+            assert_eq!(
+                (Index::Inserted(0), Index::Inserted(3)),
+                (btc_node, usd_node)
+            );
+            assert!(exchange_graph.graph.contains_edge(3, 1));
+            assert_eq!(5, exchange_graph.index_map.index_bound());
+            assert_eq!(vec![0, 3], exchange_graph.exchange_nodes("GDAX".into()));
+            assert_eq!(vec![0], exchange_graph.currency_nodes("BTC".into()));
+        }
+
+        #[test]
+        fn it_indexes_the_nodes_by_currency_and_exchange() {
+            let mut exchange_graph = ExchangeCompleteGraph::default();
+
+            for (exchange, factor) in &[("KRAKEN", 1000.0), ("GDAX", 1001.0), ("BITSTAMP", 999.0)] {
+                exchange_graph.add(&PriceUpdate::new(
+                    Utc::now(),
+                    exchange,
+                    "BTC",
+                    "USD",
+                    *factor,
+                    0.0009,
+                ));
+            }
+            exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "GDAX",
+                "ETH",
+                "BTC",
+                0.05,
+                19.0,
+            ));
+
+            assert_eq!(vec![0, 2, 4], exchange_graph.currency_nodes("BTC".into()));
+            assert_eq!(vec![1, 3, 5], exchange_graph.currency_nodes("USD".into()));
+            assert_eq!(vec![2, 3, 6], exchange_graph.exchange_nodes("GDAX".into()));
+            assert!(exchange_graph.currency_nodes("EUR".into()).is_empty());
+            assert!(exchange_graph.exchange_nodes("BINANCE".into()).is_empty());
+
+            // the same currencies on the other exchanges are linked both ways
+            for &(from_node, to_node) in &[(0, 2), (2, 4), (4, 0), (1, 5), (3, 1)] {
+                assert!(exchange_graph.graph.contains_edge(from_node, to_node));
+                assert!(exchange_graph.graph.contains_edge(to_node, from_node));
+            }
+            // ETH is only on GDAX
+            assert_eq!(
+                vec![2],
+                exchange_graph.graph.neighbors(6).collect::<Vec<_>>()
+            );
+
+            exchange_graph.remove_exchange("KRAKEN".into());
+            assert_eq!(vec![2, 4], exchange_graph.currency_nodes("BTC".into()));
+            assert!(exchange_graph.exchange_nodes("KRAKEN".into()).is_empty());
+        }
+    }
+
+    mod transfer_costs {
+        use chrono::prelude::*;
+
+        use super::*;
+
+        #[test]
+        fn transfer_edges_are_weighted_by_the_transfer_costs() {
+            let mut exchange_graph = ExchangeCompleteGraph::default();
+            let cost = |percent_fee| TransferCost {
+                percent_fee,
+                fixed_fee: 0.0,
+            };
+
+            // set before the nodes exist
+            assert_eq!(
+                None,
+                exchange_graph.set_transfer_cost(
+                    "KRAKEN".into(),
+                    "EXCI".into(),
+                    "BTC".into(),
+                    cost(1.0)
+                )
+            );
+
+            let (kraken_btc, _) = exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ));
+            let (exci_btc, _) = exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "EXCI",
+                "BTC",
+                "EUR",
+                5000.0,
+                2.0,
+            ));
+            let (kraken_btc, exci_btc) = (*kraken_btc.get_value(), *exci_btc.get_value());
+
+            assert_eq!(
+                Some(&0.99),
+                exchange_graph.graph.edge_weight(kraken_btc, exci_btc)
+            );
+            assert_eq!(
+                Some(&1.0),
+                exchange_graph.graph.edge_weight(exci_btc, kraken_btc)
+            );
+
+            // and re-weighted when changed
+            assert_eq!(
+                Some((exci_btc, kraken_btc, 1.0)),
+                exchange_graph.set_transfer_cost(
+                    "EXCI".into(),
+                    "KRAKEN".into(),
+                    "BTC".into(),
+                    cost(2.0)
+                )
+            );
+            assert_eq!(
+                Some(&0.98),
+                exchange_graph.graph.edge_weight(exci_btc, kraken_btc)
+            );
+            assert_eq!(
+                None,
+                exchange_graph.set_transfer_cost(
+                    "EXCI".into(),
+                    "KRAKEN".into(),
+                    "USD".into(),
+                    cost(2.0)
+                )
+            );
+        }
+    }
+
+    mod withdrawal_fees {
+        use chrono::prelude::*;
+
+        use super::*;
+
+        #[test]
+        fn only_transfer_edges_have_fixed_fees() {
+            let mut exchange_graph = ExchangeCompleteGraph::default();
+            exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ));
+            exchange_graph.add(&PriceUpdate::new(
+                Utc::now(),
+                "GDAX",
+                "BTC",
+                "USD",
+                1001.0,
+                0.0008,
+            ));
+            exchange_graph
+                .withdrawal_fees
+                .set("KRAKEN".into(), "BTC".into(), 0.0005);
+            exchange_graph.set_transfer_cost(
+                "KRAKEN".into(),
+                "GDAX".into(),
+                "BTC".into(),
+                TransferCost {
+                    percent_fee: 0.0,
+                    fixed_fee: 0.0001,
+                },
+            );
+
+            let fixed =
+                |from_node, to_node| exchange_graph.edge_cost(from_node, to_node, 1.0).fixed;
+
+            // KRAKEN BTC -> GDAX BTC
+            assert!((fixed(0, 2) - 0.0006).abs() < 1e-12);
+            assert_eq!(0.0, fixed(2, 0));
+            assert_eq!(0.0, fixed(0, 1));
+        }
+    }
+
+    mod trading_fees {
+        use chrono::prelude::*;
+
+        use super::*;
+
+        #[test]
+        fn trade_edges_are_weighted_after_the_fees_and_keep_the_quotes() {
+            let mut exchange_graph = ExchangeCompleteGraph::default();
+            exchange_graph.set_trading_fee("KRAKEN".into(), None, 0.5);
+
+            let timestamp = Utc.ymd(2017, 11, 1).and_hms(9, 42, 23);
+            exchange_graph.add(&PriceUpdate::new(
+                timestamp, "KRAKEN", "BTC", "USD", 1000.0, 0.0009,
+            ));
+            exchange_graph.add(&PriceUpdate::new(
+                timestamp, "KRAKEN", "BTC", "EUR", 900.0, 0.001,
+            ));
+
+            assert_eq!(Some(&995.0), exchange_graph.graph.edge_weight(0, 1));
+            assert_eq!(
+                Some(&Quote {
+                    factor: 1000.0,
+                    timestamp,
+                }),
+                exchange_graph.quotes.get(&(0, 1))
+            );
+
+            // only the pair edges are re-weighted
+            let changed_edges = exchange_graph.set_trading_fee(
+                "KRAKEN".into(),
+                Some(("USD".into(), "BTC".into())),
+                0.0,
+            );
+
+            assert_eq!(vec![(0, 1, 995.0), (1, 0, 0.0009 * 0.995)], changed_edges);
+            assert_eq!(Some(&1000.0), exchange_graph.graph.edge_weight(0, 1));
+            assert_eq!(Some(&895.5), exchange_graph.graph.edge_weight(0, 2));
+        }
     }
 
     mod index_map {
@@ -413,9 +1101,9 @@ mod test {
             let key_1 = "Key 1".to_string();
 
             assert_eq!(None, index_hash_map.get(&key_1));
-            assert_eq!(false, index_hash_map.contains(&key_1));
+            assert!(!index_hash_map.contains(&key_1));
             assert_eq!(None, index_hash_map.get_index(&0_usize));
-            assert_eq!(false, index_hash_map.contains_index(&0_usize));
+            assert!(!index_hash_map.contains_index(&0_usize));
 
             assert_eq!(Index::Inserted(0_usize), index_hash_map.entry(key_1));
             assert_eq!(1_usize, index_hash_map.len());
@@ -428,9 +1116,9 @@ mod test {
             let key_1_check = "Key 1".to_string();
             // check for existence of keys
             assert_eq!(Some(&0_usize), index_hash_map.get(&key_1_check));
-            assert_eq!(true, index_hash_map.contains(&key_1_check));
+            assert!(index_hash_map.contains(&key_1_check));
             assert_eq!(Some(&key_1_check), index_hash_map.get_index(&0_usize));
-            assert_eq!(true, index_hash_map.contains_index(&0_usize));
+            assert!(index_hash_map.contains_index(&0_usize));
             assert_eq!(Index::Fetched(0_usize), index_hash_map.entry(key_1_check));
             assert_eq!(2_usize, index_hash_map.len());
 
@@ -439,14 +1127,47 @@ mod test {
             let key_2_index_check = 1_usize;
 
             assert_eq!(Some(&key_2_index_check), index_hash_map.get(&key_2_check));
-            assert_eq!(true, index_hash_map.contains(&key_2_check));
+            assert!(index_hash_map.contains(&key_2_check));
             assert_eq!(
                 Some(&key_2_check),
                 index_hash_map.get_index(&key_2_index_check)
             );
-            assert_eq!(true, index_hash_map.contains_index(&key_2_index_check));
+            assert!(index_hash_map.contains_index(&key_2_index_check));
             assert_eq!(Index::Fetched(1_usize), index_hash_map.entry(key_2_check));
             assert_eq!(2_usize, index_hash_map.len());
         }
+
+        #[test]
+        fn recycles_the_indices_of_the_removed_keys() {
+            let mut index_hash_map: IndexHashMap<String> = IndexHashMap::new();
+
+            for key in &["Key 1", "Key 2", "Key 3"] {
+                index_hash_map.entry(key.to_string());
+            }
+
+            assert_eq!(Some(2), index_hash_map.remove(&"Key 3".to_string()));
+            assert_eq!(Some(0), index_hash_map.remove(&"Key 1".to_string()));
+            assert_eq!(None, index_hash_map.remove(&"Key 1".to_string()));
+            assert_eq!(1, index_hash_map.len());
+            assert_eq!(3, index_hash_map.index_bound());
+            assert_eq!(None, index_hash_map.get_index(&0));
+            assert!(!index_hash_map.contains_index(&2));
+
+            // the smallest free index first
+            assert_eq!(
+                Index::Inserted(0),
+                index_hash_map.entry("Key 4".to_string())
+            );
+            assert_eq!(
+                Index::Inserted(2),
+                index_hash_map.entry("Key 5".to_string())
+            );
+            assert_eq!(
+                Index::Inserted(3),
+                index_hash_map.entry("Key 6".to_string())
+            );
+            assert_eq!(Some(&"Key 5".to_string()), index_hash_map.get_index(&2));
+            assert_eq!(4, index_hash_map.index_bound());
+        }
     }
 }
@@ -0,0 +1,226 @@
+use std::collections::hash_map::Iter;
+use std::collections::HashMap;
+
+use crate::symbol::{CurrencyId, ExchangeId};
+
+/// The cost of exchanging over an edge, `amount` becomes `amount * factor - fixed`.
+/// The fixed part can't be a weight of the graph, so only the searches for an amount use it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeCost {
+    pub factor: f64,
+    pub fixed: f64,
+}
+
+impl EdgeCost {
+    /// `None` when the fixed part takes the whole amount
+    pub fn output(&self, amount: f64) -> Option<f64> {
+        let output = amount * self.factor - self.fixed;
+
+        if output > 0.0 {
+            Some(output)
+        } else {
+            None
+        }
+    }
+}
+
+/// The cost of moving a currency from one exchange to another
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransferCost {
+    /// Percentage of the transferred amount, `0.1` is 0.1%
+    pub percent_fee: f64,
+    /// Amount of the currency taken on every transfer
+    pub fixed_fee: f64,
+}
+
+impl TransferCost {
+    /// The weight of the transfer edge, the fixed fee depends on the amount so it's not part of it
+    pub fn rate(&self) -> f64 {
+        1.0 - self.percent_fee / 100.0
+    }
+}
+
+/// The transfer costs by (from exchange, to exchange, currency), transfers without a cost are free
+#[derive(Debug, Clone, Default)]
+pub struct TransferCosts {
+    costs: HashMap<(ExchangeId, ExchangeId, CurrencyId), TransferCost>,
+}
+
+impl TransferCosts {
+    pub fn get(
+        &self,
+        from_exchange: ExchangeId,
+        to_exchange: ExchangeId,
+        currency: CurrencyId,
+    ) -> TransferCost {
+        self.costs
+            .get(&(from_exchange, to_exchange, currency))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set(
+        &mut self,
+        from_exchange: ExchangeId,
+        to_exchange: ExchangeId,
+        currency: CurrencyId,
+        cost: TransferCost,
+    ) {
+        self.costs
+            .insert((from_exchange, to_exchange, currency), cost);
+    }
+
+    pub fn iter(&self) -> Iter<'_, (ExchangeId, ExchangeId, CurrencyId), TransferCost> {
+        self.costs.iter()
+    }
+}
+
+/// The fixed amount of a currency taken for withdrawing it from an exchange,
+/// currencies without a fee are withdrawn for free
+#[derive(Debug, Clone, Default)]
+pub struct WithdrawalFees {
+    fees: HashMap<(ExchangeId, CurrencyId), f64>,
+}
+
+impl WithdrawalFees {
+    pub fn get(&self, exchange: ExchangeId, currency: CurrencyId) -> f64 {
+        self.fees.get(&(exchange, currency)).cloned().unwrap_or(0.0)
+    }
+
+    pub fn set(&mut self, exchange: ExchangeId, currency: CurrencyId, fee: f64) {
+        self.fees.insert((exchange, currency), fee);
+    }
+
+    pub fn iter(&self) -> Iter<'_, (ExchangeId, CurrencyId), f64> {
+        self.fees.iter()
+    }
+}
+
+/// The taker fees in percent of each exchange, optionally overridden for a pair of currencies.
+/// Exchanges without a fee are free
+#[derive(Debug, Clone, Default)]
+pub struct TradingFees {
+    exchange_fees: HashMap<ExchangeId, f64>,
+    /// Both directions of a pair have the same fee, it's kept under one of them
+    pair_fees: HashMap<(ExchangeId, CurrencyId, CurrencyId), f64>,
+}
+
+impl TradingFees {
+    pub fn percent_fee(
+        &self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+    ) -> f64 {
+        self.pair_fees
+            .get(&(exchange, currency, other_currency))
+            .or_else(|| self.pair_fees.get(&(exchange, other_currency, currency)))
+            .or_else(|| self.exchange_fees.get(&exchange))
+            .cloned()
+            .unwrap_or(0.0)
+    }
+
+    /// The part of the traded amount left after the fee
+    pub fn rate(
+        &self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+    ) -> f64 {
+        1.0 - self.percent_fee(exchange, currency, other_currency) / 100.0
+    }
+
+    pub fn set_exchange_fee(&mut self, exchange: ExchangeId, percent_fee: f64) {
+        self.exchange_fees.insert(exchange, percent_fee);
+    }
+
+    pub fn set_pair_fee(
+        &mut self,
+        exchange: ExchangeId,
+        currency: CurrencyId,
+        other_currency: CurrencyId,
+        percent_fee: f64,
+    ) {
+        self.pair_fees.remove(&(exchange, other_currency, currency));
+        self.pair_fees
+            .insert((exchange, currency, other_currency), percent_fee);
+    }
+
+    pub fn exchange_fees(&self) -> Iter<'_, ExchangeId, f64> {
+        self.exchange_fees.iter()
+    }
+
+    pub fn pair_fees(&self) -> Iter<'_, (ExchangeId, CurrencyId, CurrencyId), f64> {
+        self.pair_fees.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edge_cost_takes_the_fixed_part_after_the_factor() {
+        let edge_cost = EdgeCost {
+            factor: 0.5,
+            fixed: 1.0,
+        };
+
+        assert_eq!(Some(4.0), edge_cost.output(10.0));
+        assert_eq!(None, edge_cost.output(2.0));
+        assert_eq!(None, edge_cost.output(1.0));
+    }
+
+    #[test]
+    fn pair_fees_override_the_exchange_fee() {
+        let (kraken, gdax) = (ExchangeId::new("KRAKEN"), ExchangeId::new("GDAX"));
+        let (btc, usd, eur) = (
+            CurrencyId::new("BTC"),
+            CurrencyId::new("USD"),
+            CurrencyId::new("EUR"),
+        );
+        let mut trading_fees = TradingFees::default();
+
+        assert_eq!(0.0, trading_fees.percent_fee(kraken, btc, usd));
+
+        trading_fees.set_exchange_fee(kraken, 0.26);
+        trading_fees.set_pair_fee(kraken, usd, btc, 0.1);
+
+        assert_eq!(0.1, trading_fees.percent_fee(kraken, btc, usd));
+        assert_eq!(0.1, trading_fees.percent_fee(kraken, usd, btc));
+        assert_eq!(0.26, trading_fees.percent_fee(kraken, btc, eur));
+        assert_eq!(0.0, trading_fees.percent_fee(gdax, btc, usd));
+        assert_eq!(0.999, trading_fees.rate(kraken, btc, usd));
+
+        // setting the pair the other way around replaces its fee
+        trading_fees.set_pair_fee(kraken, btc, usd, 0.2);
+        assert_eq!(0.2, trading_fees.percent_fee(kraken, usd, btc));
+        assert_eq!(1, trading_fees.pair_fees().len());
+    }
+
+    #[test]
+    fn transfers_are_free_unless_configured() {
+        let (kraken, gdax, btc) = (
+            ExchangeId::new("KRAKEN"),
+            ExchangeId::new("GDAX"),
+            CurrencyId::new("BTC"),
+        );
+        let mut transfer_costs = TransferCosts::default();
+
+        assert_eq!(1.0, transfer_costs.get(kraken, gdax, btc).rate());
+
+        let cost = TransferCost {
+            percent_fee: 0.5,
+            fixed_fee: 0.0005,
+        };
+        transfer_costs.set(kraken, gdax, btc, cost);
+
+        assert_eq!(cost, transfer_costs.get(kraken, gdax, btc));
+        assert_eq!(0.995, transfer_costs.get(kraken, gdax, btc).rate());
+        // the costs depend on the direction of the transfer
+        assert_eq!(
+            TransferCost::default(),
+            transfer_costs.get(gdax, kraken, btc)
+        );
+    }
+}
@@ -1,41 +1,1352 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
 
+use chrono::{DateTime, Utc};
+use petgraph::algo::kosaraju_scc;
 use petgraph::graphmap::DiGraphMap;
+use petgraph::Direction;
 
-use crate::command::PriceUpdate;
-use crate::graph::collection::ExchangeCompleteGraph;
-use crate::graph::collection::Index;
-use crate::graph::collection::IndexHashMap;
+use crate::command::{
+    ArbitrageRequest, EdgeLimitUpdate, ExchangeRequest, MaxAgeUpdate, OrderBookUpdate, PairKey,
+    PriceUpdate, RemoveExchangeUpdate, RemovePairUpdate, TradingFeeUpdate, TransferCostUpdate,
+    ValidationPolicyUpdate, WithdrawalFeeUpdate,
+};
+use crate::display::dot;
+use crate::display::output::{
+    Amounts, BestRates, GraphStats, ProfitableCycle, SplitRoute, StaleEdge, StaleEdges,
+};
+use crate::graph::book::{self, OrderBooks};
+use crate::graph::collection::{ExchangeCompleteGraph, Index, Quote};
+use crate::graph::fees::TransferCost;
+use crate::graph::history::PriceHistory;
+use crate::graph::snapshot::{Snapshot, SnapshotError};
+use crate::graph::staleness::{Clock, FixedClock, MaxAges, SystemClock};
+use crate::graph::trade::{Arbitrage, Exchanger, PathLimits, RatesTable, SearchEdge};
+use crate::graph::wal::{WalError, WalErrorKind, WriteAheadLog};
+use crate::parse::parsing::ParseCommandError;
+use crate::parse::validation::{Validation, ValidationPolicy};
+use crate::symbol::{CurrencyId, ExchangeId};
+use crate::{parse_line, ParsedLine};
+
+/// A split route first looks for the path taking this fraction of the amount
+const SPLIT_CHUNKS: usize = 50;
+
+/// Tolerance for the rounding errors of summing the parts sent over a limited edge
+const LIMIT_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateRequestErrorKind {
+    UnknownSource,
+    UnknownDestination,
+    NoPath,
+    NoPathWithinLimits,
+    /// The order books along the paths are not deep enough for the amount
+    NoPathForAmount,
+    /// An amount is exchanged only over the best path, not over several of them
+    PathsCountForAmount,
+    /// A profitable cycle of (exchange, currency) is reachable from the source,
+    /// the first and the last entries are the same
+    Arbitrage(Vec<(String, String)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateRequestError(pub RateRequestErrorKind);
+
+impl fmt::Display for RateRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let error_description = match &self.0 {
+            RateRequestErrorKind::UnknownSource => "Unknown source exchange and currency",
+            RateRequestErrorKind::UnknownDestination => "Unknown destination exchange and currency",
+            RateRequestErrorKind::NoPath => "No path between source and destination",
+            RateRequestErrorKind::NoPathWithinLimits => {
+                "No path between source and destination within the limits"
+            }
+            RateRequestErrorKind::PathsCountForAmount => {
+                "Only the best path can be requested for an amount"
+            }
+            RateRequestErrorKind::NoPathForAmount => {
+                "No path between source and destination deep enough for the amount"
+            }
+            RateRequestErrorKind::Arbitrage(cycle) => {
+                let cycle = cycle
+                    .iter()
+                    .map(|(exchange, currency)| format!("{} {}", exchange, currency))
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+
+                return write!(f, "Arbitrage cycle {}", cycle);
+            }
+        };
+
+        error_description.fmt(f)
+    }
+}
+
+/// What happened to a price update
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateOutcome {
+    Inserted,
+    Replaced {
+        previous: PriceUpdate,
+    },
+    /// There is a newer update of the pair
+    IgnoredOlder,
+    /// There is an update of the pair with the same timestamp
+    IgnoredDuplicate,
+    Rejected(ParseCommandError),
+}
+
+/// The outcome of a price update with the nodes and edges of the graph it changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateReport {
+    pub outcome: UpdateOutcome,
+    /// How the validation policy accepted or rejected the update
+    pub validation: Validation,
+    /// The nodes added for the update
+    pub changed_nodes: Vec<usize>,
+    /// The edges added or re-weighted for the update, the transfer edges of the new nodes as well
+    pub changed_edges: Vec<(usize, usize)>,
+}
 
 #[derive(Debug)]
 pub struct GraphHandler {
     pub exchange_graph: ExchangeCompleteGraph,
-    pub price_updates: HashSet<PriceUpdate>,
+    /// The latest update of each pair, normalized to the orientation of its key
+    pub price_updates: HashMap<PairKey, PriceUpdate>,
+    pub order_books: OrderBooks,
+    pub rates_table: RatesTable,
+    /// Every accepted price update, for solving against the graph at an earlier time
+    pub price_history: PriceHistory,
+    /// The quotes older than the max age of their exchange are left out of the searches
+    pub max_ages: MaxAges,
+    pub clock: Box<dyn Clock>,
+    pub validation_policy: ValidationPolicy,
+    /// Every accepted update and admin command is appended to the log, when there is one
+    pub log: Option<WriteAheadLog>,
+    /// The sequence of the last log record in the state
+    pub log_sequence: u64,
 }
 
 impl Default for GraphHandler {
     fn default() -> Self {
         Self {
             exchange_graph: ExchangeCompleteGraph::default(),
-            price_updates: HashSet::default(),
+            price_updates: HashMap::new(),
+            order_books: OrderBooks::default(),
+            rates_table: RatesTable::default(),
+            price_history: PriceHistory::default(),
+            max_ages: MaxAges::default(),
+            clock: Box::new(SystemClock),
+            validation_policy: ValidationPolicy::default(),
+            log: None,
+            log_sequence: 0,
+        }
+    }
+}
+
+impl GraphHandler {
+    /// A handler which solves the best rates using multiple threads
+    pub fn with_threads(threads: usize) -> Self {
+        Self {
+            rates_table: RatesTable::with_threads(threads),
+            ..Self::default()
+        }
+    }
+
+    /// Validates the update with the policy first, the rejected updates are not kept.
+    /// Only an update newer than the one of the pair changes the graph,
+    /// and only once it is in the log.
+    /// The quote replaces the order book of the pair, whose levels are older
+    pub fn handle_update(
+        &mut self,
+        mut price_update: PriceUpdate,
+    ) -> Result<UpdateReport, WalError> {
+        let (validation, outcome) = self.update_outcome(&mut price_update);
+
+        let (changed_nodes, changed_edges) = match outcome {
+            UpdateOutcome::Inserted | UpdateOutcome::Replaced { .. } => {
+                // with the factors as they were accepted
+                self.append_to_log(&price_update)?;
+                self.order_books.remove(
+                    price_update.exchange,
+                    price_update.source_currency,
+                    price_update.destination_currency,
+                );
+
+                self.apply_update(price_update)
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        Ok(UpdateReport {
+            outcome,
+            validation,
+            changed_nodes,
+            changed_edges,
+        })
+    }
+
+    /// Validates the top of the book as a price update, the book is only kept
+    /// and the pair updated with its top when the price update would be
+    pub fn handle_order_book(
+        &mut self,
+        order_book_update: OrderBookUpdate,
+    ) -> Result<UpdateReport, WalError> {
+        let mut price_update = order_book_update.price_update();
+        let (validation, outcome) = self.update_outcome(&mut price_update);
+
+        let (changed_nodes, changed_edges) = match outcome {
+            UpdateOutcome::Inserted | UpdateOutcome::Replaced { .. } => {
+                // replaying the book updates the pair again, so its price update is not logged
+                self.append_to_log(&order_book_update)?;
+                self.order_books.insert(order_book_update);
+
+                self.apply_update(price_update)
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        Ok(UpdateReport {
+            outcome,
+            validation,
+            changed_nodes,
+            changed_edges,
+        })
+    }
+
+    /// What the policy and the current update of the pair make of the price update
+    fn update_outcome(&self, price_update: &mut PriceUpdate) -> (Validation, UpdateOutcome) {
+        let validation = self.validation_policy.apply(price_update);
+
+        let pair_key = price_update.pair_key();
+        let outcome = match (validation, self.price_updates.get(&pair_key)) {
+            (Validation::Rejected(error), _) => UpdateOutcome::Rejected(error),
+            (_, Some(current)) if current.timestamp == price_update.timestamp => {
+                UpdateOutcome::IgnoredDuplicate
+            }
+            (_, Some(current)) if current.timestamp > price_update.timestamp => {
+                UpdateOutcome::IgnoredOlder
+            }
+            (_, Some(current)) => UpdateOutcome::Replaced {
+                previous: current.clone(),
+            },
+            (_, None) => UpdateOutcome::Inserted,
+        };
+
+        (validation, outcome)
+    }
+
+    /// Adds the accepted update to the graph, the history and the updates of the pairs
+    fn apply_update(&mut self, price_update: PriceUpdate) -> (Vec<usize>, Vec<(usize, usize)>) {
+        let changes = self.add_to_graph(&price_update);
+        self.price_history.insert(&price_update);
+        self.price_updates
+            .insert(price_update.pair_key(), price_update.normalized());
+
+        changes
+    }
+
+    /// The withdrawal fees are fixed amounts, so they don't change the weights of the graph
+    /// and only the requests for an amount take them into account
+    pub fn handle_withdrawal_fee(
+        &mut self,
+        withdrawal_fee_update: &WithdrawalFeeUpdate,
+    ) -> Result<(), WalError> {
+        self.append_to_log(withdrawal_fee_update)?;
+        self.set_withdrawal_fee(withdrawal_fee_update);
+
+        Ok(())
+    }
+
+    fn set_withdrawal_fee(&mut self, withdrawal_fee_update: &WithdrawalFeeUpdate) {
+        self.exchange_graph.withdrawal_fees.set(
+            ExchangeId::new(&withdrawal_fee_update.exchange),
+            CurrencyId::new(&withdrawal_fee_update.currency),
+            withdrawal_fee_update.fee,
+        );
+    }
+
+    pub fn handle_validation_policy(
+        &mut self,
+        validation_policy_update: &ValidationPolicyUpdate,
+    ) -> Result<(), WalError> {
+        self.append_to_log(validation_policy_update)?;
+        self.validation_policy = validation_policy_update.policy;
+
+        Ok(())
+    }
+
+    /// The max ages only change which edges the searches go over, not the graph
+    pub fn handle_max_age(&mut self, max_age_update: &MaxAgeUpdate) -> Result<(), WalError> {
+        self.append_to_log(max_age_update)?;
+        self.set_max_age(max_age_update);
+
+        Ok(())
+    }
+
+    fn set_max_age(&mut self, max_age_update: &MaxAgeUpdate) {
+        let exchange = max_age_update.exchange.as_deref().map(ExchangeId::new);
+        self.max_ages.set(exchange, max_age_update.max_age);
+    }
+
+    /// The limits only bound the amounts sent over the edges, not their weights
+    pub fn handle_edge_limit(
+        &mut self,
+        edge_limit_update: &EdgeLimitUpdate,
+    ) -> Result<(), WalError> {
+        self.append_to_log(edge_limit_update)?;
+        self.set_edge_limit(edge_limit_update);
+
+        Ok(())
+    }
+
+    fn set_edge_limit(&mut self, edge_limit_update: &EdgeLimitUpdate) {
+        let from_key = (
+            ExchangeId::new(&edge_limit_update.from_exchange),
+            CurrencyId::new(&edge_limit_update.from_currency),
+        );
+        let to_key = (
+            ExchangeId::new(&edge_limit_update.to_exchange),
+            CurrencyId::new(&edge_limit_update.to_currency),
+        );
+
+        self.exchange_graph
+            .edge_limits
+            .insert((from_key, to_key), edge_limit_update.limit);
+    }
+
+    /// Changes the cost of the transfers and the best rates affected by the re-weighted edge
+    pub fn handle_transfer_cost(
+        &mut self,
+        transfer_cost_update: &TransferCostUpdate,
+    ) -> Result<(), WalError> {
+        self.append_to_log(transfer_cost_update)?;
+        self.set_transfer_cost(transfer_cost_update);
+
+        Ok(())
+    }
+
+    fn set_transfer_cost(&mut self, transfer_cost_update: &TransferCostUpdate) {
+        let cost = TransferCost {
+            percent_fee: transfer_cost_update.percent_fee,
+            fixed_fee: transfer_cost_update.fixed_fee,
+        };
+
+        let changed_edge = self.exchange_graph.set_transfer_cost(
+            ExchangeId::new(&transfer_cost_update.from_exchange),
+            ExchangeId::new(&transfer_cost_update.to_exchange),
+            CurrencyId::new(&transfer_cost_update.currency),
+            cost,
+        );
+
+        if let Some((from_node, to_node, previous_weight)) = changed_edge {
+            self.rates_table.update_edge(
+                &self.exchange_graph.graph,
+                from_node,
+                to_node,
+                Some(previous_weight),
+            );
+        }
+    }
+
+    /// Changes the trading fee and the best rates affected by the re-weighted edges
+    pub fn handle_trading_fee(
+        &mut self,
+        trading_fee_update: &TradingFeeUpdate,
+    ) -> Result<(), WalError> {
+        self.append_to_log(trading_fee_update)?;
+        self.set_trading_fee(trading_fee_update);
+
+        Ok(())
+    }
+
+    fn set_trading_fee(&mut self, trading_fee_update: &TradingFeeUpdate) {
+        let pair = trading_fee_update
+            .pair
+            .as_ref()
+            .map(|(currency, other_currency)| {
+                (CurrencyId::new(currency), CurrencyId::new(other_currency))
+            });
+
+        let changed_edges = self.exchange_graph.set_trading_fee(
+            ExchangeId::new(&trading_fee_update.exchange),
+            pair,
+            trading_fee_update.percent_fee,
+        );
+
+        for (from_node, to_node, previous_weight) in changed_edges {
+            self.rates_table.update_edge(
+                &self.exchange_graph.graph,
+                from_node,
+                to_node,
+                Some(previous_weight),
+            );
+        }
+    }
+
+    /// Delists the pair of the exchange, dropping the nodes left without any pairs.
+    /// The history keeps the removal, at the time of the update or now.
+    /// Returns the removed nodes, `None` if the exchange doesn't have the pair
+    pub fn handle_remove_pair(
+        &mut self,
+        remove_pair_update: &RemovePairUpdate,
+    ) -> Result<Option<Vec<usize>>, WalError> {
+        let (exchange, currency, other_currency) = match (
+            ExchangeId::get(&remove_pair_update.exchange),
+            CurrencyId::get(&remove_pair_update.currency),
+            CurrencyId::get(&remove_pair_update.other_currency),
+        ) {
+            (Some(exchange), Some(currency), Some(other_currency))
+                if self
+                    .exchange_graph
+                    .has_pair(exchange, currency, other_currency) =>
+            {
+                (exchange, currency, other_currency)
+            }
+            _ => return Ok(None),
+        };
+
+        // with the time of the removal, so replaying it removes the pair at the same time
+        let timestamp = remove_pair_update
+            .timestamp
+            .unwrap_or_else(|| self.clock.now());
+        self.append_to_log(&RemovePairUpdate {
+            timestamp: Some(timestamp),
+            ..remove_pair_update.clone()
+        })?;
+        let removed_nodes = self
+            .exchange_graph
+            .remove_pair(exchange, currency, other_currency);
+        let pair_key = PairKey::new(exchange, currency, other_currency);
+        self.price_updates.remove(&pair_key);
+        self.price_history.remove(pair_key, timestamp);
+        self.order_books.remove(exchange, currency, other_currency);
+        self.solve_rates_table();
+
+        Ok(removed_nodes)
+    }
+
+    /// Removes all the nodes of the exchange, returns them.
+    /// The history keeps the removal, at the time of the update or now
+    pub fn handle_remove_exchange(
+        &mut self,
+        remove_exchange_update: &RemoveExchangeUpdate,
+    ) -> Result<Vec<usize>, WalError> {
+        let exchange = match ExchangeId::get(&remove_exchange_update.exchange) {
+            Some(exchange) if !self.exchange_graph.exchange_nodes(exchange).is_empty() => exchange,
+            _ => return Ok(Vec::new()),
+        };
+
+        let timestamp = remove_exchange_update
+            .timestamp
+            .unwrap_or_else(|| self.clock.now());
+        self.append_to_log(&RemoveExchangeUpdate {
+            timestamp: Some(timestamp),
+            ..remove_exchange_update.clone()
+        })?;
+        let removed_nodes = self.exchange_graph.remove_exchange(exchange);
+        self.price_updates
+            .retain(|pair_key, _| pair_key.exchange != exchange);
+        self.price_history.remove_exchange(exchange, timestamp);
+        self.order_books.remove_exchange(exchange);
+        self.solve_rates_table();
+
+        Ok(removed_nodes)
+    }
+
+    /// Removing edges can make many of the best rates worse, so the table is solved again
+    fn solve_rates_table(&mut self) {
+        self.rates_table = RatesTable::solve(
+            &self.exchange_graph.graph,
+            self.exchange_graph.index_map.index_bound(),
+            self.rates_table.threads(),
+        );
+    }
+
+    /// Adds the update to the graph and repairs the best rates affected by the changed edges,
+    /// returns the new nodes and the changed edges
+    fn add_to_graph(&mut self, price_update: &PriceUpdate) -> (Vec<usize>, Vec<(usize, usize)>) {
+        let index_map = &self.exchange_graph.index_map;
+        let source_key = (price_update.exchange, price_update.source_currency);
+        let destination_key = (price_update.exchange, price_update.destination_currency);
+
+        let mut changed_edges = match (index_map.get(&source_key), index_map.get(&destination_key))
+        {
+            (Some(&source_node), Some(&destination_node)) => {
+                let graph = &self.exchange_graph.graph;
+
+                vec![
+                    (
+                        source_node,
+                        destination_node,
+                        graph.edge_weight(source_node, destination_node).cloned(),
+                    ),
+                    (
+                        destination_node,
+                        source_node,
+                        graph.edge_weight(destination_node, source_node).cloned(),
+                    ),
+                ]
+            }
+            _ => Vec::new(),
+        };
+
+        let (source_index, destination_index) = self.exchange_graph.add(price_update);
+        let changed_nodes: Vec<usize> = [source_index, destination_index]
+            .iter()
+            .filter_map(|index| match index {
+                Index::Inserted(node) => Some(*node),
+                Index::Fetched(_) => None,
+            })
+            .collect();
+
+        let graph = &self.exchange_graph.graph;
+        // all the edges of the new nodes are new as well, the nodes recycling the indices
+        // of removed ones are already in the table, without any edges
+        for &node in &changed_nodes {
+            while self.rates_table.nodes_count() <= node {
+                self.rates_table.add_node();
+            }
+
+            changed_edges.extend(graph.edges(node).map(|(from, to, _)| (from, to, None)));
+            changed_edges.extend(
+                graph
+                    .neighbors_directed(node, Direction::Incoming)
+                    .map(|from| (from, node, None)),
+            );
+        }
+
+        // the edges between two new nodes are there twice
+        changed_edges.sort_by_key(|&(from_node, to_node, _)| (from_node, to_node));
+        changed_edges.dedup_by_key(|&mut (from_node, to_node, _)| (from_node, to_node));
+
+        let mut changed_edge_nodes = Vec::new();
+        for (from_node, to_node, previous_weight) in changed_edges {
+            if graph.edge_weight(from_node, to_node) == previous_weight.as_ref() {
+                continue;
+            }
+
+            self.rates_table
+                .update_edge(graph, from_node, to_node, previous_weight);
+            changed_edge_nodes.push((from_node, to_node));
+        }
+
+        (changed_nodes, changed_edge_nodes)
+    }
+
+    /// The nodes, the latest update of each pair and the configuration,
+    /// ordered so the same state always makes the same snapshot
+    pub fn snapshot(&self) -> Snapshot {
+        let exchange_graph = &self.exchange_graph;
+
+        let mut price_updates: Vec<PriceUpdate> = self.price_updates.values().cloned().collect();
+        price_updates.sort_by_key(PriceUpdate::pair_key);
+
+        let mut order_books: Vec<OrderBookUpdate> = self.order_books.books().cloned().collect();
+        order_books.sort_by_key(|order_book| order_book.price_update().pair_key());
+
+        let mut transfer_costs: Vec<TransferCostUpdate> = exchange_graph
+            .transfer_costs
+            .iter()
+            .map(
+                |((from_exchange, to_exchange, currency), cost)| TransferCostUpdate {
+                    from_exchange: from_exchange.to_string(),
+                    to_exchange: to_exchange.to_string(),
+                    currency: currency.to_string(),
+                    percent_fee: cost.percent_fee,
+                    fixed_fee: cost.fixed_fee,
+                },
+            )
+            .collect();
+        transfer_costs.sort_by(|left, right| {
+            (&left.from_exchange, &left.to_exchange, &left.currency).cmp(&(
+                &right.from_exchange,
+                &right.to_exchange,
+                &right.currency,
+            ))
+        });
+
+        // the fees of the exchanges first, the pairs override them
+        let mut trading_fees: Vec<TradingFeeUpdate> = exchange_graph
+            .trading_fees
+            .exchange_fees()
+            .map(|(exchange, &percent_fee)| TradingFeeUpdate {
+                exchange: exchange.to_string(),
+                percent_fee,
+                pair: None,
+            })
+            .chain(exchange_graph.trading_fees.pair_fees().map(
+                |(&(exchange, currency, other_currency), &percent_fee)| {
+                    // either way around is the same pair
+                    let pair = if currency <= other_currency {
+                        (currency, other_currency)
+                    } else {
+                        (other_currency, currency)
+                    };
+
+                    TradingFeeUpdate {
+                        exchange: exchange.to_string(),
+                        percent_fee,
+                        pair: Some((pair.0.to_string(), pair.1.to_string())),
+                    }
+                },
+            ))
+            .collect();
+        trading_fees.sort_by(|left, right| {
+            (left.pair.is_some(), &left.exchange, &left.pair).cmp(&(
+                right.pair.is_some(),
+                &right.exchange,
+                &right.pair,
+            ))
+        });
+
+        let mut withdrawal_fees: Vec<WithdrawalFeeUpdate> = exchange_graph
+            .withdrawal_fees
+            .iter()
+            .map(|((exchange, currency), &fee)| WithdrawalFeeUpdate {
+                exchange: exchange.to_string(),
+                currency: currency.to_string(),
+                fee,
+            })
+            .collect();
+        withdrawal_fees.sort_by(|left, right| {
+            (&left.exchange, &left.currency).cmp(&(&right.exchange, &right.currency))
+        });
+
+        let mut edge_limits: Vec<EdgeLimitUpdate> = exchange_graph
+            .edge_limits
+            .iter()
+            .map(
+                |(&((from_exchange, from_currency), (to_exchange, to_currency)), &limit)| {
+                    EdgeLimitUpdate {
+                        from_exchange: from_exchange.to_string(),
+                        from_currency: from_currency.to_string(),
+                        to_exchange: to_exchange.to_string(),
+                        to_currency: to_currency.to_string(),
+                        limit,
+                    }
+                },
+            )
+            .collect();
+        edge_limits.sort_by(|left, right| {
+            (
+                &left.from_exchange,
+                &left.from_currency,
+                &left.to_exchange,
+                &left.to_currency,
+            )
+                .cmp(&(
+                    &right.from_exchange,
+                    &right.from_currency,
+                    &right.to_exchange,
+                    &right.to_currency,
+                ))
+        });
+
+        let mut max_ages: Vec<MaxAgeUpdate> = self
+            .max_ages
+            .exchange_max_ages()
+            .map(|(exchange, &max_age)| MaxAgeUpdate {
+                max_age,
+                exchange: Some(exchange.to_string()),
+            })
+            .collect();
+        max_ages.sort_by(|left, right| left.exchange.cmp(&right.exchange));
+        if let Some(max_age) = self.max_ages.global() {
+            max_ages.insert(
+                0,
+                MaxAgeUpdate {
+                    max_age,
+                    exchange: None,
+                },
+            );
+        }
+
+        Snapshot {
+            nodes: exchange_graph.index_map.indices().to_vec(),
+            price_updates,
+            order_books,
+            transfer_costs,
+            trading_fees,
+            withdrawal_fees,
+            edge_limits,
+            max_ages,
+            validation_policy: self.validation_policy,
+            log_sequence: self.log_sequence,
+        }
+    }
+
+    /// A handler with the state of the snapshot, its nodes keep their indices.
+    /// The history starts over with the updates of the snapshot
+    pub fn from_snapshot(snapshot: Snapshot, threads: usize) -> Self {
+        let mut graph_handler = GraphHandler::with_threads(threads);
+
+        // the configuration first, so the edges get their weights with it
+        for transfer_cost_update in &snapshot.transfer_costs {
+            graph_handler.set_transfer_cost(transfer_cost_update);
+        }
+        for trading_fee_update in &snapshot.trading_fees {
+            graph_handler.set_trading_fee(trading_fee_update);
+        }
+        for withdrawal_fee_update in &snapshot.withdrawal_fees {
+            graph_handler.set_withdrawal_fee(withdrawal_fee_update);
+        }
+        for edge_limit_update in &snapshot.edge_limits {
+            graph_handler.set_edge_limit(edge_limit_update);
+        }
+        for max_age_update in &snapshot.max_ages {
+            graph_handler.set_max_age(max_age_update);
+        }
+        graph_handler.validation_policy = snapshot.validation_policy;
+
+        graph_handler.exchange_graph.restore_nodes(snapshot.nodes);
+        graph_handler.restore_updates(snapshot.price_updates);
+        // their tops are already the updates of the pairs
+        for order_book in snapshot.order_books {
+            graph_handler.order_books.insert(order_book);
+        }
+        graph_handler.log_sequence = snapshot.log_sequence;
+
+        graph_handler
+    }
+
+    /// Adds updates which were already accepted, one for each pair,
+    /// the table is solved once for all of them
+    fn restore_updates<I>(&mut self, price_updates: I)
+    where
+        I: IntoIterator<Item = PriceUpdate>,
+    {
+        for price_update in price_updates {
+            self.exchange_graph.add(&price_update);
+            self.price_history.insert(&price_update);
+            self.price_updates
+                .insert(price_update.pair_key(), price_update.normalized());
         }
+        self.solve_rates_table();
+    }
+
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), SnapshotError> {
+        self.snapshot().save(path)
+    }
+
+    /// Replaces the state with the one of the snapshot, the clock, the threads and the log
+    /// are kept. The whole state is logged, so the log replays without the file.
+    /// The state is left as it is when the snapshot can't be read or logged
+    pub fn load_snapshot(&mut self, path: &Path) -> Result<(), SnapshotError> {
+        let mut snapshot = Snapshot::load(path)?;
+        if self.log.is_some() {
+            self.append_to_log(&snapshot.to_record())?;
+            // the state is in the log up to its own record
+            snapshot.log_sequence = self.log_sequence;
+        }
+        self.restore(snapshot);
+
+        Ok(())
+    }
+
+    /// Replaces the state with the one of the snapshot, keeps the clock and the log
+    fn restore(&mut self, snapshot: Snapshot) {
+        let mut graph_handler = Self::from_snapshot(snapshot, self.rates_table.threads());
+
+        std::mem::swap(&mut graph_handler.clock, &mut self.clock);
+        graph_handler.log = self.log.take();
+        *self = graph_handler;
+    }
+
+    /// Replays the records of the log after the sequence of the state and keeps appending
+    /// to the log from then on. Returns how many records were replayed
+    pub fn open_log(&mut self, path: &Path) -> Result<usize, WalError> {
+        let (log, records) = WriteAheadLog::open(path)?;
+        if log.sequence() < self.log_sequence {
+            return Err(WalError(WalErrorKind::MissingRecords));
+        }
+
+        let records = &records[self.log_sequence as usize..];
+        for record in records {
+            let invalid_record = WalError(WalErrorKind::InvalidRecord(record.sequence));
+
+            match Snapshot::from_record(&record.command) {
+                Some(snapshot) => self.restore(snapshot.map_err(|_| invalid_record.clone())?),
+                None => {
+                    let parsed_line =
+                        parse_line(&record.command).map_err(|_| invalid_record.clone())?;
+                    if !self.replay(parsed_line)? {
+                        return Err(invalid_record);
+                    }
+                }
+            }
+            self.log_sequence = record.sequence;
+        }
+
+        self.log = Some(log);
+
+        Ok(records.len())
+    }
+
+    /// Applies a logged command, `false` if it is not one which changes the state
+    fn replay(&mut self, parsed_line: ParsedLine) -> Result<bool, WalError> {
+        match parsed_line {
+            ParsedLine::PriceUpdate(price_update) => {
+                self.handle_update(price_update)?;
+            }
+            ParsedLine::OrderBookUpdate(order_book_update) => {
+                self.handle_order_book(order_book_update)?;
+            }
+            ParsedLine::TransferCostUpdate(transfer_cost_update) => {
+                self.handle_transfer_cost(&transfer_cost_update)?
+            }
+            ParsedLine::TradingFeeUpdate(trading_fee_update) => {
+                self.handle_trading_fee(&trading_fee_update)?
+            }
+            ParsedLine::WithdrawalFeeUpdate(withdrawal_fee_update) => {
+                self.handle_withdrawal_fee(&withdrawal_fee_update)?
+            }
+            ParsedLine::EdgeLimitUpdate(edge_limit_update) => {
+                self.handle_edge_limit(&edge_limit_update)?
+            }
+            ParsedLine::MaxAgeUpdate(max_age_update) => self.handle_max_age(&max_age_update)?,
+            ParsedLine::ValidationPolicyUpdate(validation_policy_update) => {
+                self.handle_validation_policy(&validation_policy_update)?
+            }
+            ParsedLine::RemovePairUpdate(remove_pair_update) => {
+                self.handle_remove_pair(&remove_pair_update)?;
+            }
+            ParsedLine::RemoveExchangeUpdate(remove_exchange_update) => {
+                self.handle_remove_exchange(&remove_exchange_update)?;
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Appends the command to the log, no command is applied without its record
+    fn append_to_log(&mut self, command: &dyn fmt::Display) -> Result<(), WalError> {
+        if let Some(log) = &mut self.log {
+            self.log_sequence = log.append(command)?;
+        }
+
+        Ok(())
+    }
+
+    /// A handler with the graph as it was at the timestamp, built from the price history
+    /// with the current configuration. The order books are not kept in the history,
+    /// so the requests at a timestamp don't have an amount
+    pub fn as_of(&self, timestamp: DateTime<Utc>) -> GraphHandler {
+        let mut graph_handler = GraphHandler {
+            exchange_graph: self.exchange_graph.empty_clone(),
+            rates_table: RatesTable::with_threads(self.rates_table.threads()),
+            max_ages: self.max_ages.clone(),
+            // the quotes are as old as they were at the timestamp
+            clock: Box::new(FixedClock(timestamp)),
+            ..GraphHandler::default()
+        };
+
+        graph_handler.restore_updates(self.price_history.as_of(timestamp).into_iter().cloned());
+
+        graph_handler
+    }
+
+    pub fn best_rate(
+        &self,
+        exchange_request: &ExchangeRequest,
+    ) -> Result<BestRates, RateRequestError> {
+        let (source_node, destination_node) = self.request_nodes(exchange_request)?;
+        let index_map = &self.exchange_graph.index_map;
+
+        if let Some(amount) = exchange_request.amount {
+            return self.best_amount_rate(exchange_request, amount);
+        }
+        if let Some(limits) = Self::path_limits(exchange_request) {
+            return self
+                .best_limited_paths(exchange_request, limits, 1)
+                .map(|mut best_paths| best_paths.remove(0));
+        }
+
+        let graph = self.search_graph();
+        let path_nodes = match graph {
+            Cow::Borrowed(_) if !self.rates_table.has_arbitrage() => {
+                self.rates_table.path(source_node, destination_node)
+            }
+            // the table has the stale edges or is unbounded,
+            // but the source might not reach the profitable cycle
+            _ => Exchanger::log_best_paths(&graph, index_map.index_bound(), source_node)
+                .map_err(|arbitrage| self.arbitrage_error(&arbitrage))?
+                .path(destination_node),
+        }
+        .ok_or(RateRequestError(RateRequestErrorKind::NoPath))?;
+
+        Ok(self.best_rates_for(exchange_request, &path_nodes))
+    }
+
+    /// The `paths_count` best loopless paths of the request, the best one first.
+    /// For an amount there is only the best path
+    pub fn best_paths(
+        &self,
+        exchange_request: &ExchangeRequest,
+    ) -> Result<Vec<BestRates>, RateRequestError> {
+        let paths_count = match exchange_request.paths_count {
+            Some(paths_count) if paths_count > 1 => paths_count,
+            _ => return Ok(vec![self.best_rate(exchange_request)?]),
+        };
+        if exchange_request.amount.is_some() {
+            return Err(RateRequestError(RateRequestErrorKind::PathsCountForAmount));
+        }
+        if let Some(limits) = Self::path_limits(exchange_request) {
+            return self.best_limited_paths(exchange_request, limits, paths_count);
+        }
+        let (source_node, destination_node) = self.request_nodes(exchange_request)?;
+
+        let paths = Exchanger::k_best_paths(
+            &self.search_graph(),
+            self.exchange_graph.index_map.index_bound(),
+            source_node,
+            destination_node,
+            paths_count,
+        )
+        .map_err(|arbitrage| self.arbitrage_error(&arbitrage))?;
+
+        if paths.is_empty() {
+            return Err(RateRequestError(RateRequestErrorKind::NoPath));
+        }
+
+        Ok(paths
+            .iter()
+            .map(|path_nodes| self.best_rates_for(exchange_request, path_nodes))
+            .collect())
+    }
+
+    /// The path giving the most of the destination currency for the amount,
+    /// the trades of the pairs with an order book walk its levels
+    fn best_amount_rate(
+        &self,
+        exchange_request: &ExchangeRequest,
+        amount: f64,
+    ) -> Result<BestRates, RateRequestError> {
+        let (source_node, destination_node) = self.request_nodes(exchange_request)?;
+        let exchange_graph = &self.exchange_graph;
+        let limits = Self::path_limits(exchange_request);
+        let no_path = match limits {
+            Some(_) => RateRequestErrorKind::NoPathWithinLimits,
+            None => RateRequestErrorKind::NoPathForAmount,
+        };
+        let limits = limits.unwrap_or_default();
+        let graph = self.search_graph();
+
+        self.check_bounded(&graph, limits, source_node)?;
+
+        let edges = Exchanger::search_edges(
+            &graph,
+            limits,
+            |from_node, to_node| exchange_graph.is_transfer(from_node, to_node),
+            |_, _| true,
+        );
+        let (output, path_nodes) = Exchanger::layered_best_path(
+            exchange_graph.index_map.index_bound(),
+            &edges,
+            (source_node, amount),
+            destination_node,
+            limits,
+            |edge, amount| self.edge_output(edge, 0.0, amount),
+        )
+        .ok_or(RateRequestError(no_path))?;
+
+        Ok(BestRates {
+            rate: output / amount,
+            amounts: Some(Amounts {
+                input: amount,
+                output,
+            }),
+            ..self.best_rates_for(exchange_request, &path_nodes)
+        })
+    }
+
+    /// The amount of the request split over the paths which give the most out of it,
+    /// the order books and the edge limits bound what each path can take.
+    /// The part of the amount no path can take is left unfilled
+    pub fn split_route(
+        &self,
+        exchange_request: &ExchangeRequest,
+    ) -> Result<SplitRoute, RateRequestError> {
+        let amount = exchange_request
+            .amount
+            .ok_or(RateRequestError(RateRequestErrorKind::NoPathForAmount))?;
+        let (source_node, destination_node) = self.request_nodes(exchange_request)?;
+        let exchange_graph = &self.exchange_graph;
+        let limits = Self::path_limits(exchange_request).unwrap_or_default();
+        let graph = self.search_graph();
+
+        self.check_bounded(&graph, limits, source_node)?;
+
+        let edges = Exchanger::search_edges(
+            &graph,
+            limits,
+            |from_node, to_node| exchange_graph.is_transfer(from_node, to_node),
+            |_, _| true,
+        );
+        let split_paths = Exchanger::split_paths(
+            exchange_graph.index_map.index_bound(),
+            &edges,
+            (source_node, amount),
+            destination_node,
+            limits,
+            SPLIT_CHUNKS,
+            |edge, sent, amount| self.edge_output(edge, sent, amount),
+        );
+        if split_paths.is_empty() {
+            return Err(RateRequestError(RateRequestErrorKind::NoPathForAmount));
+        }
+        let input: f64 = split_paths.iter().map(|split_path| split_path.input).sum();
+
+        let routes: Vec<BestRates> = split_paths
+            .iter()
+            .map(|split_path| BestRates {
+                rate: split_path.output / split_path.input,
+                amounts: Some(Amounts {
+                    input: split_path.input,
+                    output: split_path.output,
+                }),
+                ..self.best_rates_for(exchange_request, &split_path.path)
+            })
+            .collect();
+
+        Ok(SplitRoute {
+            source_exchange: exchange_request.source_exchange.to_string(),
+            source_currency: exchange_request.source_currency.to_string(),
+            destination_exchange: exchange_request.destination_exchange.to_string(),
+            destination_currency: exchange_request.destination_currency.to_string(),
+            amounts: Amounts {
+                input,
+                output: split_paths.iter().map(|split_path| split_path.output).sum(),
+            },
+            unfilled: (amount - input).max(0.0),
+            routes,
+        })
+    }
+
+    /// The amount exchanged over the edge when `sent` was already sent over it,
+    /// through the order book if the pair has one and after the fixed fees of the transfers,
+    /// which are only paid once
+    fn edge_output(&self, edge: &SearchEdge, sent: f64, amount: f64) -> Option<f64> {
+        let exchange_graph = &self.exchange_graph;
+        let &(exchange, from_currency) = exchange_graph.index_map.get_index(&edge.from_node)?;
+        let &(to_exchange, to_currency) = exchange_graph.index_map.get_index(&edge.to_node)?;
+
+        if let Some(limit) = exchange_graph.edge_limit(edge.from_node, edge.to_node) {
+            if sent + amount > limit * (1.0 + LIMIT_EPSILON) {
+                return None;
+            }
+        }
+
+        let order_book = match self.order_books.get(exchange, from_currency, to_currency) {
+            Some(order_book) if exchange == to_exchange => order_book,
+            _ => {
+                let mut edge_cost =
+                    exchange_graph.edge_cost(edge.from_node, edge.to_node, edge.weight);
+                if sent > 0.0 {
+                    edge_cost.fixed = 0.0;
+                }
+
+                return edge_cost.output(amount);
+            }
+        };
+        let fee_rate = self
+            .exchange_graph
+            .trading_fees
+            .rate(exchange, from_currency, to_currency);
+
+        // the levels taken by the amount already sent are not there anymore
+        let output = book::execute(order_book, from_currency, sent + amount)?
+            - book::execute(order_book, from_currency, sent)?;
+
+        Some(output * fee_rate)
+    }
+
+    /// Without a hops limit the searches are only bounded if the source can't reach
+    /// a profitable cycle
+    fn check_bounded(
+        &self,
+        graph: &DiGraphMap<usize, f64>,
+        limits: PathLimits,
+        source_node: usize,
+    ) -> Result<(), RateRequestError> {
+        if limits.max_hops.is_none() && self.rates_table.has_arbitrage() {
+            Exchanger::log_best_paths(
+                graph,
+                self.exchange_graph.index_map.index_bound(),
+                source_node,
+            )
+            .map_err(|arbitrage| self.arbitrage_error(&arbitrage))?;
+        }
+
+        Ok(())
+    }
+
+    /// The best paths within the limits, at least one or an error
+    fn best_limited_paths(
+        &self,
+        exchange_request: &ExchangeRequest,
+        limits: PathLimits,
+        paths_count: usize,
+    ) -> Result<Vec<BestRates>, RateRequestError> {
+        let (source_node, destination_node) = self.request_nodes(exchange_request)?;
+        let graph = self.search_graph();
+        let nodes_count = self.exchange_graph.index_map.index_bound();
+
+        self.check_bounded(&graph, limits, source_node)?;
+
+        let paths = Exchanger::k_best_limited_paths(
+            &graph,
+            nodes_count,
+            source_node,
+            destination_node,
+            paths_count,
+            limits,
+            |from_node, to_node| self.exchange_graph.is_transfer(from_node, to_node),
+        );
+
+        if paths.is_empty() {
+            return Err(RateRequestError(RateRequestErrorKind::NoPathWithinLimits));
+        }
+
+        Ok(paths
+            .iter()
+            .map(|path_nodes| self.best_rates_for(exchange_request, path_nodes))
+            .collect())
+    }
+
+    fn path_limits(exchange_request: &ExchangeRequest) -> Option<PathLimits> {
+        match (
+            exchange_request.max_hops,
+            exchange_request.max_exchange_transfers,
+        ) {
+            (None, None) => None,
+            (max_hops, max_transfers) => Some(PathLimits {
+                max_hops,
+                max_transfers,
+            }),
+        }
+    }
+
+    /// The source and destination nodes of the request
+    fn request_nodes(
+        &self,
+        exchange_request: &ExchangeRequest,
+    ) -> Result<(usize, usize), RateRequestError> {
+        let source_node = self
+            .node(
+                &exchange_request.source_exchange,
+                &exchange_request.source_currency,
+            )
+            .ok_or(RateRequestError(RateRequestErrorKind::UnknownSource))?;
+        let destination_node = self
+            .node(
+                &exchange_request.destination_exchange,
+                &exchange_request.destination_currency,
+            )
+            .ok_or(RateRequestError(RateRequestErrorKind::UnknownDestination))?;
+
+        Ok((source_node, destination_node))
+    }
+
+    /// The node of the (exchange, currency), the names which were never interned
+    /// are not in the graph
+    fn node(&self, exchange: &str, currency: &str) -> Option<usize> {
+        let key = (ExchangeId::get(exchange)?, CurrencyId::get(currency)?);
+
+        self.exchange_graph.index_map.get(&key).cloned()
+    }
+
+    fn best_rates_for(
+        &self,
+        exchange_request: &ExchangeRequest,
+        path_nodes: &[usize],
+    ) -> BestRates {
+        BestRates {
+            source_exchange: exchange_request.source_exchange.to_string(),
+            source_currency: exchange_request.source_currency.to_string(),
+            destination_exchange: exchange_request.destination_exchange.to_string(),
+            destination_currency: exchange_request.destination_currency.to_string(),
+            rate: Exchanger::path_rate(&self.exchange_graph.graph, path_nodes),
+            path: self.node_keys(path_nodes),
+            amounts: None,
+        }
+    }
+
+    fn arbitrage_error(&self, arbitrage: &Arbitrage) -> RateRequestError {
+        RateRequestError(RateRequestErrorKind::Arbitrage(
+            self.node_keys(&arbitrage.cycle),
+        ))
+    }
+
+    /// All the profitable cycles of the graph up to the max length of the request,
+    /// the most profitable ones first
+    pub fn arbitrages(&self, arbitrage_request: &ArbitrageRequest) -> Vec<ProfitableCycle> {
+        let graph = self.search_graph();
+        let min_rate = 1.0 + arbitrage_request.min_profit;
+
+        let mut cycles: Vec<ProfitableCycle> = Exchanger::profitable_cycles(
+            &graph,
+            self.exchange_graph.index_map.index_bound(),
+            min_rate,
+            arbitrage_request.max_length,
+        )
+        .into_iter()
+        .map(|arbitrage| ProfitableCycle {
+            max_length: arbitrage_request.max_length,
+            rate: Exchanger::path_rate(&graph, &arbitrage.cycle),
+            path: self.node_keys(&arbitrage.cycle),
+        })
+        .collect();
+
+        cycles.sort_by(|left, right| {
+            right
+                .rate
+                .partial_cmp(&left.rate)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        cycles
+    }
+
+    /// The trade edges left out of the searches, since their quotes are older than the max age
+    pub fn stale_edges(&self) -> StaleEdges {
+        let now = self.clock.now();
+
+        let edges = self
+            .stale_quotes(now)
+            .into_iter()
+            .filter_map(|((from_node, to_node), quote)| {
+                let index_map = &self.exchange_graph.index_map;
+                let (exchange, source_currency) = index_map.get_index(&from_node)?;
+                let (_, destination_currency) = index_map.get_index(&to_node)?;
+
+                Some(StaleEdge {
+                    exchange: exchange.to_string(),
+                    source_currency: source_currency.to_string(),
+                    destination_currency: destination_currency.to_string(),
+                    timestamp: quote.timestamp,
+                    age: now.signed_duration_since(quote.timestamp).num_seconds(),
+                })
+            })
+            .collect();
+
+        StaleEdges { edges }
+    }
+
+    /// The counts of the graph, its strongly connected components and the timestamps
+    /// of the latest updates of the pairs, with the exchanges quoting the most pairs
+    pub fn graph_stats(&self, top_exchanges: usize) -> GraphStats {
+        let exchange_graph = &self.exchange_graph;
+        let keys: Vec<&(ExchangeId, CurrencyId)> = exchange_graph
+            .index_map
+            .iter()
+            .map(|(key, _)| key)
+            .collect();
+        let exchanges: HashSet<ExchangeId> = keys.iter().map(|&&(exchange, _)| exchange).collect();
+        let currencies: HashSet<CurrencyId> = keys.iter().map(|&&(_, currency)| currency).collect();
+
+        let transfer_edges = exchange_graph
+            .get_edges()
+            .filter(|&(from_node, to_node, _)| exchange_graph.is_transfer(from_node, to_node))
+            .count();
+
+        let timestamps = self
+            .price_updates
+            .values()
+            .map(|price_update| price_update.timestamp);
+
+        let mut pairs_counts: HashMap<ExchangeId, usize> = HashMap::new();
+        for pair_key in self.price_updates.keys() {
+            *pairs_counts.entry(pair_key.exchange).or_default() += 1;
+        }
+        let mut pairs_counts: Vec<(ExchangeId, usize)> = pairs_counts.into_iter().collect();
+        pairs_counts.sort_by(|(exchange, count), (other_exchange, other_count)| {
+            other_count.cmp(count).then(exchange.cmp(other_exchange))
+        });
+
+        GraphStats {
+            exchanges: exchanges.len(),
+            currencies: currencies.len(),
+            nodes: exchange_graph.index_map.len(),
+            trade_edges: exchange_graph.graph.edge_count() - transfer_edges,
+            transfer_edges,
+            strongly_connected_components: kosaraju_scc(&exchange_graph.graph).len(),
+            oldest_quote: timestamps.clone().min(),
+            newest_quote: timestamps.max(),
+            top_exchanges: pairs_counts
+                .into_iter()
+                .take(top_exchanges)
+                .map(|(exchange, count)| (exchange.to_string(), count))
+                .collect(),
+        }
+    }
+
+    /// The graph in the Graphviz DOT format with the ages of the quotes as of now,
+    /// the best path of the request is highlighted
+    pub fn export_dot(
+        &self,
+        highlight: Option<&ExchangeRequest>,
+    ) -> Result<String, RateRequestError> {
+        let highlighted_path = match highlight {
+            Some(exchange_request) => self.best_rate(exchange_request)?.path,
+            None => Vec::new(),
+        };
+
+        Ok(dot::render(
+            &self.exchange_graph,
+            self.clock.now(),
+            &highlighted_path,
+        ))
+    }
+
+    /// The quotes of the trade edges older than the max age of their exchange
+    fn stale_quotes(&self, now: DateTime<Utc>) -> Vec<((usize, usize), Quote)> {
+        let index_map = &self.exchange_graph.index_map;
+
+        let mut stale_quotes: Vec<((usize, usize), Quote)> = self
+            .exchange_graph
+            .quotes
+            .iter()
+            .filter(|(&(from_node, _), quote)| {
+                index_map
+                    .get_index(&from_node)
+                    .is_some_and(|&(exchange, _)| {
+                        self.max_ages.is_stale(exchange, quote.timestamp, now)
+                    })
+            })
+            .map(|(&edge, &quote)| (edge, quote))
+            .collect();
+        stale_quotes.sort_by_key(|&(edge, _)| edge);
+
+        stale_quotes
+    }
+
+    /// The graph without the stale edges, borrowed when none of the edges is stale
+    fn search_graph(&self) -> Cow<'_, DiGraphMap<usize, f64>> {
+        let stale_quotes = self.stale_quotes(self.clock.now());
+        if stale_quotes.is_empty() {
+            return Cow::Borrowed(&self.exchange_graph.graph);
+        }
+
+        let mut graph = self.exchange_graph.graph.clone();
+        for ((from_node, to_node), _) in stale_quotes {
+            graph.remove_edge(from_node, to_node);
+        }
+
+        Cow::Owned(graph)
     }
-}
 
-impl GraphHandler {
-    pub fn handle_update(&mut self, price_update: PriceUpdate) {
-        match self.price_updates.get(&price_update) {
-            Some(current_price_update) => {
-                if price_update.is_eq_and_newer(&current_price_update) {
-                    self.exchange_graph.add(&price_update);
-                    self.price_updates.replace(price_update);
-                }
-            }
-            None => {
-                self.exchange_graph.add(&price_update);
-                self.price_updates.insert(price_update);
-            }
-        }
+    /// Maps the nodes back to the names of their (exchange, currency) keys
+    fn node_keys(&self, nodes: &[usize]) -> Vec<(String, String)> {
+        nodes
+            .iter()
+            .filter_map(|node| self.exchange_graph.index_map.get_index(node))
+            .map(|(exchange, currency)| (exchange.to_string(), currency.to_string()))
+            .collect()
     }
 }
 
@@ -44,7 +1355,8 @@ impl From<Vec<PriceUpdate>> for GraphHandler {
     fn from(price_updates: Vec<PriceUpdate>) -> Self {
         let mut graph_handler = GraphHandler::default();
         for price_update in price_updates {
-            graph_handler.handle_update(price_update);
+            // there is no log to append the updates to, so they can't fail
+            let _ = graph_handler.handle_update(price_update);
         }
 
         graph_handler
@@ -54,8 +1366,11 @@ impl From<Vec<PriceUpdate>> for GraphHandler {
 #[cfg(test)]
 mod test {
     use chrono::prelude::*;
+    use chrono::Duration;
 
     use super::*;
+    use crate::graph::staleness::FixedClock;
+    use crate::ParseErrorKind;
 
     #[test]
     fn it_handles_price_update_addition() {
@@ -65,7 +1380,7 @@ mod test {
 
         assert_eq!(0, graph_handler.price_updates.len());
 
-        graph_handler.handle_update(price_update);
+        graph_handler.handle_update(price_update).unwrap();
 
         assert_eq!(1, graph_handler.price_updates.len());
         assert_eq!(2, graph_handler.exchange_graph.get_edges().count());
@@ -76,9 +1391,8 @@ mod test {
         let mut graph_handler = GraphHandler::default();
         let latest_timestamp = Utc::now();
 
-        let price_update =
-            PriceUpdate::new(latest_timestamp.clone(), "Ex", "EUR", "ETH", 1000.0, 0.009);
-        graph_handler.handle_update(price_update);
+        let price_update = PriceUpdate::new(latest_timestamp, "Ex", "EUR", "ETH", 1000.0, 0.009);
+        graph_handler.handle_update(price_update).unwrap();
 
         let older_price_update = PriceUpdate::new(
             Utc.ymd(2017, 12, 10).and_hms(0, 0, 0),
@@ -88,18 +1402,19 @@ mod test {
             10.0,
             0.9,
         );
-        graph_handler.handle_update(older_price_update);
+        graph_handler.handle_update(older_price_update).unwrap();
 
         assert_eq!(1, graph_handler.price_updates.len());
         assert_eq!(2, graph_handler.exchange_graph.get_edges().count());
 
         // get the current PriceUpdate
-        let current_price_update = graph_handler.price_updates.iter().next().unwrap();
+        let current_price_update = graph_handler.price_updates.values().next().unwrap();
 
-        // we only need to check for the timestamp, forward and backward factors
+        // we only need to check for the timestamp, forward and backward factors,
+        // EUR/ETH is kept as ETH/EUR so the factors are swapped
         assert_eq!(latest_timestamp, current_price_update.timestamp);
-        assert_eq!(1000.0, current_price_update.forward_factor);
-        assert_eq!(0.009, current_price_update.backward_factor);
+        assert_eq!(0.009, current_price_update.forward_factor);
+        assert_eq!(1000.0, current_price_update.backward_factor);
     }
 
     #[test]
@@ -115,21 +1430,1332 @@ mod test {
             10.0,
             0.9,
         );
-        graph_handler.handle_update(price_update);
+        graph_handler.handle_update(price_update).unwrap();
 
         let newer_price_update =
-            PriceUpdate::new(latest_timestamp.clone(), "Ex", "EUR", "ETH", 1000.0, 0.009);
-        graph_handler.handle_update(newer_price_update);
+            PriceUpdate::new(latest_timestamp, "Ex", "EUR", "ETH", 1000.0, 0.009);
+        graph_handler.handle_update(newer_price_update).unwrap();
 
         assert_eq!(1, graph_handler.price_updates.len());
         assert_eq!(2, graph_handler.exchange_graph.get_edges().count());
 
         // get the current PriceUpdate
-        let current_price_update = graph_handler.price_updates.iter().next().unwrap();
+        let current_price_update = graph_handler.price_updates.values().next().unwrap();
 
-        // we only need to check for the timestamp, forward and backward factors
+        // we only need to check for the timestamp, forward and backward factors,
+        // EUR/ETH is kept as ETH/EUR so the factors are swapped
         assert_eq!(latest_timestamp, current_price_update.timestamp);
-        assert_eq!(1000.0, current_price_update.forward_factor);
-        assert_eq!(0.009, current_price_update.backward_factor);
+        assert_eq!(0.009, current_price_update.forward_factor);
+        assert_eq!(1000.0, current_price_update.backward_factor);
+    }
+
+    #[test]
+    fn it_reports_the_outcome_and_the_changes_of_the_updates() {
+        let mut graph_handler = GraphHandler::default();
+        let now = Utc::now();
+        let earlier = now - Duration::minutes(1);
+        let later = now + Duration::minutes(1);
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                now, "KRAKEN", "BTC", "USD", 1000.0, 0.0009,
+            ))
+            .unwrap();
+        assert_eq!(UpdateOutcome::Inserted, report.outcome);
+        assert_eq!(vec![0, 1], report.changed_nodes);
+        assert_eq!(vec![(0, 1), (1, 0)], report.changed_edges);
+
+        // the new nodes are linked to the same currencies on KRAKEN
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(now, "GDAX", "BTC", "USD", 1001.0, 0.0008))
+            .unwrap();
+        assert_eq!(UpdateOutcome::Inserted, report.outcome);
+        assert_eq!(vec![2, 3], report.changed_nodes);
+        assert_eq!(
+            vec![(0, 2), (1, 3), (2, 0), (2, 3), (3, 1), (3, 2)],
+            report.changed_edges
+        );
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(now, "GDAX", "BTC", "USD", 1002.0, 0.0007))
+            .unwrap();
+        assert_eq!(UpdateOutcome::IgnoredDuplicate, report.outcome);
+        assert!(report.changed_edges.is_empty());
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                earlier, "GDAX", "BTC", "USD", 1002.0, 0.0007,
+            ))
+            .unwrap();
+        assert_eq!(UpdateOutcome::IgnoredOlder, report.outcome);
+
+        // only the forward factor changes
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                later, "GDAX", "BTC", "USD", 1002.0, 0.0008,
+            ))
+            .unwrap();
+        match report.outcome {
+            UpdateOutcome::Replaced { previous } => {
+                assert_eq!((now, 1001.0), (previous.timestamp, previous.forward_factor))
+            }
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        assert!(report.changed_nodes.is_empty());
+        assert_eq!(vec![(2, 3)], report.changed_edges);
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(later, "GDAX", "BTC", "EUR", 0.0, 0.0008))
+            .unwrap();
+        assert_eq!(
+            UpdateOutcome::Rejected(ParseCommandError(ParseErrorKind::ZeroFactor)),
+            report.outcome
+        );
+        assert_eq!(4, graph_handler.exchange_graph.index_map.len());
+    }
+
+    #[test]
+    fn the_newest_update_of_the_pair_wins_whatever_its_orientation() {
+        let mut graph_handler = GraphHandler::default();
+        let now = Utc::now();
+        let later = now + Duration::minutes(1);
+
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                now, "KRAKEN", "BTC", "USD", 1000.0, 0.0009,
+            ))
+            .unwrap();
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                now, "KRAKEN", "USD", "BTC", 0.0008, 1100.0,
+            ))
+            .unwrap();
+        assert_eq!(UpdateOutcome::IgnoredDuplicate, report.outcome);
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                later, "KRAKEN", "USD", "BTC", 0.0008, 1100.0,
+            ))
+            .unwrap();
+        assert!(matches!(report.outcome, UpdateOutcome::Replaced { .. }));
+        assert_eq!(vec![(0, 1), (1, 0)], report.changed_edges);
+        assert_eq!(
+            Some(&1100.0),
+            graph_handler.exchange_graph.graph.edge_weight(0, 1)
+        );
+
+        // kept as BTC/USD with the factors swapped
+        assert_eq!(1, graph_handler.price_updates.len());
+        let price_update = &graph_handler.price_updates
+            [&PairKey::new("KRAKEN".into(), "USD".into(), "BTC".into())];
+        assert_eq!(
+            ("BTC", 1100.0, 0.0008),
+            (
+                price_update.source_currency.as_str(),
+                price_update.forward_factor,
+                price_update.backward_factor
+            )
+        );
+
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                now, "KRAKEN", "BTC", "USD", 1200.0, 0.0007,
+            ))
+            .unwrap();
+        assert_eq!(UpdateOutcome::IgnoredOlder, report.outcome);
+    }
+
+    #[test]
+    fn the_removed_pairs_stay_removed_in_the_history() {
+        let timestamp = |time: &str| {
+            format!("2017-11-01T{}+00:00", time)
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        };
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(
+                timestamp("09:00:00"),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ),
+            PriceUpdate::new(timestamp("09:00:00"), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+        graph_handler.clock = Box::new(FixedClock(timestamp("10:00:00")));
+
+        graph_handler
+            .handle_remove_pair(&RemovePairUpdate {
+                exchange: "KRAKEN".into(),
+                currency: "USD".into(),
+                other_currency: "BTC".into(),
+                timestamp: None,
+            })
+            .unwrap();
+        graph_handler
+            .handle_remove_exchange(&RemoveExchangeUpdate {
+                exchange: "GDAX".into(),
+                timestamp: Some(timestamp("11:00:00")),
+            })
+            .unwrap();
+
+        let pairs_as_of = |time| {
+            graph_handler
+                .as_of(timestamp(time))
+                .price_updates
+                .keys()
+                .map(|pair_key| pair_key.exchange.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(2, pairs_as_of("09:59:59").len());
+        assert_eq!(vec!["GDAX".to_string()], pairs_as_of("10:00:00"));
+        assert!(pairs_as_of("12:00:00").is_empty());
+    }
+
+    #[test]
+    fn it_removes_pairs_and_exchanges_and_reuses_their_nodes() {
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+            PriceUpdate::new(Utc::now(), "GDAX", "USD", "EUR", 0.9, 1.1),
+        ]);
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+        assert_eq!(
+            1001.0,
+            graph_handler.best_rate(&exchange_request).unwrap().rate
+        );
+
+        // GDAX USD still has the EUR pair
+        let remove_pair = RemovePairUpdate {
+            exchange: "GDAX".into(),
+            currency: "USD".into(),
+            other_currency: "BTC".into(),
+            timestamp: None,
+        };
+        assert_eq!(
+            Some(vec![2]),
+            graph_handler.handle_remove_pair(&remove_pair).unwrap()
+        );
+        assert_eq!(
+            None,
+            graph_handler.handle_remove_pair(&remove_pair).unwrap()
+        );
+        assert_eq!(2, graph_handler.price_updates.len());
+        assert_eq!(
+            1000.0,
+            graph_handler.best_rate(&exchange_request).unwrap().rate
+        );
+
+        let remove_exchange = RemoveExchangeUpdate {
+            exchange: "GDAX".into(),
+            timestamp: None,
+        };
+        assert_eq!(
+            vec![3, 4],
+            graph_handler
+                .handle_remove_exchange(&remove_exchange)
+                .unwrap()
+        );
+        assert_eq!(1, graph_handler.price_updates.len());
+        assert_eq!(2, graph_handler.exchange_graph.index_map.len());
+
+        // the new nodes reuse the removed ones and the table is repaired incrementally again
+        let report = graph_handler
+            .handle_update(PriceUpdate::new(
+                Utc::now(),
+                "BITSTAMP",
+                "BTC",
+                "USD",
+                1002.0,
+                0.0008,
+            ))
+            .unwrap();
+        assert_eq!(vec![2, 3], report.changed_nodes);
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1002.0, best_rates.rate);
+        assert_eq!("BITSTAMP", best_rates.path[1].0);
+
+        let expected = RatesTable::new(
+            &graph_handler.exchange_graph.graph,
+            graph_handler.exchange_graph.index_map.index_bound(),
+        );
+        for from_node in 0..5 {
+            for to_node in 0..5 {
+                assert_eq!(
+                    expected.rate(from_node, to_node),
+                    graph_handler.rates_table.rate(from_node, to_node)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_restores_the_same_graph_from_its_snapshot() {
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "USD", "BTC", 0.0008, 1001.0),
+            PriceUpdate::new(Utc::now(), "GDAX", "EUR", "USD", 1.1, 0.9),
+            PriceUpdate::new(Utc::now(), "BITSTAMP", "BTC", "EUR", 900.0, 0.001),
+        ]);
+        graph_handler
+            .handle_transfer_cost(&TransferCostUpdate {
+                from_exchange: "KRAKEN".to_owned(),
+                to_exchange: "GDAX".to_owned(),
+                currency: "BTC".to_owned(),
+                percent_fee: 0.1,
+                fixed_fee: 0.0005,
+            })
+            .unwrap();
+        graph_handler
+            .handle_trading_fee(&TradingFeeUpdate {
+                exchange: "GDAX".to_owned(),
+                percent_fee: 0.25,
+                pair: None,
+            })
+            .unwrap();
+        graph_handler
+            .handle_max_age(&MaxAgeUpdate {
+                max_age: Duration::seconds(60),
+                exchange: Some("GDAX".to_owned()),
+            })
+            .unwrap();
+        graph_handler
+            .handle_validation_policy(&ValidationPolicyUpdate {
+                policy: ValidationPolicy::Reject,
+            })
+            .unwrap();
+        graph_handler
+            .handle_order_book(
+                OrderBookUpdate::from_input(&[
+                    OrderBookUpdate::COMMAND_PREFIX,
+                    &Utc::now().to_rfc3339(),
+                    "BITSTAMP",
+                    "BTC",
+                    "EUR",
+                    "905@1,880@5",
+                    "950@2",
+                ])
+                .unwrap(),
+            )
+            .unwrap();
+        // a hole in the indices
+        graph_handler
+            .handle_remove_exchange(&RemoveExchangeUpdate {
+                exchange: "KRAKEN".into(),
+                timestamp: None,
+            })
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "exchange_rate_path_snapshot_{}.txt",
+            std::process::id()
+        ));
+        graph_handler.save_snapshot(&path).unwrap();
+
+        let mut restored_handler = GraphHandler::default();
+        restored_handler.load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let exchange_graph = &graph_handler.exchange_graph;
+        let restored_graph = &restored_handler.exchange_graph;
+        assert_eq!(
+            exchange_graph.index_map.indices(),
+            restored_graph.index_map.indices()
+        );
+        let edges = |exchange_graph: &ExchangeCompleteGraph| {
+            let mut edges: Vec<(usize, usize, f64)> = exchange_graph
+                .get_edges()
+                .map(|(from_node, to_node, &weight)| (from_node, to_node, weight))
+                .collect();
+            edges.sort_by_key(|&(from_node, to_node, _)| (from_node, to_node));
+            edges
+        };
+        assert_eq!(edges(exchange_graph), edges(restored_graph));
+        assert_eq!(exchange_graph.quotes, restored_graph.quotes);
+
+        let nodes_count = exchange_graph.index_map.index_bound();
+        for from_node in 0..nodes_count {
+            for to_node in 0..nodes_count {
+                assert_eq!(
+                    graph_handler.rates_table.rate(from_node, to_node),
+                    restored_handler.rates_table.rate(from_node, to_node)
+                );
+            }
+        }
+        assert_eq!(graph_handler.snapshot(), restored_handler.snapshot());
+        assert_eq!(1, restored_handler.order_books.len());
+        let exchange_request = ExchangeRequest {
+            source_exchange: "BITSTAMP".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "BITSTAMP".into(),
+            destination_currency: "EUR".into(),
+            amount: Some(3.0),
+            ..ExchangeRequest::default()
+        };
+        assert_eq!(
+            graph_handler.best_rate(&exchange_request),
+            restored_handler.best_rate(&exchange_request)
+        );
+        assert_eq!(
+            Some(Duration::seconds(60)),
+            restored_handler.max_ages.get("GDAX".into())
+        );
+        assert_eq!(ValidationPolicy::Reject, restored_handler.validation_policy);
+
+        // the new nodes reuse the hole as they would have
+        let report = restored_handler
+            .handle_update(PriceUpdate::new(
+                Utc::now(),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ))
+            .unwrap();
+        assert_eq!(vec![0, 1], report.changed_nodes);
+    }
+
+    #[test]
+    fn replaying_the_log_gives_the_same_graph_and_rates() {
+        let temp_path = |extension: &str| {
+            std::env::temp_dir().join(format!(
+                "exchange_rate_path_replay_{}.{}",
+                std::process::id(),
+                extension
+            ))
+        };
+        let (log_path, snapshot_path) = (temp_path("wal"), temp_path("snapshot"));
+        let _ = std::fs::remove_file(&log_path);
+
+        let apply_lines = |graph_handler: &mut GraphHandler, lines: &[&str]| {
+            for line in lines {
+                assert_eq!(
+                    Ok(true),
+                    graph_handler.replay(parse_line(line).unwrap()),
+                    "{}",
+                    line
+                );
+            }
+        };
+        let assert_same_state = |graph_handler: &GraphHandler, replayed_handler: &GraphHandler| {
+            let (exchange_graph, replayed_graph) = (
+                &graph_handler.exchange_graph,
+                &replayed_handler.exchange_graph,
+            );
+            assert_eq!(
+                exchange_graph.index_map.indices(),
+                replayed_graph.index_map.indices()
+            );
+            let edges = |exchange_graph: &ExchangeCompleteGraph| {
+                let mut edges: Vec<(usize, usize, f64)> = exchange_graph
+                    .get_edges()
+                    .map(|(from_node, to_node, &weight)| (from_node, to_node, weight))
+                    .collect();
+                edges.sort_by_key(|&(from_node, to_node, _)| (from_node, to_node));
+                edges
+            };
+            assert_eq!(edges(exchange_graph), edges(replayed_graph));
+            assert_eq!(exchange_graph.quotes, replayed_graph.quotes);
+
+            let nodes_count = exchange_graph.index_map.index_bound();
+            for from_node in 0..nodes_count {
+                for to_node in 0..nodes_count {
+                    assert_eq!(
+                        graph_handler.rates_table.rate(from_node, to_node),
+                        replayed_handler.rates_table.rate(from_node, to_node)
+                    );
+                }
+            }
+            assert_eq!(graph_handler.snapshot(), replayed_handler.snapshot());
+
+            // the amounts walk the same order books
+            assert!(!replayed_handler.order_books.is_empty());
+            for (exchange, currency, other_currency) in
+                &[("BITSTAMP", "BTC", "EUR"), ("GDAX", "USD", "BTC")]
+            {
+                let exchange_request = ExchangeRequest {
+                    source_exchange: (*exchange).into(),
+                    source_currency: (*currency).into(),
+                    destination_exchange: (*exchange).into(),
+                    destination_currency: (*other_currency).into(),
+                    amount: Some(2.0),
+                    ..ExchangeRequest::default()
+                };
+                assert_eq!(
+                    graph_handler.best_rate(&exchange_request),
+                    replayed_handler.best_rate(&exchange_request)
+                );
+            }
+        };
+
+        let mut graph_handler = GraphHandler::default();
+        assert_eq!(Ok(0), graph_handler.open_log(&log_path));
+        apply_lines(
+            &mut graph_handler,
+            &[
+                "TRANSFER_COST KRAKEN GDAX BTC 0.1 0.0005",
+                "TRADING_FEE GDAX 0.25",
+                "VALIDATION_POLICY CLAMP",
+                "2017-11-01T09:42:23.123456789+00:00 KRAKEN BTC USD 1000.0 0.0011",
+                "2017-11-01T09:42:24+00:00 GDAX USD BTC 0.0008 1001.0",
+                "2017-11-01T09:42:25+00:00 GDAX EUR USD 1.1 0.9",
+                "ORDER_BOOK 2017-11-01T09:42:26+00:00 BITSTAMP BTC EUR 899@1,898@2 901@1.5",
+            ],
+        );
+        // neither an older update nor the unknown pair changes the state
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                "2017-11-01T09:00:00+00:00".parse().unwrap(),
+                "GDAX",
+                "USD",
+                "BTC",
+                0.0009,
+                1100.0,
+            ))
+            .unwrap();
+        graph_handler
+            .handle_remove_pair(&RemovePairUpdate {
+                exchange: "KRAKEN".into(),
+                currency: "BTC".into(),
+                other_currency: "JPY".into(),
+                timestamp: None,
+            })
+            .unwrap();
+        graph_handler.save_snapshot(&snapshot_path).unwrap();
+        apply_lines(
+            &mut graph_handler,
+            &[
+                "REMOVE_PAIR GDAX EUR USD",
+                "2017-11-01T09:42:27+00:00 GDAX BTC EUR 880.0 0.0011",
+                "REMOVE_EXCHANGE KRAKEN",
+                "MAX_AGE 60 GDAX",
+            ],
+        );
+        assert_eq!(11, graph_handler.log_sequence);
+
+        let mut replayed_handler = GraphHandler::default();
+        assert_eq!(Ok(11), replayed_handler.open_log(&log_path));
+        assert_same_state(&graph_handler, &replayed_handler);
+        drop(replayed_handler);
+
+        // the loaded state is in the log, so the file is not needed to replay it
+        graph_handler.load_snapshot(&snapshot_path).unwrap();
+        apply_lines(
+            &mut graph_handler,
+            &[
+                "2017-11-01T09:42:28+00:00 GDAX BTC USD 1002.0 0.00099",
+                "ORDER_BOOK 2017-11-01T09:42:29+00:00 GDAX BTC USD 1001@1,990@5 1010@0.5,1020@5",
+            ],
+        );
+        assert_eq!(14, graph_handler.log_sequence);
+
+        // only the records after the snapshot are replayed on top of it
+        let mut restored_handler = GraphHandler::default();
+        restored_handler.load_snapshot(&snapshot_path).unwrap();
+        assert_eq!(7, restored_handler.log_sequence);
+        std::fs::remove_file(&snapshot_path).unwrap();
+        assert_eq!(Ok(7), restored_handler.open_log(&log_path));
+        assert_same_state(&graph_handler, &restored_handler);
+
+        let mut replayed_handler = GraphHandler::default();
+        assert_eq!(Ok(14), replayed_handler.open_log(&log_path));
+        assert_same_state(&graph_handler, &replayed_handler);
+
+        std::fs::remove_file(&log_path).unwrap();
+    }
+
+    #[test]
+    fn it_reports_the_stats_of_the_graph() {
+        let timestamp = |seconds| {
+            "2017-11-01T09:42:23+00:00"
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+                + Duration::seconds(seconds)
+        };
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(timestamp(0), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(timestamp(10), "KRAKEN", "BTC", "EUR", 900.0, 0.001),
+            PriceUpdate::new(timestamp(20), "GDAX", "USD", "BTC", 0.0008, 1001.0),
+            PriceUpdate::new(timestamp(30), "BITSTAMP", "ETH", "JPY", 30000.0, 0.00003),
+        ]);
+        // a newer update of the pair replaces the oldest quote
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                timestamp(40),
+                "KRAKEN",
+                "USD",
+                "BTC",
+                0.001,
+                999.0,
+            ))
+            .unwrap();
+
+        let graph_stats = graph_handler.graph_stats(2);
+        assert_eq!(
+            GraphStats {
+                exchanges: 3,
+                currencies: 5,
+                nodes: 7,
+                trade_edges: 8,
+                // BTC and USD between KRAKEN and GDAX, both ways
+                transfer_edges: 4,
+                // the BITSTAMP ETH and JPY are not connected to the rest
+                strongly_connected_components: 2,
+                oldest_quote: Some(timestamp(10)),
+                newest_quote: Some(timestamp(40)),
+                top_exchanges: vec![("KRAKEN".to_string(), 2), ("BITSTAMP".to_string(), 1)],
+            },
+            graph_stats
+        );
+
+        assert_eq!(
+            GraphStats::default(),
+            GraphHandler::default().graph_stats(5)
+        );
+    }
+
+    #[test]
+    fn it_validates_the_price_updates_with_the_policy() {
+        let mut graph_handler = GraphHandler::default();
+        let round_trip = ParseCommandError(ParseErrorKind::ProfitableRoundTrip);
+
+        assert_eq!(
+            Validation::Rejected(ParseCommandError(ParseErrorKind::NaNFactor)),
+            graph_handler
+                .handle_update(PriceUpdate::new(
+                    Utc::now(),
+                    "KRAKEN",
+                    "BTC",
+                    "USD",
+                    f64::NAN,
+                    0.0009
+                ))
+                .unwrap()
+                .validation
+        );
+        assert_eq!(
+            Validation::Warned(round_trip),
+            graph_handler
+                .handle_update(PriceUpdate::new(
+                    Utc::now(),
+                    "KRAKEN",
+                    "BTC",
+                    "USD",
+                    1000.0,
+                    0.002
+                ))
+                .unwrap()
+                .validation
+        );
+        assert_eq!(1, graph_handler.price_updates.len());
+
+        graph_handler
+            .handle_validation_policy(&ValidationPolicyUpdate {
+                policy: ValidationPolicy::Reject,
+            })
+            .unwrap();
+        assert_eq!(
+            Validation::Rejected(round_trip),
+            graph_handler
+                .handle_update(PriceUpdate::new(
+                    Utc::now(),
+                    "GDAX",
+                    "BTC",
+                    "USD",
+                    1000.0,
+                    0.002
+                ))
+                .unwrap()
+                .validation
+        );
+        assert_eq!(1, graph_handler.price_updates.len());
+        assert_eq!(2, graph_handler.exchange_graph.index_map.len());
+    }
+
+    #[test]
+    fn it_answers_exchange_request_with_the_best_rate_and_path() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+
+        // going through GDAX gives a better rate than trading on KRAKEN directly
+        assert_eq!(1001.0, best_rates.rate);
+        assert_eq!(
+            vec![
+                ("KRAKEN".to_owned(), "BTC".to_owned()),
+                ("GDAX".to_owned(), "BTC".to_owned()),
+                ("GDAX".to_owned(), "USD".to_owned()),
+                ("KRAKEN".to_owned(), "USD".to_owned()),
+            ],
+            best_rates.path
+        );
+    }
+
+    #[test]
+    fn it_returns_errors_for_unknown_nodes_and_unreachable_destinations() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "ETH", "EUR", 200.0, 0.004),
+        ]);
+
+        let exchange_request =
+            |source_currency: &str, destination_currency: &str| ExchangeRequest {
+                source_exchange: "KRAKEN".into(),
+                source_currency: source_currency.into(),
+                destination_exchange: "GDAX".into(),
+                destination_currency: destination_currency.into(),
+                ..ExchangeRequest::default()
+            };
+
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::UnknownSource)),
+            graph_handler.best_rate(&exchange_request("LIT", "EUR"))
+        );
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::UnknownDestination)),
+            graph_handler.best_rate(&exchange_request("BTC", "LIT"))
+        );
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::NoPath)),
+            graph_handler.best_rate(&exchange_request("BTC", "EUR"))
+        );
+    }
+
+    #[test]
+    fn it_answers_exchange_request_with_the_best_paths_ranked_by_rate() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+
+        let mut exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            paths_count: Some(5),
+            ..ExchangeRequest::default()
+        };
+
+        let best_paths = graph_handler.best_paths(&exchange_request).unwrap();
+
+        assert_eq!(2, best_paths.len());
+        assert_eq!(
+            graph_handler.best_rate(&exchange_request),
+            Ok(best_paths[0].clone())
+        );
+        assert_eq!(1000.0, best_paths[1].rate);
+        assert_eq!(
+            vec![
+                ("KRAKEN".to_owned(), "BTC".to_owned()),
+                ("KRAKEN".to_owned(), "USD".to_owned()),
+            ],
+            best_paths[1].path
+        );
+
+        exchange_request.destination_currency = "EUR".into();
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::UnknownDestination)),
+            graph_handler.best_paths(&exchange_request)
+        );
+    }
+
+    #[test]
+    fn it_answers_exchange_request_within_the_hops_and_transfers_limits() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+            PriceUpdate::new(Utc::now(), "GDAX", "USD", "EUR", 0.9, 1.1),
+        ]);
+
+        let mut exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            max_exchange_transfers: Some(0),
+            ..ExchangeRequest::default()
+        };
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1000.0, best_rates.rate);
+        assert_eq!(2, best_rates.path.len());
+
+        exchange_request.destination_exchange = "GDAX".into();
+        exchange_request.destination_currency = "EUR".into();
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::NoPathWithinLimits)),
+            graph_handler.best_rate(&exchange_request)
+        );
+
+        exchange_request.max_exchange_transfers = None;
+        exchange_request.max_hops = Some(3);
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        // KRAKEN BTC -> GDAX BTC -> GDAX USD -> GDAX EUR
+        assert_eq!(4, best_rates.path.len());
+        assert_eq!(("GDAX".to_owned(), "BTC".to_owned()), best_rates.path[1]);
+        assert!((best_rates.rate - 900.9).abs() < 1e-9);
+
+        exchange_request.max_hops = Some(2);
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::NoPathWithinLimits)),
+            graph_handler.best_paths(&ExchangeRequest {
+                paths_count: Some(3),
+                ..exchange_request
+            })
+        );
+    }
+
+    #[test]
+    fn it_answers_exchange_request_with_the_transfer_costs() {
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+        assert_eq!(
+            4,
+            graph_handler
+                .best_rate(&exchange_request)
+                .unwrap()
+                .path
+                .len()
+        );
+
+        // moving the BTC to GDAX now costs more than the better rate there
+        graph_handler
+            .handle_transfer_cost(&TransferCostUpdate {
+                from_exchange: "KRAKEN".to_owned(),
+                to_exchange: "GDAX".to_owned(),
+                currency: "BTC".to_owned(),
+                percent_fee: 0.2,
+                fixed_fee: 0.0,
+            })
+            .unwrap();
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1000.0, best_rates.rate);
+        assert_eq!(2, best_rates.path.len());
+    }
+
+    #[test]
+    fn it_answers_exchange_request_with_the_trading_fees() {
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+
+        graph_handler
+            .handle_trading_fee(&TradingFeeUpdate {
+                exchange: "GDAX".to_owned(),
+                percent_fee: 0.3,
+                pair: None,
+            })
+            .unwrap();
+
+        // the better quote on GDAX is worse after its fee
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1000.0, best_rates.rate);
+        assert_eq!(2, best_rates.path.len());
+
+        graph_handler
+            .handle_trading_fee(&TradingFeeUpdate {
+                exchange: "KRAKEN".to_owned(),
+                percent_fee: 0.5,
+                pair: Some(("USD".to_owned(), "BTC".to_owned())),
+            })
+            .unwrap();
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert!((best_rates.rate - 1001.0 * 0.997).abs() < 1e-9);
+        assert_eq!(4, best_rates.path.len());
+    }
+
+    #[test]
+    fn it_answers_exchange_request_for_an_amount_walking_the_order_books() {
+        let mut graph_handler = GraphHandler::default();
+        let order_book = |exchange: &str, bids: &str, asks: &str| {
+            OrderBookUpdate::from_input(&[
+                OrderBookUpdate::COMMAND_PREFIX,
+                "2017-11-01T09:42:23+00:00",
+                exchange,
+                "BTC",
+                "USD",
+                bids,
+                asks,
+            ])
+            .unwrap()
+        };
+
+        // GDAX has the better price, but only for 1 BTC
+        graph_handler
+            .handle_order_book(order_book("KRAKEN", "1000@10", "1010@10"))
+            .unwrap();
+        graph_handler
+            .handle_order_book(order_book("GDAX", "1001@1,900@10", "1005@10"))
+            .unwrap();
+
+        let mut exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            amount: Some(1.0),
+            ..ExchangeRequest::default()
+        };
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1001.0, best_rates.rate);
+        assert_eq!(4, best_rates.path.len());
+
+        exchange_request.amount = Some(2.0);
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(
+            Some(Amounts {
+                input: 2.0,
+                output: 2000.0
+            }),
+            best_rates.amounts
+        );
+        assert_eq!(2, best_rates.path.len());
+
+        exchange_request.amount = Some(25.0);
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::NoPathForAmount)),
+            graph_handler.best_rate(&exchange_request)
+        );
+
+        exchange_request.paths_count = Some(2);
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::PathsCountForAmount)),
+            graph_handler.best_paths(&exchange_request)
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_order_books_only_when_their_top_is_accepted() {
+        let mut graph_handler = GraphHandler::default();
+        let order_book = |timestamp: &str, bids: &str, asks: &str| {
+            OrderBookUpdate::from_input(&[
+                OrderBookUpdate::COMMAND_PREFIX,
+                timestamp,
+                "KRAKEN",
+                "BTC",
+                "USD",
+                bids,
+                asks,
+            ])
+            .unwrap()
+        };
+        graph_handler
+            .handle_validation_policy(&ValidationPolicyUpdate {
+                policy: ValidationPolicy::Reject,
+            })
+            .unwrap();
+
+        // the bid above the ask makes a profitable round trip
+        let update_report = graph_handler
+            .handle_order_book(order_book(
+                "2017-11-01T09:42:23+00:00",
+                "1020@10",
+                "1010@10",
+            ))
+            .unwrap();
+        assert_eq!(
+            UpdateOutcome::Rejected(ParseCommandError(ParseErrorKind::ProfitableRoundTrip)),
+            update_report.outcome
+        );
+        assert!(graph_handler.order_books.is_empty());
+
+        graph_handler
+            .handle_order_book(order_book(
+                "2017-11-01T09:42:23+00:00",
+                "1000@10",
+                "1010@10",
+            ))
+            .unwrap();
+        // a book older than the quote of the pair
+        let update_report = graph_handler
+            .handle_order_book(order_book("2017-11-01T09:40:00+00:00", "900@10", "910@10"))
+            .unwrap();
+        assert_eq!(UpdateOutcome::IgnoredOlder, update_report.outcome);
+
+        let mut exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            amount: Some(2.0),
+            ..ExchangeRequest::default()
+        };
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert!((best_rates.amounts.unwrap().output - 2000.0).abs() < 1e-9);
+
+        // a newer quote of the pair replaces the levels of the book
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                "2017-11-01T09:45:00+00:00".parse().unwrap(),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1005.0,
+                0.00099,
+            ))
+            .unwrap();
+        assert!(graph_handler.order_books.is_empty());
+        exchange_request.amount = Some(20.0);
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert!((best_rates.amounts.unwrap().output - 20100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_avoids_the_transfers_with_fixed_fees_for_small_amounts() {
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+        graph_handler
+            .handle_withdrawal_fee(&WithdrawalFeeUpdate {
+                exchange: "KRAKEN".to_owned(),
+                currency: "BTC".to_owned(),
+                fee: 0.0005,
+            })
+            .unwrap();
+
+        let mut exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            amount: Some(0.1),
+            ..ExchangeRequest::default()
+        };
+
+        // (0.1 - 0.0005) * 1001 is less than 0.1 * 1000
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(2, best_rates.path.len());
+        assert_eq!(100.0, best_rates.amounts.unwrap().output);
+
+        // (10 - 0.0005) * 1001 is more than 10 * 1000
+        exchange_request.amount = Some(10.0);
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(4, best_rates.path.len());
+        assert!((best_rates.amounts.unwrap().output - 9.9995 * 1001.0).abs() < 1e-9);
+
+        // the fee takes all of the transfer, so the BTC goes through USD instead
+        exchange_request.destination_exchange = "GDAX".into();
+        exchange_request.destination_currency = "BTC".into();
+        exchange_request.amount = Some(0.0004);
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(("KRAKEN".to_owned(), "USD".to_owned()), best_rates.path[1]);
+        assert!((best_rates.rate - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_splits_the_amount_over_the_depth_of_the_order_books() {
+        let mut graph_handler = GraphHandler::default();
+        let order_book = |exchange: &str, bids: &str| {
+            OrderBookUpdate::from_input(&[
+                OrderBookUpdate::COMMAND_PREFIX,
+                "2017-11-01T09:42:23+00:00",
+                exchange,
+                "BTC",
+                "USD",
+                bids,
+                "1100@10",
+            ])
+            .unwrap()
+        };
+        graph_handler
+            .handle_order_book(order_book("KRAKEN", "1000@1,500@10"))
+            .unwrap();
+        graph_handler
+            .handle_order_book(order_book("GDAX", "1001@1,500@10"))
+            .unwrap();
+
+        let mut exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            amount: Some(2.0),
+            split: true,
+            ..ExchangeRequest::default()
+        };
+
+        // a single path only gets 1501 out of the 2 BTC
+        let split_route = graph_handler.split_route(&exchange_request).unwrap();
+        assert!((split_route.amounts.output - 2001.0).abs() < 1e-6);
+        assert_eq!(2, split_route.routes.len());
+
+        let gdax_route = &split_route.routes[0];
+        assert_eq!(4, gdax_route.path.len());
+        assert!((gdax_route.amounts.unwrap().input - 1.0).abs() < 1e-9);
+        assert!((split_route.routes[1].amounts.unwrap().output - 1000.0).abs() < 1e-6);
+
+        // limiting the transfer of the BTC to GDAX
+        graph_handler
+            .handle_edge_limit(&EdgeLimitUpdate {
+                from_exchange: "KRAKEN".into(),
+                from_currency: "BTC".into(),
+                to_exchange: "GDAX".into(),
+                to_currency: "BTC".into(),
+                limit: 0.4,
+            })
+            .unwrap();
+        let split_route = graph_handler.split_route(&exchange_request).unwrap();
+        assert!((split_route.amounts.output - (400.4 + 1000.0 + 300.0)).abs() < 1e-6);
+
+        // the order book of KRAKEN only takes 11 BTC and the transfer 0.4 of them
+        exchange_request.amount = Some(50.0);
+        let split_route = graph_handler.split_route(&exchange_request).unwrap();
+        assert!((split_route.amounts.input - 11.4).abs() < 1e-6);
+        assert!((split_route.amounts.output - (400.4 + 1000.0 + 5000.0)).abs() < 1e-6);
+        assert!((split_route.unfilled - 38.6).abs() < 1e-6);
+
+        // nothing can be sent when no path reaches the destination
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                Utc::now(),
+                "BITFINEX",
+                "ETH",
+                "EUR",
+                300.0,
+                0.003,
+            ))
+            .unwrap();
+        exchange_request.destination_exchange = "BITFINEX".into();
+        exchange_request.destination_currency = "EUR".into();
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::NoPathForAmount)),
+            graph_handler.split_route(&exchange_request)
+        );
+    }
+
+    #[test]
+    fn it_answers_exchange_request_against_the_graph_at_the_timestamp() {
+        let timestamp = |time: &str| {
+            format!("2017-11-01T{}+00:00", time)
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        };
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(
+                timestamp("09:00:00"),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ),
+            PriceUpdate::new(timestamp("09:00:00"), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+            PriceUpdate::new(
+                timestamp("10:30:00"),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1100.0,
+                0.0008,
+            ),
+        ]);
+
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+        assert_eq!(
+            1100.0,
+            graph_handler.best_rate(&exchange_request).unwrap().rate
+        );
+
+        let as_of_handler = graph_handler.as_of(timestamp("10:00:00"));
+        let best_rates = as_of_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1001.0, best_rates.rate);
+        assert_eq!(4, best_rates.path.len());
+        assert_eq!(2, as_of_handler.price_updates.len());
+
+        // there were no quotes yet
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::UnknownSource)),
+            graph_handler
+                .as_of(timestamp("08:00:00"))
+                .best_rate(&exchange_request)
+        );
+    }
+
+    #[test]
+    fn it_leaves_the_stale_edges_out_of_the_searches() {
+        let now = "2017-11-01T10:00:00+00:00"
+            .parse::<DateTime<Utc>>()
+            .unwrap();
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(
+                now - Duration::minutes(5),
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.0,
+                0.0009,
+            ),
+            PriceUpdate::new(
+                now - Duration::hours(1),
+                "GDAX",
+                "BTC",
+                "USD",
+                1001.0,
+                0.0008,
+            ),
+        ]);
+        graph_handler.clock = Box::new(FixedClock(now));
+
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+        assert_eq!(
+            1001.0,
+            graph_handler.best_rate(&exchange_request).unwrap().rate
+        );
+        assert!(graph_handler.stale_edges().edges.is_empty());
+
+        // the GDAX quotes are an hour old
+        graph_handler
+            .handle_max_age(&MaxAgeUpdate {
+                max_age: Duration::minutes(10),
+                exchange: None,
+            })
+            .unwrap();
+
+        let best_rates = graph_handler.best_rate(&exchange_request).unwrap();
+        assert_eq!(1000.0, best_rates.rate);
+        assert_eq!(2, best_rates.path.len());
+
+        let stale_edges = graph_handler.stale_edges().edges;
+        assert_eq!(2, stale_edges.len());
+        assert!(stale_edges.iter().all(|edge| edge.exchange == "GDAX"));
+        assert_eq!(3600, stale_edges[0].age);
+
+        // and the KRAKEN quotes are too old for its own max age
+        graph_handler
+            .handle_max_age(&MaxAgeUpdate {
+                max_age: Duration::minutes(1),
+                exchange: Some("KRAKEN".to_owned()),
+            })
+            .unwrap();
+        assert_eq!(
+            Err(RateRequestError(RateRequestErrorKind::NoPath)),
+            graph_handler.best_rate(&exchange_request)
+        );
+        assert_eq!(4, graph_handler.stale_edges().edges.len());
+    }
+
+    #[test]
+    fn it_lists_the_profitable_cycles_above_the_minimum_profit() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1200.0, 0.0008),
+            PriceUpdate::new(Utc::now(), "GDAX", "USD", "EUR", 0.9, 1.125),
+        ]);
+
+        let cycles = graph_handler.arbitrages(&ArbitrageRequest::default());
+
+        assert_eq!(2, cycles.len());
+        // the most profitable cycle comes first
+        assert!((cycles[0].rate - 1.08).abs() < 1e-9);
+        assert_eq!(
+            vec![
+                ("KRAKEN".to_owned(), "BTC".to_owned()),
+                ("GDAX".to_owned(), "BTC".to_owned()),
+                ("GDAX".to_owned(), "USD".to_owned()),
+                ("KRAKEN".to_owned(), "USD".to_owned()),
+                ("KRAKEN".to_owned(), "BTC".to_owned()),
+            ],
+            cycles[0].path
+        );
+        assert!((cycles[1].rate - 1.0125).abs() < 1e-9);
+
+        let cycles = graph_handler.arbitrages(&ArbitrageRequest {
+            min_profit: 0.05,
+            ..ArbitrageRequest::default()
+        });
+        assert_eq!(1, cycles.len());
+
+        // the cycle through both of the exchanges has 4 edges
+        let cycles = graph_handler.arbitrages(&ArbitrageRequest {
+            max_length: 3,
+            ..ArbitrageRequest::default()
+        });
+        assert_eq!(1, cycles.len());
+        assert!((cycles[0].rate - 1.0125).abs() < 1e-9);
+        assert_eq!(3, cycles[0].max_length);
+    }
+
+    #[test]
+    fn it_returns_the_profitable_cycle_instead_of_a_rate() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1200.0, 0.0008),
+        ]);
+
+        let exchange_request = ExchangeRequest {
+            source_exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "GDAX".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
+        };
+
+        // KRAKEN USD -> KRAKEN BTC -> GDAX BTC -> GDAX USD -> KRAKEN USD: 0.0009 * 1200 > 1
+        match graph_handler.best_rate(&exchange_request) {
+            Err(RateRequestError(RateRequestErrorKind::Arbitrage(cycle))) => {
+                assert_eq!(5, cycle.len());
+                assert_eq!(cycle.first(), cycle.last());
+                assert!(cycle.contains(&("GDAX".to_owned(), "USD".to_owned())));
+                assert!(cycle.contains(&("KRAKEN".to_owned(), "BTC".to_owned())));
+            }
+            result => panic!("Expected an arbitrage, got {:?}", result),
+        }
     }
 }
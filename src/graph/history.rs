@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+
+use crate::command::{PairKey, PriceUpdate};
+use crate::symbol::ExchangeId;
+
+/// Every accepted price update of each pair by its timestamp,
+/// the pairs are keyed by the exchange and their currencies ordered by name.
+/// A removal of the pair is kept as a `None` tombstone at the time of the removal
+#[derive(Debug, Default)]
+pub struct PriceHistory {
+    updates: HashMap<PairKey, BTreeMap<DateTime<Utc>, Option<PriceUpdate>>>,
+}
+
+impl PriceHistory {
+    pub fn insert(&mut self, price_update: &PriceUpdate) {
+        self.updates
+            .entry(price_update.pair_key())
+            .or_default()
+            .insert(price_update.timestamp, Some(price_update.clone()));
+    }
+
+    /// The pair is not in the graph from the timestamp on, until it's updated again
+    pub fn remove(&mut self, pair_key: PairKey, timestamp: DateTime<Utc>) {
+        self.updates
+            .entry(pair_key)
+            .or_default()
+            .insert(timestamp, None);
+    }
+
+    /// Removes all the pairs of the exchange from the timestamp on
+    pub fn remove_exchange(&mut self, exchange: ExchangeId, timestamp: DateTime<Utc>) {
+        for (_, pair_updates) in self
+            .updates
+            .iter_mut()
+            .filter(|(pair_key, _)| pair_key.exchange == exchange)
+        {
+            pair_updates.insert(timestamp, None);
+        }
+    }
+
+    /// The latest update of each pair at the timestamp, the oldest ones first.
+    /// The pairs removed since their latest update are left out
+    pub fn as_of(&self, timestamp: DateTime<Utc>) -> Vec<&PriceUpdate> {
+        let mut price_updates: Vec<&PriceUpdate> = self
+            .updates
+            .values()
+            .filter_map(|pair_updates| pair_updates.range(..=timestamp).next_back())
+            .filter_map(|(_, price_update)| price_update.as_ref())
+            .collect();
+
+        price_updates.sort_by(|left, right| {
+            (left.timestamp, left.pair_key()).cmp(&(right.timestamp, right.pair_key()))
+        });
+
+        price_updates
+    }
+
+    /// How many updates there are for all the pairs, without the removals
+    pub fn len(&self) -> usize {
+        self.updates
+            .values()
+            .flat_map(BTreeMap::values)
+            .filter(|price_update| price_update.is_some())
+            .count()
+    }
+
+    /// Whether there are no updates, the removals alone don't count
+    pub fn is_empty(&self) -> bool {
+        !self
+            .updates
+            .values()
+            .flat_map(BTreeMap::values)
+            .any(Option::is_some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_finds_the_latest_update_of_each_pair_at_the_timestamp() {
+        let timestamp = |time: &str| {
+            format!("2017-11-01T{}+00:00", time)
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        };
+        let mut price_history = PriceHistory::default();
+
+        // a removal is not an update
+        price_history.remove_exchange("KRAKEN".into(), timestamp("08:00:00"));
+        price_history.remove(
+            PriceUpdate::new(timestamp("08:00:00"), "GDAX", "BTC", "USD", 1.0, 1.0).pair_key(),
+            timestamp("08:00:00"),
+        );
+        assert_eq!(0, price_history.len());
+        assert!(price_history.is_empty());
+
+        price_history.insert(&PriceUpdate::new(
+            timestamp("09:00:00"),
+            "KRAKEN",
+            "BTC",
+            "USD",
+            1000.0,
+            0.0009,
+        ));
+        // the reversed pair is the same pair
+        price_history.insert(&PriceUpdate::new(
+            timestamp("10:00:00"),
+            "KRAKEN",
+            "USD",
+            "BTC",
+            0.0008,
+            1100.0,
+        ));
+        price_history.insert(&PriceUpdate::new(
+            timestamp("09:30:00"),
+            "GDAX",
+            "BTC",
+            "USD",
+            1001.0,
+            0.0008,
+        ));
+
+        assert_eq!(3, price_history.len());
+        assert!(price_history.as_of(timestamp("08:59:59")).is_empty());
+
+        let price_updates = price_history.as_of(timestamp("09:59:59"));
+        assert_eq!(2, price_updates.len());
+        assert_eq!(1000.0, price_updates[0].forward_factor);
+        assert_eq!("GDAX", price_updates[1].exchange);
+
+        let price_updates = price_history.as_of(timestamp("10:00:00"));
+        assert_eq!("GDAX", price_updates[0].exchange);
+        assert_eq!(1100.0, price_updates[1].backward_factor);
+
+        // the pair is gone after the removal, until it's updated again
+        price_history.remove_exchange("GDAX".into(), timestamp("10:30:00"));
+        assert_eq!(3, price_history.len());
+        assert_eq!(2, price_history.as_of(timestamp("10:29:59")).len());
+        assert_eq!(1, price_history.as_of(timestamp("10:30:00")).len());
+
+        price_history.insert(&PriceUpdate::new(
+            timestamp("11:00:00"),
+            "GDAX",
+            "USD",
+            "BTC",
+            0.0009,
+            1000.0,
+        ));
+        assert_eq!(2, price_history.as_of(timestamp("11:00:00")).len());
+    }
+}
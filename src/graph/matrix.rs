@@ -0,0 +1,357 @@
+use std::sync::{Barrier, RwLock};
+use std::thread;
+
+use petgraph::graphmap::DiGraphMap;
+
+/// Marks that there is no next hop, i.e. the destination is not reachable
+const NO_NEXT: u32 = u32::MAX;
+
+/// Tolerance for the rounding of the quotes and of multiplying the rates, the quotes have
+/// about 6 significant digits, so a round trip with rates `x` and a rounded `1 / x`
+/// (e.g. `1500` and `0.000666667`) might look profitable. Rates closer than that are equal
+pub const RATE_EPSILON: f64 = 1e-6;
+
+/// The all-pairs best rates and next hops stored in contiguous row-major matrices,
+/// the row is the source node and the column is the destination node.
+/// Rows have a `stride` bigger or equal to the nodes count, so adding nodes doesn't
+/// have to move every row all the time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RatesMatrix {
+    nodes_count: usize,
+    stride: usize,
+    rates: Vec<f64>,
+    next: Vec<u32>,
+}
+
+impl RatesMatrix {
+    /// Every node can only be exchanged to itself with rate of `1.0`
+    pub fn new(nodes_count: usize) -> Self {
+        let mut matrix = Self {
+            nodes_count,
+            stride: nodes_count,
+            rates: vec![0.0; nodes_count * nodes_count],
+            next: vec![NO_NEXT; nodes_count * nodes_count],
+        };
+
+        for node in 0..nodes_count {
+            matrix.set(node, node, 1.0, Some(node));
+        }
+
+        matrix
+    }
+
+    /// The direct rates between the nodes, i.e. the weights of the edges
+    pub fn from_graph(graph: &DiGraphMap<usize, f64>, nodes_count: usize) -> Self {
+        let mut matrix = Self::new(nodes_count);
+
+        for (from_node, to_node, &weight) in graph.all_edges() {
+            matrix.set(from_node, to_node, weight, Some(to_node));
+        }
+
+        matrix
+    }
+
+    pub fn nodes_count(&self) -> usize {
+        self.nodes_count
+    }
+
+    /// The rate from one node to another, `0.0` if the destination can't be reached
+    pub fn rate(&self, from_node: usize, to_node: usize) -> f64 {
+        self.rates[from_node * self.stride + to_node]
+    }
+
+    pub fn next(&self, from_node: usize, to_node: usize) -> Option<usize> {
+        match self.next[from_node * self.stride + to_node] {
+            NO_NEXT => None,
+            next_node => Some(next_node as usize),
+        }
+    }
+
+    pub fn set(&mut self, from_node: usize, to_node: usize, rate: f64, next: Option<usize>) {
+        let index = from_node * self.stride + to_node;
+
+        self.rates[index] = rate;
+        self.next[index] = next.map_or(NO_NEXT, |next_node| next_node as u32);
+    }
+
+    /// The best rates from the node to all the other nodes
+    pub fn row(&self, from_node: usize) -> &[f64] {
+        let start = from_node * self.stride;
+
+        &self.rates[start..start + self.nodes_count]
+    }
+
+    /// Adds a node which can only be exchanged to itself
+    pub fn add_node(&mut self) -> usize {
+        let node = self.nodes_count;
+
+        if node == self.stride {
+            self.grow((self.stride * 2).max(1));
+        }
+
+        self.nodes_count += 1;
+        for other_node in 0..node {
+            self.set(other_node, node, 0.0, None);
+            self.set(node, other_node, 0.0, None);
+        }
+        self.set(node, node, 1.0, Some(node));
+
+        node
+    }
+
+    fn grow(&mut self, stride: usize) {
+        let mut rates = vec![0.0; stride * stride];
+        let mut next = vec![NO_NEXT; stride * stride];
+
+        for row in 0..self.nodes_count {
+            let (old_start, new_start) = (row * self.stride, row * stride);
+
+            rates[new_start..new_start + self.nodes_count]
+                .copy_from_slice(&self.rates[old_start..old_start + self.nodes_count]);
+            next[new_start..new_start + self.nodes_count]
+                .copy_from_slice(&self.next[old_start..old_start + self.nodes_count]);
+        }
+
+        self.stride = stride;
+        self.rates = rates;
+        self.next = next;
+    }
+
+    /// Floyd-Warshall maximizing the product of the rates.
+    /// The loops are in `k-i-j` order, so the innermost one goes over two contiguous rows
+    pub fn floyd_warshall(&mut self) {
+        let (nodes_count, stride) = (self.nodes_count, self.stride);
+        let mut k_rates = vec![0.0; nodes_count];
+
+        for k in 0..nodes_count {
+            k_rates.copy_from_slice(self.row(k));
+
+            for i in 0..nodes_count {
+                let i_start = i * stride;
+                let (i_rates, i_next) = (
+                    &mut self.rates[i_start..i_start + nodes_count],
+                    &mut self.next[i_start..i_start + nodes_count],
+                );
+
+                relax_row(i_rates, i_next, k, &k_rates);
+            }
+        }
+    }
+
+    /// The same as `floyd_warshall`, but the rows are split in bands, one for each thread.
+    /// Every thread relaxes its own rows and they all wait for each other before the next `k`,
+    /// since each row is relaxed in the same way the result is identical to the single-threaded one
+    pub fn floyd_warshall_parallel(&mut self, threads: usize) {
+        let (nodes_count, stride) = (self.nodes_count, self.stride);
+        if threads <= 1 || nodes_count <= 1 {
+            return self.floyd_warshall();
+        }
+
+        let rows_per_band = nodes_count.div_ceil(threads);
+        let bands = nodes_count.div_ceil(rows_per_band);
+        let barrier = Barrier::new(bands);
+        // row `k` is published by the thread owning it, the buffers alternate between the
+        // even and odd `k`, so the next row can be published while the current one is read
+        let k_rows = [
+            RwLock::new(vec![0.0; nodes_count]),
+            RwLock::new(vec![0.0; nodes_count]),
+        ];
+
+        let band_len = rows_per_band * stride;
+        let rates_bands = self.rates.chunks_mut(band_len);
+        let next_bands = self.next.chunks_mut(band_len);
+
+        thread::scope(|scope| {
+            for (band, (rates_band, next_band)) in
+                rates_bands.zip(next_bands).take(bands).enumerate()
+            {
+                let (barrier, k_rows) = (&barrier, &k_rows);
+
+                scope.spawn(move || {
+                    let first_row = band * rows_per_band;
+                    let rows = rows_per_band.min(nodes_count - first_row);
+                    let mut k_rates = vec![0.0; nodes_count];
+
+                    for k in 0..nodes_count {
+                        let k_row = &k_rows[k % 2];
+
+                        if k >= first_row && k < first_row + rows {
+                            let start = (k - first_row) * stride;
+                            k_row
+                                .write()
+                                .unwrap()
+                                .copy_from_slice(&rates_band[start..start + nodes_count]);
+                        }
+                        barrier.wait();
+
+                        k_rates.copy_from_slice(&k_row.read().unwrap());
+
+                        for row in 0..rows {
+                            let start = row * stride;
+
+                            relax_row(
+                                &mut rates_band[start..start + nodes_count],
+                                &mut next_band[start..start + nodes_count],
+                                k,
+                                &k_rates,
+                            );
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Follows the next hops from one node to another
+    pub fn path(&self, from_node: usize, to_node: usize) -> Option<Vec<usize>> {
+        self.next(from_node, to_node)?;
+
+        let mut path = vec![from_node];
+
+        let mut source_node = from_node;
+        while source_node != to_node {
+            // a simple path can't be longer than the nodes count,
+            // a longer one means we are going around a profitable cycle
+            if path.len() > self.nodes_count {
+                return None;
+            }
+
+            source_node = self.next(source_node, to_node)?;
+            path.push(source_node);
+        }
+
+        Some(path)
+    }
+}
+
+/// Improves the rates of row `i` going through node `k`
+fn relax_row(i_rates: &mut [f64], i_next: &mut [u32], k: usize, k_rates: &[f64]) {
+    let (i_k_rate, i_k_next) = (i_rates[k], i_next[k]);
+
+    // nothing can be improved through a node which is not reachable
+    if i_k_rate == 0.0 {
+        return;
+    }
+
+    for ((i_j_rate, i_j_next), &k_j_rate) in i_rates.iter_mut().zip(i_next.iter_mut()).zip(k_rates)
+    {
+        let mul_rate = i_k_rate * k_j_rate;
+
+        if *i_j_rate * (1.0 + RATE_EPSILON) < mul_rate {
+            *i_j_rate = mul_rate;
+            *i_j_next = i_k_next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_creates_the_matrix_from_the_graph_edges() {
+        let mut graph = DiGraphMap::new();
+        graph.add_edge(0, 1, 2.0);
+        graph.add_edge(1, 2, 3.0);
+
+        let matrix = RatesMatrix::from_graph(&graph, 3);
+
+        assert_eq!(3, matrix.nodes_count());
+        assert_eq!(1.0, matrix.rate(0, 0));
+        assert_eq!(2.0, matrix.rate(0, 1));
+        assert_eq!(0.0, matrix.rate(0, 2));
+        assert_eq!(Some(1), matrix.next(0, 1));
+        assert_eq!(None, matrix.next(0, 2));
+        assert_eq!(&[0.0, 1.0, 3.0], matrix.row(1));
+    }
+
+    #[test]
+    fn it_finds_the_best_rates_and_paths() {
+        let mut graph = DiGraphMap::new();
+        graph.add_edge(0, 1, 2.0);
+        graph.add_edge(1, 2, 3.0);
+        graph.add_edge(0, 2, 5.0);
+        graph.add_edge(2, 3, 0.5);
+
+        let mut matrix = RatesMatrix::from_graph(&graph, 4);
+        matrix.floyd_warshall();
+
+        assert_eq!(6.0, matrix.rate(0, 2));
+        assert_eq!(3.0, matrix.rate(0, 3));
+        assert_eq!(Some(vec![0, 1, 2, 3]), matrix.path(0, 3));
+        assert_eq!(Some(vec![3]), matrix.path(3, 3));
+        assert_eq!(None, matrix.path(3, 0));
+    }
+
+    /// A dense graph with pseudo-random rates close to `1.0`, without profitable cycles
+    fn pseudo_random_graph(nodes_count: usize) -> DiGraphMap<usize, f64> {
+        let mut graph = DiGraphMap::new();
+        let mut seed: u64 = 42;
+
+        for from_node in 0..nodes_count {
+            for to_node in 0..nodes_count {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+
+                if from_node != to_node && seed.is_multiple_of(3) {
+                    let rate = 0.5 + (seed >> 33) as f64 / (1_u64 << 32) as f64 * 0.49;
+                    graph.add_edge(from_node, to_node, rate);
+                }
+            }
+        }
+
+        graph
+    }
+
+    #[test]
+    fn it_solves_in_parallel_identically_to_single_threaded() {
+        let nodes_count = 37;
+        let graph = pseudo_random_graph(nodes_count);
+
+        let mut expected = RatesMatrix::from_graph(&graph, nodes_count);
+        expected.floyd_warshall();
+
+        for threads in 1..=8 {
+            let mut matrix = RatesMatrix::from_graph(&graph, nodes_count);
+            matrix.floyd_warshall_parallel(threads);
+
+            assert_eq!(
+                expected, matrix,
+                "Different result with {} threads",
+                threads
+            );
+        }
+
+        // and with rows that have a stride bigger than the nodes count
+        let mut matrix = RatesMatrix::from_graph(&pseudo_random_graph(20), 20);
+        let node = matrix.add_node();
+        matrix.set(node, 0, 0.9, Some(0));
+        matrix.set(5, node, 0.9, Some(node));
+        let mut expected = matrix.clone();
+
+        expected.floyd_warshall();
+        matrix.floyd_warshall_parallel(4);
+        assert_eq!(expected, matrix);
+    }
+
+    #[test]
+    fn it_keeps_the_rates_when_adding_nodes() {
+        let mut graph = DiGraphMap::new();
+        graph.add_edge(0, 1, 2.0);
+        graph.add_edge(1, 0, 0.4);
+
+        let mut matrix = RatesMatrix::from_graph(&graph, 2);
+
+        // growing the stride twice
+        for node in 2..5 {
+            assert_eq!(node, matrix.add_node());
+        }
+
+        assert_eq!(5, matrix.nodes_count());
+        assert_eq!(2.0, matrix.rate(0, 1));
+        assert_eq!(0.4, matrix.rate(1, 0));
+        assert_eq!(Some(0), matrix.next(1, 0));
+        assert_eq!(1.0, matrix.rate(4, 4));
+        assert_eq!(0.0, matrix.rate(0, 4));
+        assert_eq!(None, matrix.next(4, 0));
+    }
+}
@@ -0,0 +1,434 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::command::{
+    EdgeLimitUpdate, MaxAgeUpdate, OrderBookUpdate, PriceUpdate, TradingFeeUpdate,
+    TransferCostUpdate, ValidationPolicyUpdate, WithdrawalFeeUpdate,
+};
+use crate::graph::wal::{WalError, WalErrorKind};
+use crate::parse::validation::ValidationPolicy;
+use crate::symbol::{CurrencyId, ExchangeId};
+use crate::{parse_line, ParsedLine};
+
+/// The first line of a snapshot is `SNAPSHOT_VERSION <version>`
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// The snapshots of this version don't have the order books
+const SNAPSHOT_VERSION_WITHOUT_BOOKS: u32 = 2;
+
+/// The snapshots of this version don't have a log sequence either
+const SNAPSHOT_VERSION_WITHOUT_LOG: u32 = 1;
+
+const VERSION_PREFIX: &str = "SNAPSHOT_VERSION";
+const LOG_SEQUENCE_PREFIX: &str = "LOG_SEQUENCE";
+const NODES_PREFIX: &str = "NODES";
+const NODE_PREFIX: &str = "NODE";
+
+/// A log record with a whole snapshot starts with it, its lines follow separated by tabs
+const STATE_RECORD_PREFIX: &str = "SNAPSHOT_STATE";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotErrorKind {
+    Io(io::ErrorKind),
+    /// The snapshot doesn't start with a version this build can read
+    Version,
+    /// The line with this number (counted from 1) is not part of a snapshot
+    InvalidLine(usize),
+    /// The loaded state couldn't be appended to the log
+    Log(WalErrorKind),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotError(pub SnapshotErrorKind);
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            SnapshotErrorKind::Io(kind) => write!(f, "Snapshot file error: {:?}", kind),
+            SnapshotErrorKind::Version => "Unsupported snapshot version".fmt(f),
+            SnapshotErrorKind::InvalidLine(line) => write!(f, "Invalid snapshot line {}", line),
+            SnapshotErrorKind::Log(kind) => WalError(kind.clone()).fmt(f),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError(SnapshotErrorKind::Io(error.kind()))
+    }
+}
+
+impl From<WalError> for SnapshotError {
+    fn from(error: WalError) -> Self {
+        SnapshotError(SnapshotErrorKind::Log(error.0))
+    }
+}
+
+/// The market state of a `GraphHandler`: the nodes at their indices, the latest update
+/// and order book of each pair and the configuration. Apart from the nodes, every line is a command
+/// of the tool, so a snapshot reads like a configuration file
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// The (exchange, currency) of each node index, `None` for the free indices
+    pub nodes: Vec<Option<(ExchangeId, CurrencyId)>>,
+    pub price_updates: Vec<PriceUpdate>,
+    /// The books of the pairs, their tops are the price updates of the pairs
+    pub order_books: Vec<OrderBookUpdate>,
+    pub transfer_costs: Vec<TransferCostUpdate>,
+    pub trading_fees: Vec<TradingFeeUpdate>,
+    pub withdrawal_fees: Vec<WithdrawalFeeUpdate>,
+    pub edge_limits: Vec<EdgeLimitUpdate>,
+    pub max_ages: Vec<MaxAgeUpdate>,
+    pub validation_policy: ValidationPolicy,
+    /// The sequence of the last log record in the state, 0 if none
+    pub log_sequence: u64,
+}
+
+impl Snapshot {
+    /// Writes the snapshot next to the file and renames it over the file once it's on the
+    /// disk, so a crash while saving leaves the previous snapshot as it was
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut temp_name = file_name.to_owned();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, path)?;
+        sync_directory(path)?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{} {}", VERSION_PREFIX, SNAPSHOT_VERSION)?;
+        writeln!(writer, "{} {}", LOG_SEQUENCE_PREFIX, self.log_sequence)?;
+
+        for transfer_cost in &self.transfer_costs {
+            writeln!(writer, "{}", transfer_cost)?;
+        }
+        for trading_fee in &self.trading_fees {
+            writeln!(writer, "{}", trading_fee)?;
+        }
+        for withdrawal_fee in &self.withdrawal_fees {
+            writeln!(writer, "{}", withdrawal_fee)?;
+        }
+        for edge_limit in &self.edge_limits {
+            writeln!(writer, "{}", edge_limit)?;
+        }
+        for max_age in &self.max_ages {
+            writeln!(writer, "{}", max_age)?;
+        }
+
+        writeln!(writer, "{} {}", NODES_PREFIX, self.nodes.len())?;
+        for (node, key) in self.nodes.iter().enumerate() {
+            if let Some((exchange, currency)) = key {
+                writeln!(writer, "{} {} {} {}", NODE_PREFIX, node, exchange, currency)?;
+            }
+        }
+
+        for price_update in &self.price_updates {
+            writeln!(writer, "{}", price_update)?;
+        }
+        for order_book in &self.order_books {
+            writeln!(writer, "{}", order_book)?;
+        }
+
+        // the last one, so the updates are restored as they were accepted
+        let validation_policy_update = ValidationPolicyUpdate {
+            policy: self.validation_policy,
+        };
+        writeln!(writer, "{}", validation_policy_update)
+    }
+
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, SnapshotError> {
+        let mut lines = reader.lines();
+
+        let version_line = lines
+            .next()
+            .ok_or(SnapshotError(SnapshotErrorKind::Version))??;
+        let versions = [
+            SNAPSHOT_VERSION,
+            SNAPSHOT_VERSION_WITHOUT_BOOKS,
+            SNAPSHOT_VERSION_WITHOUT_LOG,
+        ];
+        if !versions
+            .iter()
+            .any(|version| version_line.trim() == format!("{} {}", VERSION_PREFIX, version))
+        {
+            return Err(SnapshotError(SnapshotErrorKind::Version));
+        }
+
+        let mut snapshot = Self::default();
+        for (line_index, line) in lines.enumerate() {
+            let line = line?;
+            // the version is the first line
+            let invalid_line = SnapshotError(SnapshotErrorKind::InvalidLine(line_index + 2));
+            let input: Vec<&str> = line.split_whitespace().collect();
+
+            match input.as_slice() {
+                [] => {}
+                [LOG_SEQUENCE_PREFIX, sequence] => {
+                    snapshot.log_sequence = sequence.parse().map_err(|_| invalid_line.clone())?;
+                }
+                [NODES_PREFIX, count] => {
+                    let count: usize = count.parse().map_err(|_| invalid_line.clone())?;
+                    snapshot.nodes = vec![None; count];
+                }
+                [NODE_PREFIX, node, exchange, currency] => {
+                    let node: usize = node.parse().map_err(|_| invalid_line.clone())?;
+                    match snapshot.nodes.get_mut(node) {
+                        Some(key @ None) => {
+                            *key = Some((ExchangeId::new(exchange), CurrencyId::new(currency)))
+                        }
+                        _ => return Err(invalid_line),
+                    }
+                }
+                _ => match parse_line(&line).map_err(|_| invalid_line.clone())? {
+                    ParsedLine::PriceUpdate(price_update) => {
+                        snapshot.price_updates.push(price_update)
+                    }
+                    ParsedLine::OrderBookUpdate(order_book) => {
+                        snapshot.order_books.push(order_book)
+                    }
+                    ParsedLine::TransferCostUpdate(transfer_cost) => {
+                        snapshot.transfer_costs.push(transfer_cost)
+                    }
+                    ParsedLine::TradingFeeUpdate(trading_fee) => {
+                        snapshot.trading_fees.push(trading_fee)
+                    }
+                    ParsedLine::WithdrawalFeeUpdate(withdrawal_fee) => {
+                        snapshot.withdrawal_fees.push(withdrawal_fee)
+                    }
+                    ParsedLine::EdgeLimitUpdate(edge_limit) => {
+                        snapshot.edge_limits.push(edge_limit)
+                    }
+                    ParsedLine::MaxAgeUpdate(max_age) => snapshot.max_ages.push(max_age),
+                    ParsedLine::ValidationPolicyUpdate(validation_policy) => {
+                        snapshot.validation_policy = validation_policy.policy
+                    }
+                    _ => return Err(invalid_line),
+                },
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    /// The snapshot as a single log record, so replaying the log doesn't need the file.
+    /// No token of a line has whitespace in it, so the lines are separated by tabs
+    pub fn to_record(&self) -> String {
+        let mut contents = Vec::new();
+        self.write_to(&mut contents)
+            .expect("Writing to memory doesn't fail");
+
+        format!(
+            "{}\t{}",
+            STATE_RECORD_PREFIX,
+            String::from_utf8_lossy(&contents)
+                .trim_end()
+                .replace('\n', "\t")
+        )
+    }
+
+    /// Reads back a record of `to_record`, `None` if the record is not a snapshot
+    pub fn from_record(record: &str) -> Option<Result<Self, SnapshotError>> {
+        let contents = record
+            .strip_prefix(STATE_RECORD_PREFIX)?
+            .strip_prefix('\t')?
+            .replace('\t', "\n");
+
+        Some(Self::read_from(contents.as_bytes()))
+    }
+}
+
+/// Syncs the directory of the file, so its entry survives a crash as well
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(directory)?.sync_all()
+}
+
+/// The directories can't be opened for syncing on the other platforms
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::*;
+
+    #[test]
+    fn it_reads_back_the_snapshot_it_writes() {
+        let timestamp = "2017-11-01T09:42:23.5+00:00"
+            .parse::<DateTime<Utc>>()
+            .unwrap();
+        let snapshot = Snapshot {
+            nodes: vec![
+                Some(("KRAKEN".into(), "BTC".into())),
+                None,
+                Some(("KRAKEN".into(), "USD".into())),
+                None,
+            ],
+            price_updates: vec![PriceUpdate::new(
+                timestamp,
+                "KRAKEN",
+                "BTC",
+                "USD",
+                1000.1,
+                0.000_999_9,
+            )],
+            order_books: vec![OrderBookUpdate::from_input(&[
+                OrderBookUpdate::COMMAND_PREFIX,
+                "2017-11-01T09:42:23.5+00:00",
+                "KRAKEN",
+                "BTC",
+                "USD",
+                "1000.1@1,999@2.5",
+                "1000.2@0.5",
+            ])
+            .unwrap()],
+            transfer_costs: vec![TransferCostUpdate {
+                from_exchange: "KRAKEN".to_owned(),
+                to_exchange: "GDAX".to_owned(),
+                currency: "BTC".to_owned(),
+                percent_fee: 0.1,
+                fixed_fee: 0.0005,
+            }],
+            trading_fees: vec![
+                TradingFeeUpdate {
+                    exchange: "KRAKEN".to_owned(),
+                    percent_fee: 0.26,
+                    pair: None,
+                },
+                TradingFeeUpdate {
+                    exchange: "KRAKEN".to_owned(),
+                    percent_fee: 0.16,
+                    pair: Some(("BTC".to_owned(), "USD".to_owned())),
+                },
+            ],
+            withdrawal_fees: vec![WithdrawalFeeUpdate {
+                exchange: "KRAKEN".to_owned(),
+                currency: "BTC".to_owned(),
+                fee: 0.0005,
+            }],
+            edge_limits: vec![EdgeLimitUpdate {
+                from_exchange: "KRAKEN".into(),
+                from_currency: "BTC".into(),
+                to_exchange: "KRAKEN".into(),
+                to_currency: "USD".into(),
+                limit: 2.5,
+            }],
+            max_ages: vec![
+                MaxAgeUpdate {
+                    max_age: Duration::seconds(60),
+                    exchange: None,
+                },
+                MaxAgeUpdate {
+                    max_age: Duration::seconds(300),
+                    exchange: Some("KRAKEN".to_owned()),
+                },
+            ],
+            validation_policy: ValidationPolicy::Clamp,
+            log_sequence: 42,
+        };
+
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes).unwrap();
+
+        let read_snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+        assert_eq!(snapshot, read_snapshot);
+        // `PriceUpdate` equality is only about the pair
+        assert_eq!(
+            (timestamp, 1000.1, 0.000_999_9),
+            (
+                read_snapshot.price_updates[0].timestamp,
+                read_snapshot.price_updates[0].forward_factor,
+                read_snapshot.price_updates[0].backward_factor
+            )
+        );
+    }
+
+    #[test]
+    fn saving_replaces_the_previous_snapshot_as_a_whole() {
+        let path = std::env::temp_dir().join(format!(
+            "exchange_rate_path_save_{}.snapshot",
+            std::process::id()
+        ));
+        let snapshot = |log_sequence| Snapshot {
+            log_sequence,
+            ..Snapshot::default()
+        };
+
+        snapshot(1).save(&path).unwrap();
+        snapshot(2).save(&path).unwrap();
+
+        assert_eq!(Ok(snapshot(2)), Snapshot::load(&path));
+        assert!(!path.with_extension("snapshot.tmp").exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn it_reads_back_the_record_it_writes() {
+        let snapshot = Snapshot {
+            nodes: vec![
+                Some((ExchangeId::new("KRAKEN"), CurrencyId::new("BTC"))),
+                None,
+            ],
+            max_ages: vec![MaxAgeUpdate {
+                exchange: None,
+                max_age: Duration::seconds(60),
+            }],
+            log_sequence: 3,
+            ..Snapshot::default()
+        };
+
+        let record = snapshot.to_record();
+        assert!(!record.contains('\n'));
+        assert_eq!(Some(Ok(snapshot)), Snapshot::from_record(&record));
+        assert_eq!(None, Snapshot::from_record("MAX_AGE 60"));
+    }
+
+    #[test]
+    fn it_rejects_other_versions_and_invalid_lines() {
+        let read = |snapshot: &str| Snapshot::read_from(snapshot.as_bytes()).map(|_| ());
+
+        assert_eq!(Ok(()), read("SNAPSHOT_VERSION 1\n"));
+        assert_eq!(Ok(()), read("SNAPSHOT_VERSION 2\nLOG_SEQUENCE 7\n"));
+        assert_eq!(Ok(()), read("SNAPSHOT_VERSION 3\nLOG_SEQUENCE 7\n"));
+        assert_eq!(
+            Err(SnapshotError(SnapshotErrorKind::Version)),
+            read("SNAPSHOT_VERSION 4\n")
+        );
+        assert_eq!(Err(SnapshotError(SnapshotErrorKind::Version)), read(""));
+        assert_eq!(
+            Err(SnapshotError(SnapshotErrorKind::InvalidLine(3))),
+            read("SNAPSHOT_VERSION 1\nNODES 2\nNODE 2 KRAKEN BTC\n")
+        );
+        assert_eq!(
+            Err(SnapshotError(SnapshotErrorKind::InvalidLine(2))),
+            read("SNAPSHOT_VERSION 1\nEXCHANGE_RATE_REQUEST KRAKEN BTC GDAX USD\n")
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::hash_map::Iter;
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::symbol::ExchangeId;
+
+/// Where "now" comes from, so the staleness of the quotes can be checked deterministically
+pub trait Clock: Debug {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The current time of the system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always the same time, for replays and tests
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// The age after which the quotes are stale, optionally overridden for an exchange.
+/// Without any max age the quotes never get stale
+#[derive(Debug, Clone, Default)]
+pub struct MaxAges {
+    max_age: Option<Duration>,
+    exchange_max_ages: HashMap<ExchangeId, Duration>,
+}
+
+impl MaxAges {
+    pub fn get(&self, exchange: ExchangeId) -> Option<Duration> {
+        self.exchange_max_ages
+            .get(&exchange)
+            .cloned()
+            .or(self.max_age)
+    }
+
+    /// The max age of the exchanges without their own
+    pub fn global(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    pub fn exchange_max_ages(&self) -> Iter<'_, ExchangeId, Duration> {
+        self.exchange_max_ages.iter()
+    }
+
+    /// Sets the max age of the exchange or the global one
+    pub fn set(&mut self, exchange: Option<ExchangeId>, max_age: Duration) {
+        match exchange {
+            Some(exchange) => {
+                self.exchange_max_ages.insert(exchange, max_age);
+            }
+            None => self.max_age = Some(max_age),
+        }
+    }
+
+    pub fn is_stale(
+        &self,
+        exchange: ExchangeId,
+        timestamp: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.get(exchange)
+            .is_some_and(|max_age| now.signed_duration_since(timestamp) > max_age)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exchange_max_ages_override_the_global_one() {
+        let now = "2017-11-01T10:00:00+00:00"
+            .parse::<DateTime<Utc>>()
+            .unwrap();
+        let timestamp = now - Duration::seconds(120);
+        let (kraken, gdax) = (ExchangeId::new("KRAKEN"), ExchangeId::new("GDAX"));
+        let mut max_ages = MaxAges::default();
+
+        assert!(!max_ages.is_stale(kraken, timestamp, now));
+
+        max_ages.set(None, Duration::seconds(60));
+        assert!(max_ages.is_stale(kraken, timestamp, now));
+        assert!(!max_ages.is_stale(kraken, now - Duration::seconds(60), now));
+
+        max_ages.set(Some(kraken), Duration::seconds(300));
+        assert_eq!(Some(Duration::seconds(300)), max_ages.get(kraken));
+        assert!(!max_ages.is_stale(kraken, timestamp, now));
+        assert!(max_ages.is_stale(gdax, timestamp, now));
+    }
+}
@@ -1,133 +1,1170 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::thread;
 
-use petgraph::graphmap::AllEdges;
 use petgraph::graphmap::DiGraphMap;
-use petgraph::Directed;
 
 use crate::graph::handler::GraphHandler;
+use crate::graph::matrix::{RatesMatrix, RATE_EPSILON};
 
-struct Exchanger {}
+/// Relative tolerance for the rounding errors of splitting an amount
+const SPLIT_EPSILON: f64 = 1e-12;
 
-type RatesHashMap = HashMap<usize, HashMap<usize, f64>>;
-type NextHashMap = HashMap<usize, HashMap<usize, Option<usize>>>;
+/// How many times an interval is halved when bisecting for an amount
+const BISECTION_ROUNDS: usize = 64;
+
+pub struct Exchanger {}
 
 impl Exchanger {
-    fn initialize_rates(edges: AllEdges<usize, f64, Directed>) -> RatesHashMap {
-        edges.fold(HashMap::new(), |mut acc, (from_node, to_node, _weight)| {
-            let mut dest_hash_map = HashMap::new();
-            dest_hash_map.insert(to_node, 0.0);
+    pub fn best_rates(graph_handler: &GraphHandler) -> RatesMatrix {
+        let exchange_graph = &graph_handler.exchange_graph;
 
-            acc.insert(from_node, dest_hash_map);
-            acc
-        })
+        Self::floyd_warshall(
+            &exchange_graph.graph,
+            exchange_graph.index_map.index_bound(),
+        )
     }
 
-    fn initialize_next(edges: AllEdges<usize, f64, Directed>) -> NextHashMap {
-        edges.fold(HashMap::new(), |mut acc, (from_node, to_node, _weight)| {
-            let mut dest_hash_map = HashMap::new();
-            dest_hash_map.insert(to_node, None);
+    pub fn floyd_warshall(graph: &DiGraphMap<usize, f64>, nodes_count: usize) -> RatesMatrix {
+        let mut rates = RatesMatrix::from_graph(graph, nodes_count);
+        rates.floyd_warshall();
 
-            acc.insert(from_node, dest_hash_map);
-            acc
-        })
+        rates
     }
 
-    fn new_init_rates_next(
-        nodes: &[usize],
+    /// Splits the rows of each `k` iteration between the `threads`,
+    /// the result is identical to the single-threaded `floyd_warshall`
+    pub fn floyd_warshall_parallel(
         graph: &DiGraphMap<usize, f64>,
-    ) -> (RatesHashMap, NextHashMap) {
-        let (mut rates, mut next) = (0..nodes.len()).fold(
-            (RatesHashMap::new(), NextHashMap::new()),
-            |(mut rates, mut nexts), node| {
-                let (rate_init, next_init) = (0..nodes.len()).fold(
-                    (HashMap::new(), HashMap::new()),
-                    |(mut rate_acc, mut next_acc), dest_node| {
-                        rate_acc.insert(dest_node, 0.0);
-                        next_acc.insert(dest_node, None);
-
-                        (rate_acc, next_acc)
-                    },
-                );
-                rates.insert(node, rate_init);
-                nexts.insert(node, next_init);
-                (rates, nexts)
+        nodes_count: usize,
+        threads: usize,
+    ) -> RatesMatrix {
+        let mut rates = RatesMatrix::from_graph(graph, nodes_count);
+        rates.floyd_warshall_parallel(threads);
+
+        rates
+    }
+
+    /// The weight of an edge in the log space, where multiplying rates becomes
+    /// summing weights and the best rate is the shortest path
+    pub fn log_weight(rate: f64) -> f64 {
+        -rate.ln()
+    }
+
+    /// Bellman-Ford over the `-ln(rate)` weights of the graph starting from `source`.
+    /// If a negative cycle (i.e. a profitable one) is reachable from the source
+    /// the best rates are unbounded and the cycle is returned as `Arbitrage`
+    pub fn log_best_paths(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        source: usize,
+    ) -> Result<LogPaths, Arbitrage> {
+        Self::log_best_paths_filtered(graph, nodes_count, source, |_, _| true)
+    }
+
+    /// The same as `log_best_paths`, but only over the edges for which `use_edge` returns `true`
+    pub fn log_best_paths_filtered<F>(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        source: usize,
+        use_edge: F,
+    ) -> Result<LogPaths, Arbitrage>
+    where
+        F: Fn(usize, usize) -> bool,
+    {
+        let edges: Vec<(usize, usize, f64)> = graph
+            .all_edges()
+            .filter(|&(from_node, to_node, _)| use_edge(from_node, to_node))
+            .map(|(from_node, to_node, &rate)| (from_node, to_node, rate))
+            .collect();
+
+        let mut distances = vec![f64::INFINITY; nodes_count];
+        let mut predecessors = vec![None; nodes_count];
+        distances[source] = 0.0;
+
+        let relax = |distances: &[f64], from_node: usize, to_node: usize, rate: f64| {
+            let distance = distances[from_node] + Self::log_weight(rate);
+
+            if distance < distances[to_node] - RATE_EPSILON {
+                Some(distance)
+            } else {
+                None
+            }
+        };
+
+        for _ in 1..nodes_count {
+            let mut relaxed = false;
+
+            for &(from_node, to_node, rate) in &edges {
+                if let Some(distance) = relax(&distances, from_node, to_node, rate) {
+                    distances[to_node] = distance;
+                    predecessors[to_node] = Some(from_node);
+                    relaxed = true;
+                }
+            }
+
+            if !relaxed {
+                break;
+            }
+        }
+
+        // one more round, if anything is still relaxed there is a negative cycle
+        let mut cycle_node = None;
+        for &(from_node, to_node, rate) in &edges {
+            if let Some(distance) = relax(&distances, from_node, to_node, rate) {
+                distances[to_node] = distance;
+                predecessors[to_node] = Some(from_node);
+                cycle_node = Some(to_node);
+            }
+        }
+
+        match cycle_node {
+            Some(node) => Err(Arbitrage::from_predecessors(node, &predecessors)),
+            None => Ok(LogPaths {
+                source,
+                distances,
+                predecessors,
+            }),
+        }
+    }
+
+    /// Yen's algorithm for the `paths_count` best loopless paths, ranked by their rate.
+    /// Each next path deviates from one of the already found ones at a spur node,
+    /// the spur paths are searched with Bellman-Ford since the log weights can be negative
+    pub fn k_best_paths(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        source: usize,
+        destination: usize,
+        paths_count: usize,
+    ) -> Result<Vec<Vec<usize>>, Arbitrage> {
+        let best_path = Self::log_best_paths(graph, nodes_count, source)?.path(destination);
+
+        Ok(Self::yen_paths(
+            graph,
+            best_path,
+            paths_count,
+            |root, use_edge| {
+                let spur_node = *root.last().expect("The root has at least the spur node");
+
+                Self::log_best_paths_filtered(graph, nodes_count, spur_node, use_edge)
+                    .ok()?
+                    .path(destination)
             },
+        ))
+    }
+
+    /// The same as `k_best_paths`, but only the paths within the `limits` are returned.
+    /// The spur paths are searched with the limits left after their root
+    pub fn k_best_limited_paths<T>(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        source: usize,
+        destination: usize,
+        paths_count: usize,
+        limits: PathLimits,
+        is_transfer: T,
+    ) -> Vec<Vec<usize>>
+    where
+        T: Fn(usize, usize) -> bool,
+    {
+        let best_path = Self::limited_best_path(
+            graph,
+            nodes_count,
+            source,
+            destination,
+            limits,
+            &is_transfer,
+            |_, _| true,
         );
 
-        for (node_1, node_2, &weight) in graph.all_edges() {
-            let rate_entry = rates.entry(node_1).or_insert(HashMap::new());
-            rate_entry.insert(node_2, weight);
+        Self::yen_paths(graph, best_path, paths_count, |root, use_edge| {
+            let spur_node = *root.last().expect("The root has at least the spur node");
+            let root_transfers = root
+                .windows(2)
+                .filter(|nodes| is_transfer(nodes[0], nodes[1]))
+                .count();
+            let spur_limits = PathLimits {
+                max_hops: limits.max_hops.map(|max_hops| max_hops - (root.len() - 1)),
+                max_transfers: limits
+                    .max_transfers
+                    .map(|max_transfers| max_transfers - root_transfers),
+            };
+
+            Self::limited_best_path(
+                graph,
+                nodes_count,
+                spur_node,
+                destination,
+                spur_limits,
+                &is_transfer,
+                use_edge,
+            )
+        })
+    }
+
+    /// Each next path deviates from one of the already found ones at a spur node.
+    /// `spur_path` searches the best path from the last node of the root to the destination
+    /// using only the edges allowed by the given filter
+    fn yen_paths<S>(
+        graph: &DiGraphMap<usize, f64>,
+        best_path: Option<Vec<usize>>,
+        paths_count: usize,
+        spur_path: S,
+    ) -> Vec<Vec<usize>>
+    where
+        S: Fn(&[usize], &dyn Fn(usize, usize) -> bool) -> Option<Vec<usize>>,
+    {
+        let mut paths = match best_path {
+            Some(path) if paths_count > 0 && Self::is_simple_path(&path) => vec![path],
+            _ => return Vec::new(),
+        };
+        let mut candidates: Vec<(f64, Vec<usize>)> = Vec::new();
+
+        while paths.len() < paths_count {
+            let last_path = paths.last().expect("There is at least one path").clone();
+
+            for spur_index in 0..last_path.len() - 1 {
+                let root = &last_path[..=spur_index];
+
+                // the next edges of the found paths which share the same root
+                let removed_edges: HashSet<(usize, usize)> = paths
+                    .iter()
+                    .filter(|path| path.len() > spur_index + 1 && &path[..=spur_index] == root)
+                    .map(|path| (path[spur_index], path[spur_index + 1]))
+                    .collect();
+                // the root nodes, except the spur node, can't be visited again
+                let removed_nodes: HashSet<usize> = root[..spur_index].iter().cloned().collect();
+
+                let use_edge = |from_node: usize, to_node: usize| {
+                    !removed_nodes.contains(&from_node)
+                        && !removed_nodes.contains(&to_node)
+                        && !removed_edges.contains(&(from_node, to_node))
+                };
+
+                if let Some(spur_path) = spur_path(root, &use_edge) {
+                    let mut path = root[..spur_index].to_vec();
+                    path.extend(spur_path);
+
+                    if Self::is_simple_path(&path)
+                        && !paths.contains(&path)
+                        && !candidates.iter().any(|(_, other)| other == &path)
+                    {
+                        candidates.push((Self::path_rate(graph, &path), path));
+                    }
+                }
+            }
+
+            // the first of the candidates with the best rate
+            let best_candidate =
+                (0..candidates.len()).fold(None, |best: Option<usize>, index| match best {
+                    Some(best) if candidates[best].0 >= candidates[index].0 => Some(best),
+                    _ => Some(index),
+                });
 
-            let next_entry = next.entry(node_1).or_insert(HashMap::new());
-            next_entry.insert(node_2, Some(node_2));
+            match best_candidate {
+                Some(index) => paths.push(candidates.remove(index).1),
+                None => break,
+            }
         }
 
-        (rates, next)
+        paths
     }
 
-    pub fn best_rates(graph_handler: &GraphHandler) -> NextHashMap {
-        let v = &graph_handler.exchange_graph.index_map;
+    /// The best path with at most `max_hops` edges and `max_transfers` transfer edges
+    pub fn limited_best_path<T, F>(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        source: usize,
+        destination: usize,
+        limits: PathLimits,
+        is_transfer: T,
+        use_edge: F,
+    ) -> Option<Vec<usize>>
+    where
+        T: Fn(usize, usize) -> bool,
+        F: Fn(usize, usize) -> bool,
+    {
+        let edges = Self::search_edges(graph, limits, is_transfer, use_edge);
+
+        Self::layered_best_path(
+            nodes_count,
+            &edges,
+            (source, 1.0),
+            destination,
+            limits,
+            |edge, amount| Some(amount * edge.weight),
+        )
+        .map(|(_, path)| path)
+    }
 
-        let nodes = v.iter().fold(Vec::new(), |mut acc, (_key, &v)| {
-            acc.push(v);
-            acc
-        });
-        let (mut rate, mut next) =
-            Self::new_init_rates_next(&nodes, &graph_handler.exchange_graph.graph);
+    /// The edges of the graph for which `use_edge` returns `true`,
+    /// the transfers are only counted when they are limited
+    pub fn search_edges<T, F>(
+        graph: &DiGraphMap<usize, f64>,
+        limits: PathLimits,
+        is_transfer: T,
+        use_edge: F,
+    ) -> Vec<SearchEdge>
+    where
+        T: Fn(usize, usize) -> bool,
+        F: Fn(usize, usize) -> bool,
+    {
+        graph
+            .all_edges()
+            .filter(|&(from_node, to_node, _)| use_edge(from_node, to_node))
+            .map(|(from_node, to_node, &weight)| SearchEdge {
+                from_node,
+                to_node,
+                weight,
+                transfers: (limits.max_transfers.is_some() && is_transfer(from_node, to_node))
+                    as usize,
+                reverse: false,
+            })
+            .collect()
+    }
 
-        for k in 0..v.len() {
-            for i in 0..v.len() {
-                for j in 0..v.len() {
-                    let mul_rate = rate.get(&i).unwrap().get(&k).unwrap()
-                        * rate.get(&k).unwrap().get(&j).unwrap();
+    /// The path giving the biggest amount of the destination for the amount of the source,
+    /// `edge_output` is the amount exchanged over an edge or `None` if the edge can't take it.
+    /// It has to be non-decreasing in the amount, then the biggest amount reaching a node
+    /// is the best one to continue with.
+    /// Bellman-Ford where each round adds one more hop, over states of (node, transfers made),
+    /// the hops bound the search even when there are profitable cycles.
+    /// An edge to a node already on the path of its state is not relaxed,
+    /// so the paths are simple
+    pub fn layered_best_path<O>(
+        nodes_count: usize,
+        edges: &[SearchEdge],
+        (source, amount): (usize, f64),
+        destination: usize,
+        limits: PathLimits,
+        edge_output: O,
+    ) -> Option<(f64, Vec<usize>)>
+    where
+        O: Fn(&SearchEdge, f64) -> Option<f64>,
+    {
+        let max_hops = limits.max_hops.unwrap_or(nodes_count.saturating_sub(1));
+        let max_transfers = limits
+            .max_transfers
+            .map_or(0, |transfers| transfers.min(max_hops));
+        // a state is `transfers * nodes_count + node`
+        let states_count = (max_transfers + 1) * nodes_count;
+
+        let mut amounts = vec![0.0; states_count];
+        amounts[source] = amount;
+        // for every round, the previous state of the states improved in it
+        let mut rounds: Vec<Vec<Option<usize>>> = Vec::new();
+        let is_on_path = |rounds: &[Vec<Option<usize>>], mut state: usize, node: usize| {
+            if state % nodes_count == node {
+                return true;
+            }
+            for previous_states in rounds.iter().rev() {
+                if let Some(previous_state) = previous_states[state] {
+                    state = previous_state;
+                    if state % nodes_count == node {
+                        return true;
+                    }
+                }
+            }
 
-                    if rate.get(&i).unwrap().get(&j).unwrap() < &mul_rate {
-                        rate.get_mut(&i).unwrap().insert(j, mul_rate);
+            false
+        };
+
+        for _ in 0..max_hops {
+            let mut round_amounts = amounts.clone();
+            let mut previous_states = vec![None; states_count];
+            let mut improved = false;
+
+            for transfers in 0..=max_transfers {
+                for edge in edges {
+                    let to_transfers = transfers + edge.transfers;
+                    let from_state = transfers * nodes_count + edge.from_node;
+                    if to_transfers > max_transfers
+                        || amounts[from_state] <= 0.0
+                        || is_on_path(&rounds, from_state, edge.to_node)
+                    {
+                        continue;
+                    }
 
-                        let mut i_mut = next.get_mut(&i).unwrap();
+                    let to_state = to_transfers * nodes_count + edge.to_node;
+                    let to_amount = match edge_output(edge, amounts[from_state]) {
+                        Some(to_amount) => to_amount,
+                        None => continue,
+                    };
 
-                        i_mut.insert(j, *i_mut.get(&k).unwrap());
+                    if to_amount > round_amounts[to_state] {
+                        round_amounts[to_state] = to_amount;
+                        previous_states[to_state] = Some(from_state);
+                        improved = true;
                     }
                 }
             }
+
+            if !improved {
+                break;
+            }
+            amounts = round_amounts;
+            rounds.push(previous_states);
+        }
+
+        // the biggest amount with any number of transfers, the fewer transfers on a tie
+        let mut state = None;
+        for transfers in 0..=max_transfers {
+            let destination_state = transfers * nodes_count + destination;
+
+            if amounts[destination_state] > state.map_or(0.0, |state| amounts[state]) {
+                state = Some(destination_state);
+            }
         }
 
-        dbg!(&next);
-        dbg!(&rate);
-        next
+        let mut state = state?;
+        let output = amounts[state];
+        let mut path = vec![state % nodes_count];
+        for previous_states in rounds.iter().rev() {
+            if let Some(previous_state) = previous_states[state] {
+                state = previous_state;
+                path.push(state % nodes_count);
+            }
+        }
+        path.reverse();
+
+        Some((output, path))
     }
 
-    pub fn path(from_node: usize, to_node: usize, next: &NextHashMap) -> Option<Vec<usize>> {
-        if next
-            .get(&from_node)
-            .unwrap()
-            .get(&to_node)
-            .unwrap()
-            .is_none()
-        {
+    /// Splits the amount over the paths which give the most out of it, by augmenting paths
+    /// over the residual graph: each round finds the path with the best output for a part
+    /// of what is left, starting from `amount / chunks` and halving it while no path takes it,
+    /// then sends as much over the path as it takes without its rate getting worse.
+    /// `edge_output` gets the amount already sent over the edge and the amount to send now.
+    /// Without hop or transfer limits the residual graph also has the reverse edges,
+    /// which give back what an earlier round sent, so a round can reroute it
+    /// (with limits, the paths put back together from the edges could break them).
+    /// What no path can take is left out, so the inputs can sum to less than the amount
+    pub fn split_paths<O>(
+        nodes_count: usize,
+        edges: &[SearchEdge],
+        (source, amount): (usize, f64),
+        destination: usize,
+        limits: PathLimits,
+        chunks: usize,
+        edge_output: O,
+    ) -> Vec<SplitPath>
+    where
+        O: Fn(&SearchEdge, f64, f64) -> Option<f64>,
+    {
+        let with_reverse_edges = limits.max_hops.is_none() && limits.max_transfers.is_none();
+        let mut flows: BTreeMap<(usize, usize), EdgeFlow> = BTreeMap::new();
+        let mut remaining = amount;
+        let mut part = amount / chunks as f64;
+
+        while remaining > amount * SPLIT_EPSILON && part > amount * SPLIT_EPSILON {
+            let mut residual_edges = edges.to_vec();
+            if with_reverse_edges {
+                residual_edges.extend(edges.iter().filter_map(|edge| {
+                    flows.get(&(edge.from_node, edge.to_node))?;
+                    Some(SearchEdge {
+                        from_node: edge.to_node,
+                        to_node: edge.from_node,
+                        reverse: true,
+                        ..*edge
+                    })
+                }));
+            }
+            let output = |edge: &SearchEdge, amount: f64| {
+                Self::residual_output(&flows, edge, amount, &edge_output)
+            };
+
+            let part_amount = part.min(remaining);
+            let path = match Self::layered_best_path(
+                nodes_count,
+                &residual_edges,
+                (source, part_amount),
+                destination,
+                limits,
+                output,
+            ) {
+                Some((_, path)) => path,
+                None => {
+                    part /= 2.0;
+                    continue;
+                }
+            };
+
+            // the edges of the path, with the amounts going in and out of them
+            let path_hops = |amount: f64| {
+                let mut hops = Vec::with_capacity(path.len());
+                let mut hop_amount = amount;
+                for nodes in path.windows(2) {
+                    let (edge, hop_output) = residual_edges
+                        .iter()
+                        .filter(|edge| edge.from_node == nodes[0] && edge.to_node == nodes[1])
+                        .filter_map(|edge| Some((edge, output(edge, hop_amount)?)))
+                        .max_by(|(_, output), (_, other_output)| {
+                            output.partial_cmp(other_output).unwrap_or(Ordering::Equal)
+                        })?;
+
+                    hops.push((*edge, hop_amount, hop_output));
+                    hop_amount = hop_output;
+                }
+
+                Some((hop_amount, hops))
+            };
+            let path_rate = match path_hops(part_amount) {
+                Some((path_output, _)) => path_output / part_amount,
+                None => break,
+            };
+            let keeps_rate = |amount: f64| {
+                path_hops(amount).is_some_and(|(path_output, _)| {
+                    path_output >= amount * path_rate * (1.0 - SPLIT_EPSILON)
+                })
+            };
+
+            // the biggest amount the path takes at its rate, by bisection
+            let mut sent = part_amount;
+            if keeps_rate(remaining) {
+                sent = remaining;
+            } else {
+                let mut too_much = remaining;
+                for _ in 0..BISECTION_ROUNDS {
+                    let middle = (sent + too_much) / 2.0;
+                    if keeps_rate(middle) {
+                        sent = middle;
+                    } else {
+                        too_much = middle;
+                    }
+                }
+            }
+
+            let hops = match path_hops(sent) {
+                Some((_, hops)) => hops,
+                None => break,
+            };
+            for (edge, hop_input, hop_output) in hops {
+                if edge.reverse {
+                    let key = (edge.to_node, edge.from_node);
+                    let flow = flows.get_mut(&key).expect("A reverse edge has a flow");
+                    let previous_output = flow.output;
+
+                    flow.input -= hop_output;
+                    flow.output -= hop_input;
+                    if flow.output <= previous_output * SPLIT_EPSILON {
+                        flows.remove(&key);
+                    }
+                } else {
+                    let flow = flows.entry((edge.from_node, edge.to_node)).or_default();
+
+                    flow.input += hop_input;
+                    flow.output += hop_output;
+                }
+            }
+            remaining -= sent;
+        }
+
+        let mut split_paths = Self::decompose_flows(flows, source, destination);
+        // the paths left with a tiny part of the amount by the rounding errors of the limits
+        split_paths.retain(|split_path| split_path.input > amount * RATE_EPSILON);
+        split_paths.sort_by(|split_path, other| {
+            (other.output / other.input)
+                .partial_cmp(&(split_path.output / split_path.input))
+                .unwrap_or(Ordering::Equal)
+        });
+
+        split_paths
+    }
+
+    /// What the edge gives for the amount, given the flows sent by the previous rounds.
+    /// A reverse edge takes back part of the output of its forward edge
+    /// and gives the input which isn't needed for the rest of it anymore
+    fn residual_output<O>(
+        flows: &BTreeMap<(usize, usize), EdgeFlow>,
+        edge: &SearchEdge,
+        amount: f64,
+        edge_output: &O,
+    ) -> Option<f64>
+    where
+        O: Fn(&SearchEdge, f64, f64) -> Option<f64>,
+    {
+        if !edge.reverse {
+            let sent = flows
+                .get(&(edge.from_node, edge.to_node))
+                .map_or(0.0, |flow| flow.input);
+
+            return edge_output(edge, sent, amount);
+        }
+
+        let forward_edge = SearchEdge {
+            from_node: edge.to_node,
+            to_node: edge.from_node,
+            reverse: false,
+            ..*edge
+        };
+        let flow = flows.get(&(forward_edge.from_node, forward_edge.to_node))?;
+        if amount > flow.output * (1.0 + SPLIT_EPSILON) {
             return None;
         }
 
-        let mut path = vec![from_node];
+        let kept_output = flow.output - amount;
+        if kept_output <= flow.output * SPLIT_EPSILON {
+            return Some(flow.input);
+        }
+
+        // the smallest input still giving the kept output, by bisection
+        // since the output doesn't decrease with the input
+        let (mut too_little, mut kept_input) = (0.0, flow.input);
+        for _ in 0..BISECTION_ROUNDS {
+            let middle = (too_little + kept_input) / 2.0;
+            if edge_output(&forward_edge, 0.0, middle).is_some_and(|output| output >= kept_output) {
+                kept_input = middle;
+            } else {
+                too_little = middle;
+            }
+        }
+
+        Some(flow.input - kept_input)
+    }
+
+    /// Puts the flows back together into paths from the source to the destination,
+    /// each one taking the share of the edges it can carry until one of them is used up
+    fn decompose_flows(
+        mut flows: BTreeMap<(usize, usize), EdgeFlow>,
+        source: usize,
+        destination: usize,
+    ) -> Vec<SplitPath> {
+        let mut split_paths = Vec::new();
+
+        loop {
+            let mut path = vec![source];
+            let mut visited = HashSet::new();
+            if !Self::flow_path(&flows, destination, &mut path, &mut visited) {
+                return split_paths;
+            }
+
+            // the input of the path is bounded by the edge with the smallest share left
+            let mut input = f64::INFINITY;
+            let mut gain = 1.0;
+            for nodes in path.windows(2) {
+                let flow = flows[&(nodes[0], nodes[1])];
+
+                input = input.min(flow.input / gain);
+                gain *= flow.output / flow.input;
+            }
+
+            let mut edge_input = input;
+            for nodes in path.windows(2) {
+                let flow = flows
+                    .get_mut(&(nodes[0], nodes[1]))
+                    .expect("The path has flows");
+                let edge_output = edge_input * flow.output / flow.input;
+
+                flow.input -= edge_input;
+                flow.output -= edge_output;
+                if flow.input <= edge_input * SPLIT_EPSILON {
+                    flows.remove(&(nodes[0], nodes[1]));
+                }
+                edge_input = edge_output;
+            }
+
+            split_paths.push(SplitPath {
+                path,
+                input,
+                output: edge_input,
+            });
+        }
+    }
+
+    /// Extends the path with edges having a flow until it reaches the destination,
+    /// `false` if it can't
+    fn flow_path(
+        flows: &BTreeMap<(usize, usize), EdgeFlow>,
+        destination: usize,
+        path: &mut Vec<usize>,
+        visited: &mut HashSet<usize>,
+    ) -> bool {
+        let node = *path.last().expect("The path starts from the source");
+        if node == destination {
+            return true;
+        }
+        visited.insert(node);
+
+        for (&(_, to_node), _) in flows.range((node, 0)..(node + 1, 0)) {
+            if visited.contains(&to_node) {
+                continue;
+            }
+
+            path.push(to_node);
+            if Self::flow_path(flows, destination, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+
+        false
+    }
+
+    /// Finds every simple cycle of the graph with at most `max_length` edges
+    /// which multiplies the amount by more than `min_rate`.
+    /// Each cycle starts (and ends) from its lowest node, so it's listed only once.
+    /// A branch is cut when even the best walk back to the start can't make the cycle
+    /// profitable enough, which leaves out most of the transfers between the exchanges
+    pub fn profitable_cycles(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        min_rate: f64,
+        max_length: usize,
+    ) -> Vec<Arbitrage> {
+        let mut cycles = Vec::new();
+        let mut on_path = vec![false; nodes_count];
+
+        for start in 0..nodes_count {
+            if !graph.contains_node(start) {
+                continue;
+            }
+
+            let best_returns = Self::best_returns(graph, nodes_count, start, max_length);
+            let mut path = vec![start];
+            Self::search_cycles(
+                graph,
+                start,
+                1.0,
+                (min_rate, &best_returns),
+                &mut path,
+                &mut on_path,
+                &mut cycles,
+            );
+        }
+
+        cycles
+    }
+
+    /// For each number of edges up to `max_length`, the best rate of going back to the start
+    /// from each node over at most that many edges, through the nodes not lower than the start.
+    /// The walks can repeat nodes, so the rates bound the ones of the simple paths
+    fn best_returns(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        start: usize,
+        max_length: usize,
+    ) -> Vec<Vec<f64>> {
+        let mut rates = vec![0.0; nodes_count];
+        rates[start] = 1.0;
+        let mut best_returns = vec![rates];
+
+        for _ in 0..max_length {
+            let previous_rates = best_returns
+                .last()
+                .expect("There are the rates of no edges");
+            let mut rates = previous_rates.clone();
+
+            for (from_node, to_node, &weight) in graph.all_edges() {
+                if from_node >= start && to_node >= start {
+                    let rate = weight * previous_rates[to_node];
+
+                    if rate > rates[from_node] {
+                        rates[from_node] = rate;
+                    }
+                }
+            }
+            best_returns.push(rates);
+        }
+
+        best_returns
+    }
+
+    fn search_cycles(
+        graph: &DiGraphMap<usize, f64>,
+        node: usize,
+        rate: f64,
+        (min_rate, best_returns): (f64, &[Vec<f64>]),
+        path: &mut Vec<usize>,
+        on_path: &mut [bool],
+        cycles: &mut Vec<Arbitrage>,
+    ) {
+        let start = path[0];
+        // the edges left for the cycle after the one to the neighbor
+        let edges_left = match (best_returns.len() - 1).checked_sub(path.len()) {
+            Some(edges_left) => edges_left,
+            None => return,
+        };
+        on_path[node] = true;
+
+        for (_, neighbor, &weight) in graph.edges(node) {
+            let neighbor_rate = rate * weight;
+
+            if neighbor == start {
+                if neighbor_rate > min_rate {
+                    let mut cycle = path.clone();
+                    cycle.push(start);
+                    cycles.push(Arbitrage { cycle });
+                }
+            } else if neighbor > start
+                && !on_path[neighbor]
+                && neighbor_rate * best_returns[edges_left][neighbor] > min_rate
+            {
+                path.push(neighbor);
+                Self::search_cycles(
+                    graph,
+                    neighbor,
+                    neighbor_rate,
+                    (min_rate, best_returns),
+                    path,
+                    on_path,
+                    cycles,
+                );
+                path.pop();
+            }
+        }
+
+        on_path[node] = false;
+    }
 
-        let mut source_node = from_node;
-        let dest_node = to_node;
-        while source_node != dest_node {
-            source_node = next
-                .get(&source_node)
-                .unwrap()
-                .get(&dest_node)
-                .unwrap()
-                .unwrap();
-            path.push(source_node);
+    /// Runs `log_best_paths` for each of the sources, splitting them between the `threads`.
+    /// The results are in the same order as the sources
+    pub fn log_best_paths_parallel(
+        graph: &DiGraphMap<usize, f64>,
+        nodes_count: usize,
+        sources: &[usize],
+        threads: usize,
+    ) -> Vec<Result<LogPaths, Arbitrage>> {
+        if threads <= 1 || sources.len() <= 1 {
+            return sources
+                .iter()
+                .map(|&source| Self::log_best_paths(graph, nodes_count, source))
+                .collect();
         }
 
+        let sources_per_thread = sources.len().div_ceil(threads);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = sources
+                .chunks(sources_per_thread)
+                .map(|thread_sources| {
+                    scope.spawn(move || {
+                        thread_sources
+                            .iter()
+                            .map(|&source| Self::log_best_paths(graph, nodes_count, source))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Solving a source shouldn't panic"))
+                .collect()
+        })
+    }
+
+    /// Whether the path visits each of its nodes only once
+    pub fn is_simple_path(path: &[usize]) -> bool {
+        let mut visited = HashSet::with_capacity(path.len());
+
+        path.iter().all(|&node| visited.insert(node))
+    }
+
+    /// Multiplies the rates of the edges along the path
+    pub fn path_rate(graph: &DiGraphMap<usize, f64>, path: &[usize]) -> f64 {
+        path.windows(2)
+            .filter_map(|nodes| graph.edge_weight(nodes[0], nodes[1]))
+            .product()
+    }
+}
+
+/// Limits of the path search, `None` means there is no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PathLimits {
+    pub max_hops: Option<usize>,
+    /// Transfers are the edges between the same currency on different exchanges
+    pub max_transfers: Option<usize>,
+}
+
+/// An edge of the layered path search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchEdge {
+    pub from_node: usize,
+    pub to_node: usize,
+    pub weight: f64,
+    /// `1` for a counted transfer
+    pub transfers: usize,
+    /// Set on the residual edges of a split, which go back over the edge of the other direction
+    /// and keep its weight
+    pub reverse: bool,
+}
+
+/// A path with the part of the amount sent over it and what it gives
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitPath {
+    pub path: Vec<usize>,
+    pub input: f64,
+    pub output: f64,
+}
+
+/// The amounts going in and out of an edge of a split
+#[derive(Debug, Clone, Copy, Default)]
+struct EdgeFlow {
+    input: f64,
+    output: f64,
+}
+
+#[derive(Debug)]
+pub struct LogPaths {
+    pub source: usize,
+    pub distances: Vec<f64>,
+    pub predecessors: Vec<Option<usize>>,
+}
+
+impl LogPaths {
+    pub fn rate(&self, to_node: usize) -> Option<f64> {
+        self.distances
+            .get(to_node)
+            .filter(|distance| distance.is_finite())
+            .map(|distance| (-distance).exp())
+    }
+
+    pub fn path(&self, to_node: usize) -> Option<Vec<usize>> {
+        self.rate(to_node)?;
+
+        let mut path = vec![to_node];
+        let mut node = to_node;
+
+        while node != self.source {
+            node = self.predecessors[node]?;
+            path.push(node);
+        }
+        path.reverse();
+
         Some(path)
     }
 }
 
+/// The all-pairs best rates and next hops, kept up to date with every change of the graph
+/// so requests don't have to solve the whole graph
+#[derive(Debug, Default)]
+pub struct RatesTable {
+    matrix: RatesMatrix,
+    /// A profitable cycle makes the best rates unbounded and the table can't be used
+    arbitrage: bool,
+    /// How many threads to use when solving (parts of) the table from scratch
+    threads: usize,
+}
+
+impl RatesTable {
+    pub fn new(graph: &DiGraphMap<usize, f64>, nodes_count: usize) -> Self {
+        Self::solve(graph, nodes_count, 1)
+    }
+
+    /// An empty table which will use the `threads` for solving
+    pub fn with_threads(threads: usize) -> Self {
+        Self {
+            threads,
+            ..Self::default()
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn solve(graph: &DiGraphMap<usize, f64>, nodes_count: usize, threads: usize) -> Self {
+        let matrix = Exchanger::floyd_warshall_parallel(graph, nodes_count, threads);
+        let arbitrage = (0..nodes_count).any(|node| matrix.rate(node, node) > 1.0 + RATE_EPSILON);
+
+        Self {
+            matrix,
+            arbitrage,
+            threads,
+        }
+    }
+
+    pub fn nodes_count(&self) -> usize {
+        self.matrix.nodes_count()
+    }
+
+    pub fn has_arbitrage(&self) -> bool {
+        self.arbitrage
+    }
+
+    pub fn rate(&self, from_node: usize, to_node: usize) -> Option<f64> {
+        Some(self.matrix.rate(from_node, to_node)).filter(|&rate| rate > 0.0)
+    }
+
+    pub fn path(&self, from_node: usize, to_node: usize) -> Option<Vec<usize>> {
+        self.matrix.path(from_node, to_node)
+    }
+
+    /// Adds a node without any edges, its edges should be added with `update_edge`
+    pub fn add_node(&mut self) -> usize {
+        self.matrix.add_node()
+    }
+
+    /// Repairs the table after the weight of the edge has changed from `previous_weight`,
+    /// where `None` means that the edge didn't exist
+    pub fn update_edge(
+        &mut self,
+        graph: &DiGraphMap<usize, f64>,
+        from_node: usize,
+        to_node: usize,
+        previous_weight: Option<f64>,
+    ) {
+        let weight = graph
+            .edge_weight(from_node, to_node)
+            .cloned()
+            .unwrap_or(0.0);
+        let previous_weight = previous_weight.unwrap_or(0.0);
+
+        if weight > previous_weight {
+            self.improve_edge(from_node, to_node, weight);
+        } else if weight < previous_weight {
+            self.worsen_edge(graph, from_node, to_node, previous_weight);
+        }
+    }
+
+    /// A better edge can only improve the pairs going through it,
+    /// so every pair is checked against the path `i -> from_node -> to_node -> j`
+    fn improve_edge(&mut self, from_node: usize, to_node: usize, weight: f64) {
+        // the rates are already unbounded, only a worse edge can change that
+        if self.arbitrage {
+            return;
+        }
+
+        if self.matrix.rate(to_node, from_node) * weight > 1.0 + RATE_EPSILON {
+            self.arbitrage = true;
+            return;
+        }
+
+        let nodes_count = self.nodes_count();
+        let to_from_node: Vec<(f64, Option<usize>)> = (0..nodes_count)
+            .map(|node| {
+                (
+                    self.matrix.rate(node, from_node),
+                    self.matrix.next(node, from_node),
+                )
+            })
+            .collect();
+        let from_to_node = self.matrix.row(to_node).to_vec();
+
+        for (i, &(rate_to_edge, next_to_edge)) in to_from_node.iter().enumerate() {
+            if rate_to_edge == 0.0 {
+                continue;
+            }
+
+            let next_hop = if i == from_node {
+                Some(to_node)
+            } else {
+                next_to_edge
+            };
+
+            for (j, &rate_from_edge) in from_to_node.iter().enumerate() {
+                let candidate_rate = rate_to_edge * weight * rate_from_edge;
+
+                if i != j && self.matrix.rate(i, j) * (1.0 + RATE_EPSILON) < candidate_rate {
+                    self.matrix.set(i, j, candidate_rate, next_hop);
+                }
+            }
+        }
+    }
+
+    /// A worse edge affects only the rows which have a best path going through it,
+    /// those are solved again from scratch unless there are too many of them
+    fn worsen_edge(
+        &mut self,
+        graph: &DiGraphMap<usize, f64>,
+        from_node: usize,
+        to_node: usize,
+        previous_weight: f64,
+    ) {
+        let nodes_count = self.nodes_count();
+
+        if self.arbitrage {
+            *self = Self::solve(graph, nodes_count, self.threads);
+            return;
+        }
+
+        let from_edge_rates = self.matrix.row(to_node);
+        let affected_rows: Vec<usize> = (0..nodes_count)
+            .filter(|&i| {
+                let rate_to_edge = self.matrix.rate(i, from_node) * previous_weight;
+
+                rate_to_edge > 0.0
+                    && self.matrix.row(i).iter().zip(from_edge_rates).any(
+                        |(&i_j_rate, &rate_from_edge)| {
+                            let edge_rate = rate_to_edge * rate_from_edge;
+
+                            edge_rate > 0.0 && edge_rate >= i_j_rate * (1.0 - RATE_EPSILON)
+                        },
+                    )
+            })
+            .collect();
+
+        // solving the rows one by one costs `O(nodes * edges)` each,
+        // when that's more than solving all of them at once do the latter
+        if affected_rows.len() * graph.edge_count() >= nodes_count * nodes_count {
+            *self = Self::solve(graph, nodes_count, self.threads);
+            return;
+        }
+
+        let rows_paths =
+            Exchanger::log_best_paths_parallel(graph, nodes_count, &affected_rows, self.threads);
+
+        for (source, log_paths) in affected_rows.into_iter().zip(rows_paths) {
+            match log_paths {
+                Ok(log_paths) => self.set_row(graph, source, &log_paths),
+                Err(_) => {
+                    self.arbitrage = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn set_row(&mut self, graph: &DiGraphMap<usize, f64>, source: usize, log_paths: &LogPaths) {
+        for node in 0..self.nodes_count() {
+            let (rate, next_hop) = match log_paths.path(node) {
+                Some(path) => (
+                    Exchanger::path_rate(graph, &path),
+                    path.get(1).or_else(|| path.first()).cloned(),
+                ),
+                None => (0.0, None),
+            };
+
+            self.matrix.set(source, node, rate, next_hop);
+        }
+    }
+}
+
+/// A profitable cycle, the first and the last nodes are the same
+#[derive(Debug, PartialEq)]
+pub struct Arbitrage {
+    pub cycle: Vec<usize>,
+}
+
+impl Arbitrage {
+    fn from_predecessors(node: usize, predecessors: &[Option<usize>]) -> Self {
+        // going back enough times guarantees we are on the cycle itself
+        // and not on a path leading to it
+        let cycle_node = (0..predecessors.len()).fold(node, |node, _| {
+            predecessors[node].expect("Every node leading to a cycle has a predecessor")
+        });
+
+        let mut cycle = vec![cycle_node];
+        let mut node = cycle_node;
+        loop {
+            node = predecessors[node].expect("Every node of a cycle has a predecessor");
+            cycle.push(node);
+
+            if node == cycle_node {
+                break;
+            }
+        }
+        cycle.reverse();
+
+        Self { cycle }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
     use crate::parse::command::PriceUpdate;
 
     use super::*;
@@ -146,12 +1183,13 @@ mod test {
 
         let mut graph_handler: GraphHandler = GraphHandler::default();
 
-        graph_handler.handle_update(price_update);
+        graph_handler.handle_update(price_update).unwrap();
 
         let best_rates = Exchanger::best_rates(&graph_handler);
 
-        let path = Exchanger::path(0, 1, &best_rates);
+        let path = best_rates.path(0, 1);
         assert_eq!(Some(vec![0_usize, 1_usize]), path);
+        assert_eq!(2.0, best_rates.rate(0, 1));
     }
 
     #[test]
@@ -177,12 +1215,13 @@ mod test {
             .unwrap(),
         ];
 
-        let mut graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
 
         let best_rates = Exchanger::best_rates(&graph_handler);
 
-        //        let path = Exchanger::path(0, 2, &best_rates);
-        //        assert_eq!(Some(vec![0_usize, 1_usize, 2_usize]), path);
+        let path = best_rates.path(0, 2);
+        assert_eq!(Some(vec![0_usize, 1_usize, 2_usize]), path);
+        assert!((best_rates.rate(0, 2) - 3.0).abs() < 1e-6);
     }
 
     #[test]
@@ -208,11 +1247,11 @@ mod test {
             .unwrap(),
         ];
 
-        let mut graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
 
         let best_rates = Exchanger::best_rates(&graph_handler);
 
-        let path = Exchanger::path(0, 3, &best_rates);
+        let path = best_rates.path(0, 3);
         assert_eq!(Some(vec![0_usize, 1_usize, 2_usize, 3_usize]), path);
     }
 
@@ -248,19 +1287,26 @@ mod test {
             .unwrap(),
         ];
 
-        let mut graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph = &graph_handler.exchange_graph.graph;
 
-        let best_rates = Exchanger::best_rates(&graph_handler);
+        // EXCI USD -> EXCI BTC -> EXCI USD is a profitable cycle: 0.005 * 5000 > 1
+        let arbitrage = Exchanger::log_best_paths(graph, 6, 0).unwrap_err();
 
-        //        dbg!(best_rates);
-        //        let path = Exchanger::path(0, 3, &best_rates);
-        //        assert_eq!(Some(vec![0_usize, 1_usize, 2_usize, 3_usize]), path);
+        assert_eq!(3, arbitrage.cycle.len());
+        assert!(arbitrage.cycle.contains(&4) && arbitrage.cycle.contains(&5));
+        assert_eq!(25.0, Exchanger::path_rate(graph, &arbitrage.cycle));
 
-        //        let path = Exchanger::path(0, 5, &best_rates);
-        //        assert_eq!(Some(vec![0_usize, 4_usize, 5_usize]), path);
+        // the FW solver doesn't loop forever on the profitable cycle
+        let best_rates = Exchanger::best_rates(&graph_handler);
+        for from_node in 0..6 {
+            for to_node in 0..6 {
+                best_rates.path(from_node, to_node);
+            }
+        }
     }
 
-    //    #[test]
+    #[test]
     fn test_multiple_paths_from_different_exchanges() {
         let price_updates = vec![
             PriceUpdate::from_input(&[
@@ -292,15 +1338,19 @@ mod test {
             .unwrap(),
         ];
 
-        let mut graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph = &graph_handler.exchange_graph.graph;
 
-        let best_rates = Exchanger::best_rates(&graph_handler);
+        // KRAKEN LIT -> KRAKEN USD -> KRAKEN EUR -> EXCI EUR -> EXCI LIT -> KRAKEN LIT
+        // is a profitable cycle: 1000 * 2 * 1.0 * 0.001 * 1.0 = 2
+        let arbitrage = Exchanger::log_best_paths(graph, 5, 0).unwrap_err();
 
-        let path = Exchanger::path(0, 4, &best_rates);
-        assert_eq!(Some(vec![0_usize, 2_usize, 4_usize]), path);
+        assert_eq!(6, arbitrage.cycle.len());
+        assert_eq!(arbitrage.cycle.first(), arbitrage.cycle.last());
+        assert!((Exchanger::path_rate(graph, &arbitrage.cycle) - 2.0).abs() < 1e-9);
     }
 
-    //    #[test]
+    #[test]
     fn test_multiple_paths_long_path_is_the_best_from_different_exchanges() {
         let price_updates = vec![
             // 0 KRAKEN USD
@@ -338,35 +1388,534 @@ mod test {
             .unwrap(),
         ];
 
-        let mut graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
 
         // 0
         let from_node = graph_handler
             .exchange_graph
             .index_map
-            .get(&("KRAKEN".to_owned(), "USD".to_owned()))
+            .get(&("KRAKEN".into(), "USD".into()))
             .unwrap();
         // 5
         let to_node = graph_handler
             .exchange_graph
             .index_map
-            .get(&("EXCI".to_owned(), "BTC".to_owned()))
+            .get(&("EXCI".into(), "BTC".into()))
             .unwrap();
 
+        // GDAX EUR -> GDAX LIT -> GDAX EUR gains 0.000666667 * 1500 = 1.0000005,
+        // which is the rounding of the quotes and not an arbitrage
+
         // KRAKEN USD -> KRAKEN LIT 2000 * 0.001 = 2
         // KRAKEN LIT -> GDAX LIT 2 * 1.0 = 2
         // GDAX LIT -> GDAX EUR 2 * 1500 = 3000
         // GDAX EUR -> EXCI EUR 3000 * 1.0 = 3000
         // EXCI EUR -> EXCI BTC 3000 * 0.0002 = 0.6
 
+        let expected_path = Some(vec![0_usize, 1_usize, 3_usize, 2_usize, 4_usize, 5_usize]);
+
         let best_rates = Exchanger::best_rates(&graph_handler);
+        assert_eq!(expected_path, best_rates.path(*from_node, *to_node));
+
+        let graph = &graph_handler.exchange_graph.graph;
+        let log_paths = Exchanger::log_best_paths(graph, 6, *from_node).unwrap();
+
+        assert_eq!(expected_path, log_paths.path(*to_node));
+        assert!((log_paths.rate(*to_node).unwrap() - 0.0003).abs() < 1e-12);
+    }
+
+    fn assert_same_rates(expected: &RatesTable, table: &RatesTable) {
+        assert_eq!(expected.nodes_count(), table.nodes_count());
+
+        for from_node in 0..expected.nodes_count() {
+            for to_node in 0..expected.nodes_count() {
+                let expected_rate = expected.rate(from_node, to_node).unwrap_or(0.0);
+                let rate = table.rate(from_node, to_node).unwrap_or(0.0);
+
+                assert!(
+                    (expected_rate - rate).abs() <= expected_rate * 1e-9,
+                    "Rate from {} to {} should be {}, got {}",
+                    from_node,
+                    to_node,
+                    expected_rate,
+                    rate
+                );
+                assert_eq!(
+                    expected.path(from_node, to_node).is_some(),
+                    table.path(from_node, to_node).is_some()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rates_table_is_kept_up_to_date_on_better_and_worse_edges() {
+        let timestamp = Utc.ymd(2017, 11, 1).and_hms(9, 0, 0);
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(timestamp, "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(timestamp, "GDAX", "BTC", "USD", 1001.0, 0.0008),
+            PriceUpdate::new(timestamp, "GDAX", "USD", "EUR", 0.9, 1.1),
+            PriceUpdate::new(timestamp, "EXCI", "EUR", "LIT", 0.5, 1.9),
+        ]);
+
+        let exchange_graph = &graph_handler.exchange_graph;
+        let expected = RatesTable::new(
+            &exchange_graph.graph,
+            exchange_graph.index_map.index_bound(),
+        );
+        assert_same_rates(&expected, &graph_handler.rates_table);
+
+        // a better rate on KRAKEN makes it the best path for BTC -> USD
+        let newer = timestamp + Duration::minutes(1);
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                newer, "KRAKEN", "BTC", "USD", 1010.0, 0.0009,
+            ))
+            .unwrap();
+
+        let exchange_graph = &graph_handler.exchange_graph;
+        let expected = RatesTable::new(
+            &exchange_graph.graph,
+            exchange_graph.index_map.index_bound(),
+        );
+        assert_same_rates(&expected, &graph_handler.rates_table);
+        assert_eq!(Some(vec![2, 0, 1]), graph_handler.rates_table.path(2, 1));
+
+        // and a worse rate on GDAX changes the paths from (GDAX, USD) to (GDAX, EUR)
+        let newest = timestamp + Duration::minutes(2);
+        graph_handler
+            .handle_update(PriceUpdate::new(newest, "GDAX", "USD", "EUR", 0.8, 1.1))
+            .unwrap();
+
+        let exchange_graph = &graph_handler.exchange_graph;
+        let expected = RatesTable::new(
+            &exchange_graph.graph,
+            exchange_graph.index_map.index_bound(),
+        );
+        assert_same_rates(&expected, &graph_handler.rates_table);
+        assert!(!graph_handler.rates_table.has_arbitrage());
+    }
+
+    #[test]
+    fn test_rates_table_detects_and_recovers_from_arbitrage() {
+        let timestamp = Utc.ymd(2017, 11, 1).and_hms(9, 0, 0);
+        let mut graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(timestamp, "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(timestamp, "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+        assert!(!graph_handler.rates_table.has_arbitrage());
+
+        // KRAKEN USD -> KRAKEN BTC -> GDAX BTC -> GDAX USD -> KRAKEN USD: 0.0009 * 1200 > 1
+        let newer = timestamp + Duration::minutes(1);
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                newer, "GDAX", "BTC", "USD", 1200.0, 0.0008,
+            ))
+            .unwrap();
+        assert!(graph_handler.rates_table.has_arbitrage());
+
+        let newest = timestamp + Duration::minutes(2);
+        graph_handler
+            .handle_update(PriceUpdate::new(
+                newest, "GDAX", "BTC", "USD", 1002.0, 0.0008,
+            ))
+            .unwrap();
+        assert!(!graph_handler.rates_table.has_arbitrage());
+
+        let exchange_graph = &graph_handler.exchange_graph;
+        let expected = RatesTable::new(
+            &exchange_graph.graph,
+            exchange_graph.index_map.index_bound(),
+        );
+        assert_same_rates(&expected, &graph_handler.rates_table);
+    }
+
+    #[test]
+    fn test_log_best_paths_in_parallel_are_the_same_as_one_by_one() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+            PriceUpdate::new(Utc::now(), "GDAX", "USD", "EUR", 0.9, 1.1),
+            PriceUpdate::new(Utc::now(), "EXCI", "EUR", "LIT", 0.5, 1.9),
+        ]);
+        let graph = &graph_handler.exchange_graph.graph;
+        let sources: Vec<usize> = (0..7).collect();
+
+        let parallel = Exchanger::log_best_paths_parallel(graph, 7, &sources, 3);
+
+        assert_eq!(sources.len(), parallel.len());
+        for (&source, log_paths) in sources.iter().zip(parallel) {
+            let expected = Exchanger::log_best_paths(graph, 7, source).unwrap();
+            let log_paths = log_paths.unwrap();
+
+            assert_eq!(source, log_paths.source);
+            assert_eq!(expected.distances, log_paths.distances);
+            assert_eq!(expected.predecessors, log_paths.predecessors);
+        }
+    }
+
+    #[test]
+    fn test_k_best_paths_are_loopless_and_ranked_by_rate() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+        let graph = &graph_handler.exchange_graph.graph;
+        let index_map = &graph_handler.exchange_graph.index_map;
+        let node = |exchange: &str, currency: &str| {
+            *index_map.get(&(exchange.into(), currency.into())).unwrap()
+        };
+        let (kraken_btc, kraken_usd) = (node("KRAKEN", "BTC"), node("KRAKEN", "USD"));
+        let (gdax_btc, gdax_usd) = (node("GDAX", "BTC"), node("GDAX", "USD"));
+
+        assert_eq!(
+            vec![vec![kraken_btc, gdax_btc, gdax_usd, kraken_usd]],
+            Exchanger::k_best_paths(graph, 4, kraken_btc, kraken_usd, 1).unwrap()
+        );
+
+        // going back to KRAKEN BTC or GDAX BTC would be a loop, so there are only 2 paths
+        let paths = Exchanger::k_best_paths(graph, 4, kraken_btc, kraken_usd, 3).unwrap();
+        assert_eq!(
+            vec![
+                vec![kraken_btc, gdax_btc, gdax_usd, kraken_usd],
+                vec![kraken_btc, kraken_usd],
+            ],
+            paths
+        );
+        assert_eq!(1000.0, Exchanger::path_rate(graph, &paths[1]));
+    }
+
+    #[test]
+    fn test_k_best_paths_through_many_exchanges_are_loopless_and_ranked() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+            PriceUpdate::new(Utc::now(), "EXCI", "BTC", "USD", 999.0, 0.0007),
+        ]);
+        let graph = &graph_handler.exchange_graph.graph;
+
+        // every simple path from KRAKEN BTC to KRAKEN USD
+        fn simple_paths(graph: &DiGraphMap<usize, f64>, path: &mut Vec<usize>, all: &mut usize) {
+            let node = *path.last().unwrap();
+            if node == 1 {
+                *all += 1;
+                return;
+            }
+            for neighbor in graph.neighbors(node) {
+                if !path.contains(&neighbor) {
+                    path.push(neighbor);
+                    simple_paths(graph, path, all);
+                    path.pop();
+                }
+            }
+        }
+        let mut all_paths_count = 0;
+        simple_paths(graph, &mut vec![0], &mut all_paths_count);
+
+        let paths = Exchanger::k_best_paths(graph, 6, 0, 1, 100).unwrap();
+
+        assert_eq!(all_paths_count, paths.len());
+        for (index, path) in paths.iter().enumerate() {
+            let unique: HashSet<&usize> = path.iter().collect();
+            assert_eq!(path.len(), unique.len(), "Path {:?} has a loop", path);
+            assert!(
+                !paths[index + 1..].contains(path),
+                "Path {:?} is repeated",
+                path
+            );
+        }
+        let rates: Vec<f64> = paths
+            .iter()
+            .map(|path| Exchanger::path_rate(graph, path))
+            .collect();
+        assert!(rates.windows(2).all(|rates| rates[0] >= rates[1]));
+    }
+
+    #[test]
+    fn test_limited_best_path_respects_the_hops_and_transfers() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1001.0, 0.0008),
+        ]);
+        let exchange_graph = &graph_handler.exchange_graph;
+        let is_transfer = |from_node, to_node| exchange_graph.is_transfer(from_node, to_node);
+        let limited_best_path = |max_hops, max_transfers| {
+            Exchanger::limited_best_path(
+                &exchange_graph.graph,
+                4,
+                0,
+                1,
+                PathLimits {
+                    max_hops,
+                    max_transfers,
+                },
+                is_transfer,
+                |_, _| true,
+            )
+        };
+
+        // KRAKEN BTC -> GDAX BTC -> GDAX USD -> KRAKEN USD
+        let through_gdax = Some(vec![0, 2, 3, 1]);
+        let direct = Some(vec![0, 1]);
+
+        assert_eq!(through_gdax, limited_best_path(None, None));
+        for max_hops in 0..6 {
+            let path = limited_best_path(Some(max_hops), None).unwrap_or_default();
+            assert!(Exchanger::is_simple_path(&path), "{:?} has a loop", path);
+        }
+        assert_eq!(through_gdax, limited_best_path(Some(3), Some(2)));
+        assert_eq!(direct, limited_best_path(Some(2), None));
+        assert_eq!(direct, limited_best_path(None, Some(1)));
+        assert_eq!(direct, limited_best_path(Some(3), Some(0)));
+        assert_eq!(None, limited_best_path(Some(0), None));
+
+        let paths = Exchanger::k_best_limited_paths(
+            &exchange_graph.graph,
+            4,
+            0,
+            1,
+            5,
+            PathLimits {
+                max_hops: None,
+                max_transfers: Some(0),
+            },
+            is_transfer,
+        );
+        assert_eq!(vec![vec![0, 1]], paths);
+    }
+
+    #[test]
+    fn test_limited_best_path_is_bounded_by_the_hops_with_arbitrage() {
+        let graph_handler = GraphHandler::from(vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1200.0, 0.0008),
+        ]);
+        let exchange_graph = &graph_handler.exchange_graph;
+        let is_transfer = |from_node, to_node| exchange_graph.is_transfer(from_node, to_node);
+        let limits = PathLimits {
+            max_hops: Some(6),
+            max_transfers: None,
+        };
+
+        // going around the profitable cycle would revisit the nodes
+        let path = Exchanger::limited_best_path(
+            &exchange_graph.graph,
+            4,
+            0,
+            3,
+            limits,
+            is_transfer,
+            |_, _| true,
+        );
+        assert_eq!(Some(vec![0, 2, 3]), path);
 
-        dbg!(best_rates);
-        //        let path = Exchanger::path(0, 4, &best_rates);
-        //        assert_eq!(Some(vec![0_usize, 1_usize, 2_usize, 3_usize, 4_usize, 5_usize]), path);
+        // KRAKEN BTC -> GDAX BTC -> GDAX USD and KRAKEN BTC -> KRAKEN USD -> GDAX USD
+        let paths =
+            Exchanger::k_best_limited_paths(&exchange_graph.graph, 4, 0, 3, 5, limits, is_transfer);
+        assert_eq!(vec![vec![0, 2, 3], vec![0, 1, 3]], paths);
+        assert!(paths.iter().all(|path| Exchanger::is_simple_path(path)));
     }
 
-    //    #[test]
+    #[test]
+    fn test_split_paths_fill_the_capacity_of_the_best_path_first() {
+        // two parallel edges 0 -> 1 with a capacity of 1.0 each
+        let edge = |from_node, to_node, weight| SearchEdge {
+            from_node,
+            to_node,
+            weight,
+            transfers: 0,
+            reverse: false,
+        };
+        let edges = vec![edge(0, 1, 2.0), edge(0, 2, 1.5), edge(2, 1, 1.0)];
+        let edge_output = |edge: &SearchEdge, sent: f64, amount: f64| {
+            if edge.from_node == 0 && sent + amount > 1.0 + 1e-9 {
+                None
+            } else {
+                Some(amount * edge.weight)
+            }
+        };
+        let is_close = |split_path: &SplitPath, input: f64, output: f64| {
+            (split_path.input - input).abs() < 1e-6 && (split_path.output - output).abs() < 1e-6
+        };
+
+        let split_paths = Exchanger::split_paths(
+            3,
+            &edges,
+            (0, 2.0),
+            1,
+            PathLimits::default(),
+            4,
+            edge_output,
+        );
+
+        assert_eq!(2, split_paths.len());
+        assert_eq!(vec![0, 1], split_paths[0].path);
+        assert!(is_close(&split_paths[0], 1.0, 2.0));
+        assert_eq!(vec![0, 2, 1], split_paths[1].path);
+        assert!(is_close(&split_paths[1], 1.0, 1.5));
+
+        // there is no capacity for the whole amount, the rest of it isn't sent
+        let split_paths = Exchanger::split_paths(
+            3,
+            &edges,
+            (0, 3.0),
+            1,
+            PathLimits::default(),
+            4,
+            edge_output,
+        );
+        assert_eq!(2, split_paths.len());
+        assert!(is_close(&split_paths[1], 1.0, 1.5));
+
+        // an edge taking less than a chunk is still used
+        let small_edge_output = |edge: &SearchEdge, sent: f64, amount: f64| {
+            if edge.from_node == 0 && sent + amount > 0.01 + 1e-9 {
+                None
+            } else {
+                Some(amount * edge.weight)
+            }
+        };
+        let split_paths = Exchanger::split_paths(
+            3,
+            &edges,
+            (0, 2.0),
+            1,
+            PathLimits::default(),
+            4,
+            small_edge_output,
+        );
+        assert_eq!(2, split_paths.len());
+        assert!(is_close(&split_paths[0], 0.01, 0.02));
+    }
+
+    #[test]
+    fn test_split_paths_reroute_what_a_greedy_split_sends_first() {
+        // 0 -> 1 -> 2 -> 3 has the best rate but takes the capacity of both of the other paths,
+        // 0 -> 1 -> 3 and 0 -> 2 -> 3, which together take the whole amount.
+        // Sending over the best path first only fills 1.0 / 1.2 + (1.0 - 1.0 / 1.2)
+        // and gets 1.0 + 1.0 / 6.0 out of the 2.0
+        let edge = |from_node, to_node, weight| SearchEdge {
+            from_node,
+            to_node,
+            weight,
+            transfers: 0,
+            reverse: false,
+        };
+        let edges = vec![
+            edge(0, 1, 1.0),
+            edge(0, 2, 1.0),
+            edge(1, 2, 1.2),
+            edge(1, 3, 1.0),
+            edge(2, 3, 1.0),
+        ];
+        // each edge takes at most 1.0
+        let edge_output = |edge: &SearchEdge, sent: f64, amount: f64| {
+            if sent + amount > 1.0 + 1e-9 {
+                None
+            } else {
+                Some(amount * edge.weight)
+            }
+        };
+
+        let split_paths = Exchanger::split_paths(
+            4,
+            &edges,
+            (0, 2.0),
+            3,
+            PathLimits::default(),
+            50,
+            edge_output,
+        );
+
+        let mut paths: Vec<&[usize]> = split_paths
+            .iter()
+            .map(|split_path| split_path.path.as_slice())
+            .collect();
+        paths.sort();
+        assert_eq!(vec![&[0, 1, 3][..], &[0, 2, 3][..]], paths);
+
+        let input: f64 = split_paths.iter().map(|split_path| split_path.input).sum();
+        let output: f64 = split_paths.iter().map(|split_path| split_path.output).sum();
+        assert!((input - 2.0).abs() < 1e-6);
+        assert!((output - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_all_profitable_cycles_are_found() {
+        let price_updates = vec![
+            PriceUpdate::new(Utc::now(), "KRAKEN", "BTC", "USD", 1000.0, 0.0009),
+            PriceUpdate::new(Utc::now(), "GDAX", "BTC", "USD", 1200.0, 0.0008),
+            PriceUpdate::new(Utc::now(), "GDAX", "USD", "EUR", 0.9, 1.2),
+        ];
+        // 0 KRAKEN BTC
+        // 1 KRAKEN USD
+        // 2 GDAX BTC
+        // 3 GDAX USD
+        // 4 GDAX EUR
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph = &graph_handler.exchange_graph.graph;
+
+        let cycles = Exchanger::profitable_cycles(graph, 5, 1.0, 4);
+
+        // KRAKEN BTC -> GDAX BTC -> GDAX USD -> KRAKEN USD -> KRAKEN BTC: 1200 * 0.0009 = 1.08
+        // GDAX USD -> GDAX EUR -> GDAX USD: 0.9 * 1.2 = 1.08
+        assert_eq!(
+            vec![
+                Arbitrage {
+                    cycle: vec![0, 2, 3, 1, 0]
+                },
+                Arbitrage {
+                    cycle: vec![3, 4, 3]
+                },
+            ],
+            cycles
+        );
+
+        // with the minimum rate above the profit, there are no cycles
+        assert!(Exchanger::profitable_cycles(graph, 5, 1.1, 4).is_empty());
+
+        // the cycle through both of the exchanges is too long
+        assert_eq!(
+            vec![Arbitrage {
+                cycle: vec![3, 4, 3]
+            }],
+            Exchanger::profitable_cycles(graph, 5, 1.0, 3)
+        );
+    }
+
+    #[test]
+    fn test_profitable_cycles_are_bounded_over_many_exchanges() {
+        // the BTC and the USD of all the exchanges can be transferred between each other,
+        // so the graph has more simple cycles than could ever be listed
+        let mut price_updates: Vec<PriceUpdate> = (0..40)
+            .map(|exchange| {
+                let exchange = format!("EXCHANGE{}", exchange);
+                PriceUpdate::new(Utc::now(), &exchange, "BTC", "USD", 1000.0, 0.00099)
+            })
+            .collect();
+        // only selling the BTC bought on another exchange here makes money
+        price_updates.push(PriceUpdate::new(
+            Utc::now(),
+            "EXPENSIVE",
+            "BTC",
+            "USD",
+            1100.0,
+            0.0009,
+        ));
+        let graph_handler: GraphHandler = GraphHandler::from(price_updates);
+        let graph = &graph_handler.exchange_graph.graph;
+        let nodes_count = graph_handler.exchange_graph.index_map.index_bound();
+
+        // USD -> BTC on one of the 40 exchanges, then to EXPENSIVE and back
+        let cycles = Exchanger::profitable_cycles(graph, nodes_count, 1.0, 4);
+        assert_eq!(40, cycles.len());
+        for arbitrage in &cycles {
+            assert_eq!(5, arbitrage.cycle.len());
+            assert!((Exchanger::path_rate(graph, &arbitrage.cycle) - 1.089).abs() < 1e-9);
+        }
+    }
+
+    #[test]
     fn let_us_test() {
         let price_updates = vec![
             // KRAKEN USD -> LIT
@@ -445,18 +1994,14 @@ mod test {
         let from_node = graph_handler
             .exchange_graph
             .index_map
-            .get(&("KRAKEN".to_owned(), "USD".to_owned()))
+            .get(&("KRAKEN".into(), "USD".into()))
             .unwrap();
         let to_node = graph_handler
             .exchange_graph
             .index_map
-            .get(&("GDAX".to_owned(), "LIT".to_owned()))
+            .get(&("GDAX".into(), "LIT".into()))
             .unwrap();
 
-        let next = Exchanger::best_rates(&graph_handler);
-
-        let path = Exchanger::path(*from_node, *to_node, &next);
-
         // KRAKEN USD - 0
         // KRAKEN LIT - 1
         // KRAKEN EUR - 2
@@ -466,7 +2011,16 @@ mod test {
         // GDAX EUR - 6
         // GDAX LIT - 7
 
-        assert_eq!(Some(vec![0, 2, 3, 4, 5]), path);
+        // GDAX EUR -> GDAX ETH -> GDAX EUR is a profitable cycle: 0.012 * 85 > 1
+        // and GDAX EUR -> GDAX LIT -> GDAX EUR is a profitable cycle too: 0.5 * 2.04 > 1
+        let graph = &graph_handler.exchange_graph.graph;
+        let arbitrage = Exchanger::log_best_paths(graph, 8, *from_node).unwrap_err();
+
+        assert!(arbitrage.cycle.contains(&6));
+        assert!(Exchanger::path_rate(graph, &arbitrage.cycle) > 1.0);
+
+        // the destination is not reachable from the cycle, but can't be answered either
+        assert!(Exchanger::log_best_paths(graph, 8, *to_node).is_err());
     }
 
     // (KRAKEN, BTC) -> (KRAKEN, USD) 1000 - 0.0009
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalErrorKind {
+    Io(io::ErrorKind),
+    /// The record with this sequence is damaged, but it is not the last one
+    CorruptRecord(u64),
+    /// The record with this sequence is not a command the handler can replay
+    InvalidRecord(u64),
+    /// The records up to the sequence of the state are not in the log
+    MissingRecords,
+    /// A failed append couldn't be cut off the log, so nothing more is appended to it
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalError(pub WalErrorKind);
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            WalErrorKind::Io(kind) => write!(f, "Log file error: {:?}", kind),
+            WalErrorKind::CorruptRecord(sequence) => {
+                write!(f, "Corrupt log record {}", sequence)
+            }
+            WalErrorKind::InvalidRecord(sequence) => {
+                write!(f, "Invalid log record {}", sequence)
+            }
+            WalErrorKind::MissingRecords => "The log ends before the state".fmt(f),
+            WalErrorKind::Failed => "The log failed and can't be appended to".fmt(f),
+        }
+    }
+}
+
+impl From<io::Error> for WalError {
+    fn from(error: io::Error) -> Self {
+        WalError(WalErrorKind::Io(error.kind()))
+    }
+}
+
+/// A record of the log, the command line of an accepted update or admin command,
+/// or the state of a loaded snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub sequence: u64,
+    pub command: String,
+}
+
+/// An append-only log of the commands which changed the state of a `GraphHandler`.
+/// Each record is a line `<sequence> <checksum> <command>`, the sequences start at 1
+/// and every record is synced to the disk before the command is answered
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    file: File,
+    path: PathBuf,
+    sequence: u64,
+    /// The length of the file up to the end of the last record
+    length: u64,
+    failed: bool,
+}
+
+impl WriteAheadLog {
+    /// Opens or creates the log, returns it with its records. A torn last record,
+    /// left by a crash while appending it, is cut off the file,
+    /// any other damaged record is an error
+    pub fn open(path: &Path) -> Result<(Self, Vec<Record>), WalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut records = Vec::new();
+        let mut valid_length = 0;
+        let mut lines = contents.split_inclusive(|&byte| byte == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            let sequence = records.len() as u64 + 1;
+            let is_last = lines.peek().is_none();
+
+            match parse_record(line) {
+                Some(record) if record.sequence == sequence => {
+                    valid_length += line.len();
+                    records.push(record);
+                }
+                _ if is_last => break,
+                _ => return Err(WalError(WalErrorKind::CorruptRecord(sequence))),
+            }
+        }
+
+        if valid_length < contents.len() {
+            file.set_len(valid_length as u64)?;
+            file.sync_data()?;
+        }
+
+        let log = Self {
+            file,
+            path: path.to_owned(),
+            sequence: records.len() as u64,
+            length: valid_length as u64,
+            failed: false,
+        };
+
+        Ok((log, records))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The sequence of the last record, 0 for an empty log
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Appends the command and syncs it to the disk, returns the sequence of its record.
+    /// When either fails, what was written of the record is cut off, so the log neither
+    /// has a torn record before the next ones nor replays a command which wasn't applied
+    pub fn append(&mut self, command: &dyn fmt::Display) -> Result<u64, WalError> {
+        if self.failed {
+            return Err(WalError(WalErrorKind::Failed));
+        }
+
+        let record = Record {
+            sequence: self.sequence + 1,
+            command: command.to_string(),
+        };
+        let bytes = format_record(&record);
+
+        let written = self
+            .file
+            .write_all(bytes.as_bytes())
+            .and_then(|()| self.file.sync_data());
+        if let Err(error) = written {
+            self.cut_back();
+            return Err(error.into());
+        }
+
+        self.sequence = record.sequence;
+        self.length += bytes.len() as u64;
+
+        Ok(self.sequence)
+    }
+
+    /// Cuts the file back to the end of the last record,
+    /// the log fails if it can't be sure the file ends there
+    fn cut_back(&mut self) {
+        if self
+            .file
+            .set_len(self.length)
+            .and_then(|()| self.file.sync_data())
+            .is_err()
+        {
+            self.failed = true;
+        }
+    }
+}
+
+fn format_record(record: &Record) -> String {
+    format!(
+        "{} {:016x} {}\n",
+        record.sequence,
+        checksum(record.sequence, &record.command),
+        record.command
+    )
+}
+
+/// `None` for a line which is not a whole record
+fn parse_record(line: &[u8]) -> Option<Record> {
+    let line = std::str::from_utf8(line.strip_suffix(b"\n")?).ok()?;
+    let mut parts = line.splitn(3, ' ');
+
+    let sequence: u64 = parts.next()?.parse().ok()?;
+    let record_checksum = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let command = parts.next()?;
+
+    if checksum(sequence, command) != record_checksum {
+        return None;
+    }
+
+    Some(Record {
+        sequence,
+        command: command.to_owned(),
+    })
+}
+
+/// FNV-1a of the sequence and the command
+fn checksum(sequence: u64, command: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    format!("{} {}", sequence, command)
+        .bytes()
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn it_drops_a_torn_last_record_and_detects_the_corrupt_ones() {
+        let path = env::temp_dir().join(format!("exchange_rate_path_{}.wal", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(1, log.append(&"MAX_AGE 60").unwrap());
+        assert_eq!(2, log.append(&"VALIDATION_POLICY CLAMP").unwrap());
+        drop(log);
+
+        // a crash while appending the third record
+        let mut contents = fs::read(&path).unwrap();
+        let complete_length = contents.len();
+        contents.extend_from_slice(b"3 0123456789abcdef REMOVE_EXC");
+        fs::write(&path, &contents).unwrap();
+
+        let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+        let commands: Vec<&str> = records
+            .iter()
+            .map(|record| record.command.as_str())
+            .collect();
+        assert_eq!(vec!["MAX_AGE 60", "VALIDATION_POLICY CLAMP"], commands);
+        assert_eq!(complete_length, fs::read(&path).unwrap().len());
+
+        assert_eq!(3, log.append(&"REMOVE_EXCHANGE KRAKEN").unwrap());
+        drop(log);
+        assert_eq!(3, WriteAheadLog::open(&path).unwrap().0.sequence());
+
+        // a damaged record followed by others can't be a torn write
+        let contents = fs::read_to_string(&path)
+            .unwrap()
+            .replace("MAX_AGE 60", "MAX_AGE 90");
+        fs::write(&path, contents).unwrap();
+        assert_eq!(
+            WalError(WalErrorKind::CorruptRecord(1)),
+            WriteAheadLog::open(&path).unwrap_err()
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_failed_append_leaves_no_record_behind() {
+        let path = env::temp_dir().join(format!(
+            "exchange_rate_path_failed_{}.wal",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let (mut log, _) = WriteAheadLog::open(&path).unwrap();
+        log.append(&"MAX_AGE 60").unwrap();
+        let complete_length = fs::read(&path).unwrap().len();
+
+        // the part of a record written before running out of space
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"2 0123456789abcdef VALIDA").unwrap();
+        log.cut_back();
+        assert_eq!(complete_length, fs::read(&path).unwrap().len());
+        assert_eq!(2, log.append(&"VALIDATION_POLICY CLAMP").unwrap());
+
+        // neither the record nor the file can be written, so the log fails
+        let (mut log, records) = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(2, records.len());
+        log.file = File::open(&path).unwrap();
+        assert!(log.append(&"MAX_AGE 90").is_err());
+        assert_eq!(
+            WalError(WalErrorKind::Failed),
+            log.append(&"MAX_AGE 90").unwrap_err()
+        );
+        assert_eq!(2, WriteAheadLog::open(&path).unwrap().1.len());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,3 +4,4 @@ pub use self::parse::parsing::{parse_line, ParseCommandError, ParseErrorKind, Pa
 pub mod display;
 pub mod graph;
 pub mod parse;
+pub mod symbol;
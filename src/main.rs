@@ -1,6 +1,13 @@
+use std::env;
+use std::fs;
 use std::io;
+use std::path::PathBuf;
+use std::thread;
 
+use exchange_rate_path::command::{ExchangeRequest, SnapshotAction};
 use exchange_rate_path::graph::handler::GraphHandler;
+use exchange_rate_path::graph::wal::WalError;
+use exchange_rate_path::parse::validation::Validation;
 use exchange_rate_path::{parse_line, ParsedLine};
 
 fn main() {
@@ -18,16 +25,52 @@ fn main() {
     let _line = "EXCHANGE_RATE_REQUEST KRAKEN BTC EXCI USD";
 
     println!("The Exchange rate path problem solver");
-    let mut handler = GraphHandler::default();
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let mut handler = GraphHandler::with_threads(threads);
 
-    for _ in 0..3 {
+    // `[config] [--snapshot <path>] [--log <path>]`
+    let (mut config_path, mut snapshot_path, mut log_path) = (None, None, None);
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--snapshot" => snapshot_path = args.next().map(PathBuf::from),
+            "--log" => log_path = args.next().map(PathBuf::from),
+            _ => config_path = Some(arg),
+        }
+    }
+
+    // the snapshot replaces the state, the log is replayed from the last record in it
+    if let Some(snapshot_path) = snapshot_path {
+        handler
+            .load_snapshot(&snapshot_path)
+            .expect("Loading the snapshot failed");
+    }
+    if let Some(log_path) = log_path {
+        let replayed_records = handler
+            .open_log(&log_path)
+            .expect("Replaying the log failed");
+        println!("Replayed {} log records", replayed_records);
+    }
+    // the optional configuration file has a command on each line, e.g. `TRANSFER_COST`.
+    // It's applied last, so it overrides the configuration of the snapshot and the log,
+    // and its commands are logged, so a replay doesn't need the file
+    if let Some(config_path) = config_path {
+        load_config(&mut handler, &config_path);
+    }
+
+    loop {
         println!("Enter a command:");
 
         let mut command_line_str = String::new();
-        io::stdin()
+        let read_bytes = io::stdin()
             .read_line(&mut command_line_str)
             .expect("Reading the line failed");
 
+        // end of input
+        if read_bytes == 0 {
+            break;
+        }
+
         let parsed_line = match parse_line(&command_line_str) {
             Ok(parsed_line) => parsed_line,
             Err(command_error) => {
@@ -42,21 +85,218 @@ fn main() {
         };
 
         handle_command(&mut handler, parsed_line);
-        for edge in handler.exchange_graph.graph.all_edges() {
-            dbg!(edge);
-        }
     }
+}
+
+fn load_config(graph_handler: &mut GraphHandler, config_path: &str) {
+    let config = fs::read_to_string(config_path).expect("Reading the configuration file failed");
+
+    // empty lines and lines starting with `#` are skipped
+    let command_lines = config
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
 
-    //    for edge in handler.graph.graph.all_edges() {
-    //        dbg!(edge);
-    //    }
+    for command_line in command_lines {
+        match parse_line(command_line) {
+            Ok(parsed_line) => handle_command(graph_handler, parsed_line),
+            Err(command_error) => println!(
+                "Error '{}' occurred parsing configuration line '{}'",
+                command_error,
+                command_line.trim()
+            ),
+        }
+    }
 }
 
 fn handle_command(graph_handler: &mut GraphHandler, parsed_line: ParsedLine) {
     match parsed_line {
-        ParsedLine::PriceUpdate(price_update) => {
-            graph_handler.handle_update(price_update);
+        ParsedLine::PriceUpdate(price_update) => match graph_handler.handle_update(price_update) {
+            Ok(update_report) => print_validation(update_report.validation, "price update"),
+            Err(log_error) => print_log_error(&log_error),
+        },
+        ParsedLine::ExchangeRequest(exchange_request) => match exchange_request.at {
+            // the graph at the timestamp is built from the price history for the request only
+            Some(timestamp) => {
+                answer_exchange_request(&graph_handler.as_of(timestamp), &exchange_request)
+            }
+            None => answer_exchange_request(graph_handler, &exchange_request),
+        },
+        ParsedLine::ArbitrageRequest(arbitrage_request) => {
+            let profitable_cycles = graph_handler.arbitrages(&arbitrage_request);
+
+            if profitable_cycles.is_empty() {
+                println!(
+                    "No profitable cycles of up to {} edges found",
+                    arbitrage_request.max_length
+                );
+            }
+
+            for profitable_cycle in profitable_cycles {
+                println!("{}", profitable_cycle);
+            }
+        }
+        ParsedLine::TransferCostUpdate(transfer_cost_update) => {
+            if let Err(log_error) = graph_handler.handle_transfer_cost(&transfer_cost_update) {
+                print_log_error(&log_error);
+            }
+        }
+        ParsedLine::TradingFeeUpdate(trading_fee_update) => {
+            if let Err(log_error) = graph_handler.handle_trading_fee(&trading_fee_update) {
+                print_log_error(&log_error);
+            }
+        }
+        ParsedLine::OrderBookUpdate(order_book_update) => {
+            match graph_handler.handle_order_book(order_book_update) {
+                Ok(update_report) => print_validation(update_report.validation, "order book"),
+                Err(log_error) => print_log_error(&log_error),
+            }
+        }
+        ParsedLine::WithdrawalFeeUpdate(withdrawal_fee_update) => {
+            if let Err(log_error) = graph_handler.handle_withdrawal_fee(&withdrawal_fee_update) {
+                print_log_error(&log_error);
+            }
+        }
+        ParsedLine::EdgeLimitUpdate(edge_limit_update) => {
+            if let Err(log_error) = graph_handler.handle_edge_limit(&edge_limit_update) {
+                print_log_error(&log_error);
+            }
         }
-        ParsedLine::ExchangeRequest(_exchange_request) => {}
+        ParsedLine::RemovePairUpdate(remove_pair_update) => {
+            match graph_handler.handle_remove_pair(&remove_pair_update) {
+                Ok(Some(_)) => {}
+                Ok(None) => println!(
+                    "Unknown pair {} {} {}",
+                    remove_pair_update.exchange,
+                    remove_pair_update.currency,
+                    remove_pair_update.other_currency
+                ),
+                Err(log_error) => print_log_error(&log_error),
+            }
+        }
+        ParsedLine::RemoveExchangeUpdate(remove_exchange_update) => {
+            match graph_handler.handle_remove_exchange(&remove_exchange_update) {
+                Ok(removed_nodes) if removed_nodes.is_empty() => {
+                    println!("Unknown exchange {}", remove_exchange_update.exchange)
+                }
+                Ok(_) => {}
+                Err(log_error) => print_log_error(&log_error),
+            }
+        }
+        ParsedLine::SnapshotRequest(snapshot_request) => {
+            let result = match snapshot_request.action {
+                SnapshotAction::Save => graph_handler.save_snapshot(&snapshot_request.path),
+                SnapshotAction::Load => graph_handler.load_snapshot(&snapshot_request.path),
+            };
+
+            if let Err(snapshot_error) = result {
+                println!(
+                    "Error '{}' for the snapshot '{}'",
+                    snapshot_error,
+                    snapshot_request.path.display()
+                );
+            }
+        }
+        ParsedLine::GraphStatsRequest(graph_stats_request) => {
+            println!(
+                "{}",
+                graph_handler.graph_stats(graph_stats_request.top_exchanges)
+            );
+        }
+        ParsedLine::ExportDotRequest(export_dot_request) => {
+            let dot = match graph_handler.export_dot(export_dot_request.highlight.as_ref()) {
+                Ok(dot) => dot,
+                Err(request_error) => {
+                    println!(
+                        "Error '{}' occurred for the highlighted request",
+                        request_error
+                    );
+
+                    return;
+                }
+            };
+
+            match export_dot_request.path {
+                Some(path) => {
+                    if let Err(error) = fs::write(&path, dot) {
+                        println!(
+                            "Error '{}' writing the DOT file '{}'",
+                            error,
+                            path.display()
+                        );
+                    }
+                }
+                None => print!("{}", dot),
+            }
+        }
+        ParsedLine::ValidationPolicyUpdate(validation_policy_update) => {
+            if let Err(log_error) =
+                graph_handler.handle_validation_policy(&validation_policy_update)
+            {
+                print_log_error(&log_error);
+            }
+        }
+        ParsedLine::MaxAgeUpdate(max_age_update) => {
+            if let Err(log_error) = graph_handler.handle_max_age(&max_age_update) {
+                print_log_error(&log_error);
+            }
+        }
+    }
+}
+
+/// The command is not applied when it can't be appended to the log
+/// The warnings and errors of the validation policy for the update
+fn print_validation(validation: Validation, update: &str) {
+    match validation {
+        Validation::Valid => {}
+        Validation::Clamped(error) => println!("Warning '{}', the {} was clamped", error, update),
+        Validation::Warned(error) => println!("Warning '{}' for the {}", error, update),
+        Validation::Rejected(error) => {
+            println!("Error '{}', the {} was rejected", error, update)
+        }
+    }
+}
+
+fn print_log_error(log_error: &WalError) {
+    println!("Error '{}', the command was not applied", log_error);
+}
+
+fn answer_exchange_request(graph_handler: &GraphHandler, exchange_request: &ExchangeRequest) {
+    print_stale_edges(graph_handler);
+
+    let answer = if exchange_request.split {
+        graph_handler
+            .split_route(exchange_request)
+            .map(|split_route| split_route.to_string())
+    } else {
+        graph_handler
+            .best_paths(exchange_request)
+            .map(|best_paths| {
+                best_paths
+                    .iter()
+                    .map(|best_rates| best_rates.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+    };
+
+    match answer {
+        Ok(answer) => println!("{}", answer),
+        Err(request_error) => println!(
+            "Error '{}' occurred for request from ({}, {}) to ({}, {})",
+            request_error,
+            exchange_request.source_exchange,
+            exchange_request.source_currency,
+            exchange_request.destination_exchange,
+            exchange_request.destination_currency
+        ),
+    }
+}
+
+/// Reports the edges the request is not going to use, since their quotes are too old
+fn print_stale_edges(graph_handler: &GraphHandler) {
+    let stale_edges = graph_handler.stale_edges();
+
+    if !stale_edges.edges.is_empty() {
+        println!("{}", stale_edges);
     }
 }
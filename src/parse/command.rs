@@ -1,25 +1,56 @@
+use std::fmt;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::path::PathBuf;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 use crate::parse::parsing::{ParseCommandError, ParseErrorKind};
+use crate::parse::validation::ValidationPolicy;
+use crate::symbol::{CurrencyId, ExchangeId};
 
-#[derive(Debug)]
+/// Only the commands which add to the graph, the price updates and the order books,
+/// hold interned ids. The others hold the names: the configuration interns them
+/// once it's applied and the requests and removals only look them up
+#[derive(Debug, Clone)]
 pub struct PriceUpdate {
     pub timestamp: DateTime<Utc>,
-    pub exchange: String,
-    pub source_currency: String,
-    pub destination_currency: String,
+    pub exchange: ExchangeId,
+    pub source_currency: CurrencyId,
+    pub destination_currency: CurrencyId,
     pub forward_factor: f64,
     pub backward_factor: f64,
 }
 
+/// The pair of an exchange whichever the orientation of its currencies,
+/// they are ordered by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PairKey {
+    pub exchange: ExchangeId,
+    pub currency: CurrencyId,
+    pub other_currency: CurrencyId,
+}
+
+impl PairKey {
+    pub fn new(exchange: ExchangeId, currency: CurrencyId, other_currency: CurrencyId) -> Self {
+        let (currency, other_currency) = if currency <= other_currency {
+            (currency, other_currency)
+        } else {
+            (other_currency, currency)
+        };
+
+        Self {
+            exchange,
+            currency,
+            other_currency,
+        }
+    }
+}
+
+/// Equal updates have equal hashes, the reversed pair as well
 impl Hash for PriceUpdate {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.exchange.hash(state);
-        self.source_currency.hash(state);
-        self.destination_currency.hash(state);
+        self.pair_key().hash(state);
     }
 }
 
@@ -46,14 +77,38 @@ impl PriceUpdate {
     ) -> Self {
         Self {
             timestamp,
-            exchange: exchange.to_owned(),
-            source_currency: source_currency.to_owned(),
-            destination_currency: destination_currency.to_owned(),
+            exchange: ExchangeId::new(exchange),
+            source_currency: CurrencyId::new(source_currency),
+            destination_currency: CurrencyId::new(destination_currency),
             forward_factor,
             backward_factor,
         }
     }
 
+    pub fn pair_key(&self) -> PairKey {
+        PairKey::new(
+            self.exchange,
+            self.source_currency,
+            self.destination_currency,
+        )
+    }
+
+    /// The same update with the currencies in the order of its `PairKey`,
+    /// the factors are swapped along with the reversed currencies
+    pub fn normalized(self) -> Self {
+        if self.source_currency <= self.destination_currency {
+            return self;
+        }
+
+        Self {
+            source_currency: self.destination_currency,
+            destination_currency: self.source_currency,
+            forward_factor: self.backward_factor,
+            backward_factor: self.forward_factor,
+            ..self
+        }
+    }
+
     pub fn is_eq_and_newer(&self, rhs: &Self) -> bool {
         self.eq(rhs) && self.timestamp > rhs.timestamp
     }
@@ -76,200 +131,1674 @@ impl PriceUpdate {
         };
 
         let timestamp = parse_timestamp(input_slice[0])?;
-
-        let exchange: String = input_slice[1].to_string();
-        let source_currency: String = input_slice[2].to_string();
-        let destination_currency: String = input_slice[3].to_string();
         let forward_factor: f64 = parse_float(input_slice[4])?;
         let backward_factor: f64 = parse_float(input_slice[5])?;
 
+        // the names are interned only once the whole line is parsed
         Ok(Self {
             timestamp,
-            exchange,
-            source_currency,
-            destination_currency,
+            exchange: ExchangeId::new(input_slice[1]),
+            source_currency: CurrencyId::new(input_slice[2]),
+            destination_currency: CurrencyId::new(input_slice[3]),
             forward_factor,
             backward_factor,
         })
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The price update line, `parse_line` reads it back
+impl fmt::Display for PriceUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            self.timestamp.to_rfc3339(),
+            self.exchange,
+            self.source_currency,
+            self.destination_currency,
+            self.forward_factor,
+            self.backward_factor
+        )
+    }
+}
+
+/// The names are only looked up, a request doesn't intern them
+#[derive(Debug, Default, PartialEq)]
 pub struct ExchangeRequest {
     pub source_exchange: String,
     pub source_currency: String,
     pub destination_exchange: String,
     pub destination_currency: String,
+    /// How many of the best paths to return, only the best one if not set.
+    /// There is only the best path for an amount
+    pub paths_count: Option<usize>,
+    pub max_hops: Option<usize>,
+    /// The maximum number of transfers of a currency between exchanges
+    pub max_exchange_transfers: Option<usize>,
+    /// The amount of the source currency to exchange, walking the order books of the pairs
+    pub amount: Option<f64>,
+    /// Split the amount over several paths to get the most out of it
+    pub split: bool,
+    /// Solve against the graph as it was at the timestamp
+    pub at: Option<DateTime<Utc>>,
 }
 
 impl ExchangeRequest {
     pub const COMMAND_PREFIX: &'static str = "EXCHANGE_RATE_REQUEST";
+    pub const MAX_HOPS: &'static str = "MAX_HOPS";
+    pub const MAX_TRANSFERS: &'static str = "MAX_TRANSFERS";
+    pub const AMOUNT: &'static str = "AMOUNT";
+    pub const SPLIT: &'static str = "SPLIT";
+    pub const AT: &'static str = "AT";
 
+    /// `EXCHANGE_RATE_REQUEST <exchange> <currency> <exchange> <currency> [paths count]`
+    /// followed by any of the `MAX_HOPS <n>`, `MAX_TRANSFERS <n>`, `AMOUNT <x>` and
+    /// `AT <timestamp>` options and the `SPLIT` flag, which needs an amount.
+    /// A request at a timestamp can't have an amount
     pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
-        if input_slice.len() != 5 {
+        if input_slice.len() < 5 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+        }
+
+        let parse_integer = |input: &str| -> Result<usize, ParseCommandError> {
+            input
+                .parse()
+                .map_err(|_| ParseCommandError(ParseErrorKind::IntegerParsing))
+        };
+
+        let mut options = &input_slice[5..];
+        let mut paths_count = None;
+        if let Some(&first_option) = options.first() {
+            if !Self::is_option(first_option) {
+                match parse_integer(first_option)? {
+                    0 => return Err(ParseCommandError(ParseErrorKind::IntegerParsing)),
+                    count => paths_count = Some(count),
+                }
+                options = &options[1..];
+            }
+        }
+
+        let (mut max_hops, mut max_exchange_transfers, mut amount) = (None, None, None);
+        let (mut split, mut at) = (false, None);
+        while let Some((&option, rest)) = options.split_first() {
+            if option == Self::SPLIT {
+                split = true;
+                options = rest;
+                continue;
+            }
+
+            let value = rest
+                .first()
+                .ok_or(ParseCommandError(ParseErrorKind::RequiredArgumentsCount))?;
+
+            match option {
+                Self::MAX_HOPS => max_hops = Some(parse_integer(value)?),
+                Self::MAX_TRANSFERS => max_exchange_transfers = Some(parse_integer(value)?),
+                Self::AMOUNT => match value.parse::<f64>() {
+                    Ok(value) if value.is_finite() && value > 0.0 => amount = Some(value),
+                    _ => return Err(ParseCommandError(ParseErrorKind::FloatParsing)),
+                },
+                Self::AT => {
+                    let timestamp = value
+                        .parse::<DateTime<Utc>>()
+                        .map_err(|_| ParseCommandError(ParseErrorKind::TimestampParsing))?;
+                    at = Some(timestamp);
+                }
+                _ => return Err(ParseCommandError(ParseErrorKind::StringParsing)),
+            }
+            options = &rest[1..];
+        }
+
+        if split && amount.is_none() {
             return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
         }
+        // an amount is exchanged only over the best path
+        if amount.is_some() && paths_count.is_some_and(|count| count > 1) {
+            return Err(ParseCommandError(ParseErrorKind::ConflictingOptions));
+        }
+        // the history has neither the order books nor the past fees and limits,
+        // so an amount can't be exchanged as it would have been at the timestamp
+        if amount.is_some() && at.is_some() {
+            return Err(ParseCommandError(ParseErrorKind::ConflictingOptions));
+        }
 
-        let source_exchange: String = input_slice[1].to_string();
-        let source_currency: String = input_slice[2].to_string();
+        let source_exchange = input_slice[1].to_owned();
+        let source_currency = input_slice[2].to_owned();
 
-        let destination_exchange: String = input_slice[3].to_string();
-        let destination_currency: String = input_slice[4].to_string();
+        let destination_exchange = input_slice[3].to_owned();
+        let destination_currency = input_slice[4].to_owned();
 
         Ok(Self {
             source_exchange,
             source_currency,
             destination_exchange,
             destination_currency,
+            paths_count,
+            max_hops,
+            max_exchange_transfers,
+            amount,
+            split,
+            at,
         })
     }
+
+    fn is_option(input: &str) -> bool {
+        [
+            Self::MAX_HOPS,
+            Self::MAX_TRANSFERS,
+            Self::AMOUNT,
+            Self::SPLIT,
+            Self::AT,
+        ]
+        .contains(&input)
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// `ARBITRAGE_REQUEST [min profit] [max length]`
+#[derive(Debug, PartialEq)]
+pub struct ArbitrageRequest {
+    /// The minimum profit of a cycle, `0.01` means that the cycle should multiply
+    /// the starting amount by more than `1.01`
+    pub min_profit: f64,
+    /// The most edges of a listed cycle, the transfers between the exchanges
+    /// make the number of the longer cycles grow exponentially
+    pub max_length: usize,
+}
 
-    mod price_update {
-        use chrono::prelude::*;
+impl Default for ArbitrageRequest {
+    fn default() -> Self {
+        Self {
+            min_profit: 0.0,
+            max_length: Self::DEFAULT_MAX_LENGTH,
+        }
+    }
+}
 
-        use super::*;
+impl ArbitrageRequest {
+    pub const COMMAND_PREFIX: &'static str = "ARBITRAGE_REQUEST";
+    pub const DEFAULT_MAX_LENGTH: usize = 6;
 
-        #[test]
-        fn price_update_equality() {
-            let timestamp = Utc.ymd(2017, 12, 12).and_hms(5, 30, 0);
-            let comparison_update =
-                PriceUpdate::new(timestamp.clone(), "EX1", "C1", "C2", 1.0, 2.0);
-            // it should be affected by the forward and backward factors
-            let equal_update = PriceUpdate::new(timestamp, "EX1", "C1", "C2", 5.0, 6.0);
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() > 3 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+        }
 
-            assert_eq!(equal_update, comparison_update);
+        let min_profit: f64 = match input_slice.get(1) {
+            Some(min_profit) => min_profit
+                .parse()
+                .map_err(|_| ParseCommandError(ParseErrorKind::FloatParsing))?,
+            None => 0.0,
+        };
+        if !min_profit.is_finite() || min_profit < 0.0 {
+            return Err(ParseCommandError(ParseErrorKind::ProfitRange));
+        }
 
-            // It shouldn't be affected by different time
-            assert_eq!(
-                PriceUpdate::new(Utc::now(), "EX1", "C1", "C2", 5.0, 6.0),
-                comparison_update
-            );
+        let max_length = match input_slice.get(2) {
+            Some(max_length) => match max_length.parse() {
+                Ok(0) | Err(_) => return Err(ParseCommandError(ParseErrorKind::IntegerParsing)),
+                Ok(max_length) => max_length,
+            },
+            None => Self::DEFAULT_MAX_LENGTH,
+        };
 
-            // It shouldn't be affected if source and destination have switched places
-            assert_eq!(
-                PriceUpdate::new(Utc::now(), "EX1", "C2", "C1", 5.0, 6.0),
-                comparison_update
-            );
+        Ok(Self {
+            min_profit,
+            max_length,
+        })
+    }
+}
 
-            // different exchange
-            assert_ne!(
-                PriceUpdate::new(timestamp, "DIFF", "C1", "C2", 5.0, 6.0),
-                comparison_update
-            );
+/// A price level of an order book, the quantity is in the base currency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookLevel {
+    pub price: f64,
+    pub quantity: f64,
+}
 
-            // different source currency
-            assert_ne!(
-                PriceUpdate::new(timestamp, "EX1", "SOURCE", "C2", 5.0, 6.0),
-                comparison_update
-            );
+/// `ORDER_BOOK <timestamp> <exchange> <base currency> <quote currency> <bids> <asks>`,
+/// each side is a list of `price@quantity` levels separated by `,`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBookUpdate {
+    pub timestamp: DateTime<Utc>,
+    pub exchange: ExchangeId,
+    pub base_currency: CurrencyId,
+    pub quote_currency: CurrencyId,
+    /// The best (highest) price first
+    pub bids: Vec<BookLevel>,
+    /// The best (lowest) price first
+    pub asks: Vec<BookLevel>,
+}
 
-            // different destination currency
-            assert_ne!(
-                PriceUpdate::new(timestamp, "EX1", "C1", "DEST", 5.0, 6.0),
-                comparison_update
-            );
+impl OrderBookUpdate {
+    pub const COMMAND_PREFIX: &'static str = "ORDER_BOOK";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 7 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
         }
 
-        #[test]
-        fn price_update_valid_input() {
-            let timestamp_str = "2017-11-01T09:42:23+00:00";
-            let timestamp = timestamp_str.parse::<DateTime<Utc>>().unwrap();
+        let timestamp = input_slice[1]
+            .parse::<DateTime<Utc>>()
+            .map_err(|_| ParseCommandError(ParseErrorKind::TimestampParsing))?;
 
-            assert_eq!(
-                Ok(PriceUpdate {
-                    timestamp,
-                    exchange: "KRAKEN".to_owned(),
-                    source_currency: "BTC".to_owned(),
-                    destination_currency: "USD".to_owned(),
-                    forward_factor: 1000.0,
-                    backward_factor: 0.0009,
-                }),
-                PriceUpdate::from_input(&vec![
-                    timestamp_str,
-                    "KRAKEN",
-                    "BTC",
-                    "USD",
-                    "1000.0",
-                    "0.0009"
-                ])
-            );
+        let mut bids = Self::parse_levels(input_slice[5])?;
+        let mut asks = Self::parse_levels(input_slice[6])?;
+        bids.sort_by(|left, right| right.price.total_cmp(&left.price));
+        asks.sort_by(|left, right| left.price.total_cmp(&right.price));
+
+        Ok(Self {
+            timestamp,
+            exchange: ExchangeId::new(input_slice[2]),
+            base_currency: CurrencyId::new(input_slice[3]),
+            quote_currency: CurrencyId::new(input_slice[4]),
+            bids,
+            asks,
+        })
+    }
+
+    fn parse_levels(input: &str) -> Result<Vec<BookLevel>, ParseCommandError> {
+        let level_error = ParseCommandError(ParseErrorKind::BookLevelParsing);
+
+        input
+            .split(',')
+            .map(|level| {
+                let mut price_quantity = level.splitn(2, '@');
+                let (price, quantity) = match (price_quantity.next(), price_quantity.next()) {
+                    (Some(price), Some(quantity)) => (price, quantity),
+                    _ => return Err(level_error),
+                };
+
+                let parse_positive = |input: &str| match input.parse::<f64>() {
+                    Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+                    _ => Err(level_error),
+                };
+
+                Ok(BookLevel {
+                    price: parse_positive(price)?,
+                    quantity: parse_positive(quantity)?,
+                })
+            })
+            .collect()
+    }
+
+    /// The top of the book as a price update of the pair
+    pub fn price_update(&self) -> PriceUpdate {
+        PriceUpdate {
+            timestamp: self.timestamp,
+            exchange: self.exchange,
+            source_currency: self.base_currency,
+            destination_currency: self.quote_currency,
+            forward_factor: self.bids[0].price,
+            backward_factor: 1.0 / self.asks[0].price,
         }
+    }
+}
 
-        #[test]
-        fn price_update_wrong_arguments_count() {
-            let error_arguments_count =
-                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+impl fmt::Display for OrderBookUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let levels = |levels: &[BookLevel]| {
+            levels
+                .iter()
+                .map(|level| format!("{}@{}", level.price, level.quantity))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
 
-            assert_eq!(error_arguments_count, PriceUpdate::from_input(&[]));
-            assert_eq!(
-                error_arguments_count,
-                PriceUpdate::from_input(&["1", "2", "3", "4", "5"])
-            );
-            assert_eq!(
-                error_arguments_count,
-                PriceUpdate::from_input(&["1", "2", "3", "4", "5", "6", "7"])
-            );
+        write!(
+            f,
+            "{} {} {} {} {} {} {}",
+            Self::COMMAND_PREFIX,
+            self.timestamp.to_rfc3339(),
+            self.exchange,
+            self.base_currency,
+            self.quote_currency,
+            levels(&self.bids),
+            levels(&self.asks)
+        )
+    }
+}
+
+/// A fee in percent, from `0` up to `100`
+fn parse_percent_fee(input: &str) -> Result<f64, ParseCommandError> {
+    match parse_fee(input)? {
+        percent_fee if percent_fee < 100.0 => Ok(percent_fee),
+        _ => Err(ParseCommandError(ParseErrorKind::FeeRange)),
+    }
+}
+
+/// A finite and non-negative fee
+fn parse_fee(input: &str) -> Result<f64, ParseCommandError> {
+    let fee: f64 = input
+        .parse()
+        .map_err(|_| ParseCommandError(ParseErrorKind::FloatParsing))?;
+
+    if fee.is_finite() && fee >= 0.0 {
+        Ok(fee)
+    } else {
+        Err(ParseCommandError(ParseErrorKind::FeeRange))
+    }
+}
+
+/// `TRANSFER_COST <from exchange> <to exchange> <currency> <percent fee> [fixed fee]`
+#[derive(Debug, PartialEq)]
+pub struct TransferCostUpdate {
+    pub from_exchange: String,
+    pub to_exchange: String,
+    pub currency: String,
+    pub percent_fee: f64,
+    pub fixed_fee: f64,
+}
+
+impl TransferCostUpdate {
+    pub const COMMAND_PREFIX: &'static str = "TRANSFER_COST";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 5 && input_slice.len() != 6 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
         }
 
-        #[test]
-        fn price_update_wrong_timestamp() {
-            assert_eq!(
-                Err(ParseCommandError(ParseErrorKind::TimestampParsing)),
-                PriceUpdate::from_input(&["1", "Exchange", "ETH", "EUR", "5.0", "6.0"])
-            );
+        let percent_fee = parse_percent_fee(input_slice[4])?;
+        let fixed_fee = match input_slice.get(5) {
+            Some(fixed_fee) => parse_fee(fixed_fee)?,
+            None => 0.0,
+        };
+
+        Ok(Self {
+            from_exchange: input_slice[1].to_string(),
+            to_exchange: input_slice[2].to_string(),
+            currency: input_slice[3].to_string(),
+            percent_fee,
+            fixed_fee,
+        })
+    }
+}
+
+impl fmt::Display for TransferCostUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            Self::COMMAND_PREFIX,
+            self.from_exchange,
+            self.to_exchange,
+            self.currency,
+            self.percent_fee,
+            self.fixed_fee
+        )
+    }
+}
+
+/// `WITHDRAWAL_FEE <exchange> <currency> <fee>`, the fee is an amount of the currency
+#[derive(Debug, PartialEq)]
+pub struct WithdrawalFeeUpdate {
+    pub exchange: String,
+    pub currency: String,
+    pub fee: f64,
+}
+
+impl WithdrawalFeeUpdate {
+    pub const COMMAND_PREFIX: &'static str = "WITHDRAWAL_FEE";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 4 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
         }
 
-        #[test]
-        fn price_update_wrong_floats() {
-            let float_error = Err(ParseCommandError(ParseErrorKind::FloatParsing));
-            let forward_factor_wrong = [
-                "2017-11-01T09:42:23+00:00",
-                "Exchange",
-                "ETH",
-                "EUR",
-                "not a float",
-                "6.0",
-            ];
-            assert_eq!(float_error, PriceUpdate::from_input(&forward_factor_wrong));
+        Ok(Self {
+            exchange: input_slice[1].to_string(),
+            currency: input_slice[2].to_string(),
+            fee: parse_fee(input_slice[3])?,
+        })
+    }
+}
 
-            let backward_factor_wrong = [
-                "2017-11-01T09:42:23+00:00",
-                "Exchange",
-                "ETH",
-                "EUR",
-                "5.0",
-                "not a float",
-            ];
+impl fmt::Display for WithdrawalFeeUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            Self::COMMAND_PREFIX,
+            self.exchange,
+            self.currency,
+            self.fee
+        )
+    }
+}
 
-            assert_eq!(float_error, PriceUpdate::from_input(&backward_factor_wrong));
+/// `MAX_AGE <seconds> [exchange]`, the quotes older than the max age are left out of the
+/// searches. Without an exchange it's the max age of all the exchanges without their own
+#[derive(Debug, PartialEq)]
+pub struct MaxAgeUpdate {
+    pub max_age: Duration,
+    pub exchange: Option<String>,
+}
+
+impl MaxAgeUpdate {
+    pub const COMMAND_PREFIX: &'static str = "MAX_AGE";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 2 && input_slice.len() != 3 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
         }
+
+        let seconds = input_slice[1]
+            .parse::<u32>()
+            .map_err(|_| ParseCommandError(ParseErrorKind::IntegerParsing))?;
+
+        Ok(Self {
+            max_age: Duration::seconds(i64::from(seconds)),
+            exchange: input_slice.get(2).map(|exchange| exchange.to_string()),
+        })
     }
+}
 
-    mod exchange_request {
-        use super::*;
+impl fmt::Display for MaxAgeUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", Self::COMMAND_PREFIX, self.max_age.num_seconds())?;
 
-        #[test]
-        fn exchange_request_valid_input() {
-            assert_eq!(
-                Ok(ExchangeRequest {
-                    source_exchange: "LACHO".to_owned(),
-                    source_currency: "BTC".to_owned(),
-                    destination_exchange: "KRAKEN".to_owned(),
-                    destination_currency: "USD".to_owned(),
-                }),
-                ExchangeRequest::from_input(&vec![
-                    ExchangeRequest::COMMAND_PREFIX,
-                    "LACHO",
-                    "BTC",
-                    "KRAKEN",
-                    "USD",
-                ])
-            );
+        match &self.exchange {
+            Some(exchange) => write!(f, " {}", exchange),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `REMOVE_PAIR <exchange> <currency> <currency> [timestamp]`, the exchange delisted the pair.
+/// The names are only looked up, removing an unknown pair doesn't intern them
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemovePairUpdate {
+    pub exchange: String,
+    pub currency: String,
+    pub other_currency: String,
+    /// When the pair was delisted, now if not set
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl RemovePairUpdate {
+    pub const COMMAND_PREFIX: &'static str = "REMOVE_PAIR";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        let timestamp = match input_slice.len() {
+            4 => None,
+            5 => Some(parse_timestamp(input_slice[4])?),
+            _ => return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+        };
+
+        Ok(Self {
+            exchange: input_slice[1].to_owned(),
+            currency: input_slice[2].to_owned(),
+            other_currency: input_slice[3].to_owned(),
+            timestamp,
+        })
+    }
+}
+
+impl fmt::Display for RemovePairUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            Self::COMMAND_PREFIX,
+            self.exchange,
+            self.currency,
+            self.other_currency
+        )?;
+
+        match self.timestamp {
+            Some(timestamp) => write!(f, " {}", timestamp.to_rfc3339()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `REMOVE_EXCHANGE <exchange> [timestamp]`, the exchange is gone with all of its pairs.
+/// The name is only looked up, removing an unknown exchange doesn't intern it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoveExchangeUpdate {
+    pub exchange: String,
+    /// When the exchange was gone, now if not set
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl RemoveExchangeUpdate {
+    pub const COMMAND_PREFIX: &'static str = "REMOVE_EXCHANGE";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        let timestamp = match input_slice.len() {
+            2 => None,
+            3 => Some(parse_timestamp(input_slice[2])?),
+            _ => return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+        };
+
+        Ok(Self {
+            exchange: input_slice[1].to_owned(),
+            timestamp,
+        })
+    }
+}
+
+impl fmt::Display for RemoveExchangeUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", Self::COMMAND_PREFIX, self.exchange)?;
+
+        match self.timestamp {
+            Some(timestamp) => write!(f, " {}", timestamp.to_rfc3339()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_timestamp(input: &str) -> Result<DateTime<Utc>, ParseCommandError> {
+    input
+        .parse::<DateTime<Utc>>()
+        .map_err(|_| ParseCommandError(ParseErrorKind::TimestampParsing))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotAction {
+    Save,
+    Load,
+}
+
+/// `SNAPSHOT <SAVE|LOAD> <path>`, writes the state of the graph to the file or restores it
+#[derive(Debug, PartialEq)]
+pub struct SnapshotRequest {
+    pub action: SnapshotAction,
+    pub path: PathBuf,
+}
+
+impl SnapshotRequest {
+    pub const COMMAND_PREFIX: &'static str = "SNAPSHOT";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 3 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+        }
+
+        let action = match input_slice[1] {
+            "SAVE" => SnapshotAction::Save,
+            "LOAD" => SnapshotAction::Load,
+            _ => return Err(ParseCommandError(ParseErrorKind::StringParsing)),
+        };
+
+        Ok(Self {
+            action,
+            path: PathBuf::from(input_slice[2]),
+        })
+    }
+}
+
+impl fmt::Display for SnapshotRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            SnapshotAction::Save => "SAVE",
+            SnapshotAction::Load => "LOAD",
+        };
+
+        write!(
+            f,
+            "{} {} {}",
+            Self::COMMAND_PREFIX,
+            action,
+            self.path.display()
+        )
+    }
+}
+
+/// `GRAPH_STATS [top exchanges count]`, the size of the graph and the age of its quotes
+#[derive(Debug, PartialEq)]
+pub struct GraphStatsRequest {
+    /// How many of the exchanges with the most pairs to report
+    pub top_exchanges: usize,
+}
+
+impl GraphStatsRequest {
+    pub const COMMAND_PREFIX: &'static str = "GRAPH_STATS";
+    pub const DEFAULT_TOP_EXCHANGES: usize = 5;
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        let top_exchanges = match input_slice.len() {
+            1 => Self::DEFAULT_TOP_EXCHANGES,
+            2 => input_slice[1]
+                .parse()
+                .map_err(|_| ParseCommandError(ParseErrorKind::IntegerParsing))?,
+            _ => return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+        };
+
+        Ok(Self { top_exchanges })
+    }
+}
+
+/// `EXPORT_DOT [path] [<exchange> <currency> <exchange> <currency>]`, renders the graph
+/// in the Graphviz DOT format to the file or prints it, highlighting the best path
+/// of the request if there is one
+#[derive(Debug, PartialEq)]
+pub struct ExportDotRequest {
+    pub path: Option<PathBuf>,
+    pub highlight: Option<ExchangeRequest>,
+}
+
+impl ExportDotRequest {
+    pub const COMMAND_PREFIX: &'static str = "EXPORT_DOT";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        let (path, request) = match input_slice.len() {
+            1 => (None, None),
+            2 => (Some(input_slice[1]), None),
+            5 => (None, Some(&input_slice[1..])),
+            6 => (Some(input_slice[1]), Some(&input_slice[2..])),
+            _ => return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+        };
+
+        let highlight = request.map(|request| ExchangeRequest {
+            source_exchange: request[0].into(),
+            source_currency: request[1].into(),
+            destination_exchange: request[2].into(),
+            destination_currency: request[3].into(),
+            ..ExchangeRequest::default()
+        });
+
+        Ok(Self {
+            path: path.map(PathBuf::from),
+            highlight,
+        })
+    }
+}
+
+/// `VALIDATION_POLICY <REJECT|CLAMP|WARN>`, what to do with the price updates which are not valid
+#[derive(Debug, PartialEq)]
+pub struct ValidationPolicyUpdate {
+    pub policy: ValidationPolicy,
+}
+
+impl ValidationPolicyUpdate {
+    pub const COMMAND_PREFIX: &'static str = "VALIDATION_POLICY";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 2 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+        }
+
+        let policy = match input_slice[1] {
+            "REJECT" => ValidationPolicy::Reject,
+            "CLAMP" => ValidationPolicy::Clamp,
+            "WARN" => ValidationPolicy::Warn,
+            _ => return Err(ParseCommandError(ParseErrorKind::StringParsing)),
+        };
+
+        Ok(Self { policy })
+    }
+}
+
+impl fmt::Display for ValidationPolicyUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let policy = match self.policy {
+            ValidationPolicy::Reject => "REJECT",
+            ValidationPolicy::Clamp => "CLAMP",
+            ValidationPolicy::Warn => "WARN",
+        };
+
+        write!(f, "{} {}", Self::COMMAND_PREFIX, policy)
+    }
+}
+
+/// `EDGE_LIMIT <from exchange> <from currency> <to exchange> <to currency> <amount>`,
+/// the maximum amount of the from currency which can be sent over the edge
+#[derive(Debug, PartialEq)]
+pub struct EdgeLimitUpdate {
+    pub from_exchange: String,
+    pub from_currency: String,
+    pub to_exchange: String,
+    pub to_currency: String,
+    pub limit: f64,
+}
+
+impl EdgeLimitUpdate {
+    pub const COMMAND_PREFIX: &'static str = "EDGE_LIMIT";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        if input_slice.len() != 6 {
+            return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+        }
+
+        let limit = input_slice[5]
+            .parse::<f64>()
+            .ok()
+            .filter(|limit| !limit.is_nan() && *limit >= 0.0)
+            .ok_or(ParseCommandError(ParseErrorKind::FloatParsing))?;
+
+        Ok(Self {
+            from_exchange: input_slice[1].to_owned(),
+            from_currency: input_slice[2].to_owned(),
+            to_exchange: input_slice[3].to_owned(),
+            to_currency: input_slice[4].to_owned(),
+            limit,
+        })
+    }
+}
+
+impl fmt::Display for EdgeLimitUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {}",
+            Self::COMMAND_PREFIX,
+            self.from_exchange,
+            self.from_currency,
+            self.to_exchange,
+            self.to_currency,
+            self.limit
+        )
+    }
+}
+
+/// `TRADING_FEE <exchange> <percent fee> [<currency> <currency>]`,
+/// with the currencies the fee is only for their pair
+#[derive(Debug, PartialEq)]
+pub struct TradingFeeUpdate {
+    pub exchange: String,
+    pub percent_fee: f64,
+    pub pair: Option<(String, String)>,
+}
+
+impl TradingFeeUpdate {
+    pub const COMMAND_PREFIX: &'static str = "TRADING_FEE";
+
+    pub fn from_input(input_slice: &[&str]) -> Result<Self, ParseCommandError> {
+        let pair = match input_slice.len() {
+            3 => None,
+            5 => Some((input_slice[3].to_string(), input_slice[4].to_string())),
+            _ => return Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+        };
+
+        Ok(Self {
+            exchange: input_slice[1].to_string(),
+            percent_fee: parse_percent_fee(input_slice[2])?,
+            pair,
+        })
+    }
+}
+
+impl fmt::Display for TradingFeeUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            Self::COMMAND_PREFIX,
+            self.exchange,
+            self.percent_fee
+        )?;
+
+        match &self.pair {
+            Some((currency, other_currency)) => write!(f, " {} {}", currency, other_currency),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod price_update {
+        use std::collections::hash_map::DefaultHasher;
+
+        use chrono::prelude::*;
+
+        use super::*;
+
+        #[test]
+        fn price_update_equality() {
+            let timestamp = Utc.ymd(2017, 12, 12).and_hms(5, 30, 0);
+            let comparison_update = PriceUpdate::new(timestamp, "EX1", "C1", "C2", 1.0, 2.0);
+            // it should be affected by the forward and backward factors
+            let equal_update = PriceUpdate::new(timestamp, "EX1", "C1", "C2", 5.0, 6.0);
+
+            assert_eq!(equal_update, comparison_update);
+
+            // It shouldn't be affected by different time
+            assert_eq!(
+                PriceUpdate::new(Utc::now(), "EX1", "C1", "C2", 5.0, 6.0),
+                comparison_update
+            );
+
+            // It shouldn't be affected if source and destination have switched places
+            assert_eq!(
+                PriceUpdate::new(Utc::now(), "EX1", "C2", "C1", 5.0, 6.0),
+                comparison_update
+            );
+
+            // different exchange
+            assert_ne!(
+                PriceUpdate::new(timestamp, "DIFF", "C1", "C2", 5.0, 6.0),
+                comparison_update
+            );
+
+            // different source currency
+            assert_ne!(
+                PriceUpdate::new(timestamp, "EX1", "SOURCE", "C2", 5.0, 6.0),
+                comparison_update
+            );
+
+            // different destination currency
+            assert_ne!(
+                PriceUpdate::new(timestamp, "EX1", "C1", "DEST", 5.0, 6.0),
+                comparison_update
+            );
+        }
+
+        #[test]
+        fn reversed_pairs_have_the_same_key() {
+            let timestamp = Utc.ymd(2017, 12, 12).and_hms(5, 30, 0);
+            let price_update = PriceUpdate::new(timestamp, "KRAKEN", "USD", "BTC", 0.0009, 1000.0);
+            let reversed_update =
+                PriceUpdate::new(timestamp, "KRAKEN", "BTC", "USD", 1000.0, 0.0009);
+
+            assert_eq!(reversed_update.pair_key(), price_update.pair_key());
+            assert_eq!(
+                PairKey {
+                    exchange: "KRAKEN".into(),
+                    currency: "BTC".into(),
+                    other_currency: "USD".into(),
+                },
+                price_update.pair_key()
+            );
+
+            let hash = |price_update: &PriceUpdate| {
+                let mut hasher = DefaultHasher::new();
+                price_update.hash(&mut hasher);
+                hasher.finish()
+            };
+            assert_eq!(hash(&reversed_update), hash(&price_update));
+
+            let normalized = price_update.normalized();
+            assert_eq!(
+                ("BTC", "USD"),
+                (
+                    normalized.source_currency.as_str(),
+                    normalized.destination_currency.as_str()
+                )
+            );
+            assert_eq!(
+                (1000.0, 0.0009),
+                (normalized.forward_factor, normalized.backward_factor)
+            );
+            assert_eq!(1000.0, reversed_update.clone().normalized().forward_factor);
+        }
+
+        #[test]
+        fn price_update_valid_input() {
+            let timestamp_str = "2017-11-01T09:42:23+00:00";
+            let timestamp = timestamp_str.parse::<DateTime<Utc>>().unwrap();
+
+            assert_eq!(
+                Ok(PriceUpdate {
+                    timestamp,
+                    exchange: "KRAKEN".into(),
+                    source_currency: "BTC".into(),
+                    destination_currency: "USD".into(),
+                    forward_factor: 1000.0,
+                    backward_factor: 0.0009,
+                }),
+                PriceUpdate::from_input(&[
+                    timestamp_str,
+                    "KRAKEN",
+                    "BTC",
+                    "USD",
+                    "1000.0",
+                    "0.0009"
+                ])
+            );
+        }
+
+        #[test]
+        fn price_update_wrong_arguments_count() {
+            let error_arguments_count =
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount));
+
+            assert_eq!(error_arguments_count, PriceUpdate::from_input(&[]));
+            assert_eq!(
+                error_arguments_count,
+                PriceUpdate::from_input(&["1", "2", "3", "4", "5"])
+            );
+            assert_eq!(
+                error_arguments_count,
+                PriceUpdate::from_input(&["1", "2", "3", "4", "5", "6", "7"])
+            );
+        }
+
+        #[test]
+        fn price_update_wrong_timestamp() {
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::TimestampParsing)),
+                PriceUpdate::from_input(&["1", "Exchange", "ETH", "EUR", "5.0", "6.0"])
+            );
+        }
+
+        #[test]
+        fn price_update_wrong_floats() {
+            let float_error = Err(ParseCommandError(ParseErrorKind::FloatParsing));
+            let forward_factor_wrong = [
+                "2017-11-01T09:42:23+00:00",
+                "UnparsedExchange",
+                "ETH",
+                "EUR",
+                "not a float",
+                "6.0",
+            ];
+            assert_eq!(float_error, PriceUpdate::from_input(&forward_factor_wrong));
+            // the names of a line which doesn't parse are not interned
+            assert_eq!(None, ExchangeId::get("UnparsedExchange"));
+
+            let backward_factor_wrong = [
+                "2017-11-01T09:42:23+00:00",
+                "Exchange",
+                "ETH",
+                "EUR",
+                "5.0",
+                "not a float",
+            ];
+
+            assert_eq!(float_error, PriceUpdate::from_input(&backward_factor_wrong));
+        }
+    }
+
+    mod exchange_request {
+        use super::*;
+
+        #[test]
+        fn exchange_request_valid_input() {
+            assert_eq!(
+                Ok(ExchangeRequest {
+                    source_exchange: "LACHO".into(),
+                    source_currency: "BTC".into(),
+                    destination_exchange: "KRAKEN".into(),
+                    destination_currency: "USD".into(),
+                    paths_count: None,
+                    max_hops: None,
+                    max_exchange_transfers: None,
+                    amount: None,
+                    split: false,
+                    at: None,
+                }),
+                ExchangeRequest::from_input(&[
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD"
+                ])
+            );
+
+            assert_eq!(
+                Ok(Some(3)),
+                ExchangeRequest::from_input(&[
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD",
+                    "3"
+                ])
+                .map(|exchange_request| exchange_request.paths_count)
+            );
+
+            // a request only looks the names up
+            assert!(ExchangeRequest::from_input(&[
+                ExchangeRequest::COMMAND_PREFIX,
+                "RequestedExchange",
+                "BTC",
+                "KRAKEN",
+                "USD"
+            ])
+            .is_ok());
+            assert_eq!(None, ExchangeId::get("RequestedExchange"));
+        }
+
+        #[test]
+        fn exchange_request_wrong_paths_count() {
+            let integer_error = Err(ParseCommandError(ParseErrorKind::IntegerParsing));
+            let input = |paths_count| {
+                ExchangeRequest::from_input(&[
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD",
+                    paths_count,
+                ])
+            };
+
+            assert_eq!(integer_error, input("0"));
+            assert_eq!(integer_error, input("-2"));
+            assert_eq!(integer_error, input("many"));
+        }
+
+        #[test]
+        fn exchange_request_limits() {
+            let input = |options: &[&str]| {
+                let mut input_slice = vec![
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD",
+                ];
+                input_slice.extend_from_slice(options);
+
+                ExchangeRequest::from_input(&input_slice).map(|exchange_request| {
+                    (
+                        exchange_request.paths_count,
+                        exchange_request.max_hops,
+                        exchange_request.max_exchange_transfers,
+                    )
+                })
+            };
+
+            assert_eq!(Ok((None, Some(3), None)), input(&["MAX_HOPS", "3"]));
+            assert_eq!(
+                Ok((Some(2), Some(4), Some(0))),
+                input(&["2", "MAX_TRANSFERS", "0", "MAX_HOPS", "4"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                input(&["MAX_HOPS"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::IntegerParsing)),
+                input(&["MAX_TRANSFERS", "few"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::StringParsing)),
+                input(&["2", "MAX_SPEED", "1"])
+            );
+        }
+
+        #[test]
+        fn exchange_request_amount() {
+            let input = |amount| {
+                ExchangeRequest::from_input(&[
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD",
+                    "AMOUNT",
+                    amount,
+                ])
+                .map(|exchange_request| exchange_request.amount)
+            };
+            let float_error = Err(ParseCommandError(ParseErrorKind::FloatParsing));
+
+            assert_eq!(Ok(Some(2.5)), input("2.5"));
+            assert_eq!(float_error, input("0"));
+            assert_eq!(float_error, input("-1"));
+            assert_eq!(float_error, input("NaN"));
+        }
+
+        #[test]
+        fn exchange_request_amount_is_only_for_the_best_path() {
+            let input = |options: &[&str]| {
+                let mut input_slice = vec![
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD",
+                ];
+                input_slice.extend_from_slice(options);
+
+                ExchangeRequest::from_input(&input_slice)
+                    .map(|exchange_request| (exchange_request.paths_count, exchange_request.amount))
+            };
+
+            assert_eq!(Ok((Some(1), Some(2.0))), input(&["1", "AMOUNT", "2"]));
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::ConflictingOptions)),
+                input(&["3", "AMOUNT", "2"])
+            );
+        }
+
+        #[test]
+        fn exchange_request_at() {
+            let input = |options: &[&str]| {
+                let mut input_slice = vec![
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "KRAKEN",
+                    "BTC",
+                    "GDAX",
+                    "USD",
+                ];
+                input_slice.extend_from_slice(options);
+
+                ExchangeRequest::from_input(&input_slice)
+                    .map(|exchange_request| exchange_request.at)
+            };
+
+            assert_eq!(Ok(None), input(&[]));
+            assert_eq!(
+                Ok(Some(
+                    "2017-11-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap()
+                )),
+                input(&["2", "AT", "2017-11-01T10:00:00Z"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::TimestampParsing)),
+                input(&["AT", "yesterday"])
+            );
+            for options in &[
+                &["AT", "2017-11-01T10:00:00Z", "AMOUNT", "2"][..],
+                &["AMOUNT", "2", "SPLIT", "AT", "2017-11-01T10:00:00Z"][..],
+            ] {
+                assert_eq!(
+                    Err(ParseCommandError(ParseErrorKind::ConflictingOptions)),
+                    input(options)
+                );
+            }
+        }
+
+        #[test]
+        fn exchange_request_split() {
+            let input = |options: &[&str]| {
+                let mut input_slice = vec![
+                    ExchangeRequest::COMMAND_PREFIX,
+                    "LACHO",
+                    "BTC",
+                    "KRAKEN",
+                    "USD",
+                ];
+                input_slice.extend_from_slice(options);
+
+                ExchangeRequest::from_input(&input_slice)
+                    .map(|exchange_request| (exchange_request.amount, exchange_request.split))
+            };
+
+            assert_eq!(Ok((Some(5.0), true)), input(&["SPLIT", "AMOUNT", "5"]));
+            assert_eq!(Ok((Some(5.0), true)), input(&["AMOUNT", "5", "SPLIT"]));
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                input(&["SPLIT"])
+            );
+        }
+    }
+
+    mod transfer_cost_update {
+        use super::*;
+
+        #[test]
+        fn transfer_cost_update_valid_input() {
+            assert_eq!(
+                Ok(TransferCostUpdate {
+                    from_exchange: "KRAKEN".to_owned(),
+                    to_exchange: "GDAX".to_owned(),
+                    currency: "BTC".to_owned(),
+                    percent_fee: 0.1,
+                    fixed_fee: 0.0,
+                }),
+                TransferCostUpdate::from_input(&["TRANSFER_COST", "KRAKEN", "GDAX", "BTC", "0.1"])
+            );
+            assert_eq!(
+                Ok(0.0005),
+                TransferCostUpdate::from_input(&[
+                    "TRANSFER_COST",
+                    "KRAKEN",
+                    "GDAX",
+                    "BTC",
+                    "0.1",
+                    "0.0005"
+                ])
+                .map(|transfer_cost| transfer_cost.fixed_fee)
+            );
+        }
+
+        #[test]
+        fn transfer_cost_update_wrong_input() {
+            let input = |percent_fee, fixed_fee| {
+                TransferCostUpdate::from_input(&[
+                    "TRANSFER_COST",
+                    "KRAKEN",
+                    "GDAX",
+                    "BTC",
+                    percent_fee,
+                    fixed_fee,
+                ])
+            };
+            let fee_error = Err(ParseCommandError(ParseErrorKind::FeeRange));
+
+            assert_eq!(fee_error, input("100", "0"));
+            assert_eq!(fee_error, input("-0.1", "0"));
+            assert_eq!(fee_error, input("0.1", "-1"));
+            assert_eq!(fee_error, input("0.1", "inf"));
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::FloatParsing)),
+                input("free", "0")
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                TransferCostUpdate::from_input(&["TRANSFER_COST", "KRAKEN", "GDAX", "BTC"])
+            );
+        }
+    }
+
+    mod trading_fee_update {
+        use super::*;
+
+        #[test]
+        fn trading_fee_update_valid_input() {
+            assert_eq!(
+                Ok(TradingFeeUpdate {
+                    exchange: "KRAKEN".to_owned(),
+                    percent_fee: 0.26,
+                    pair: None,
+                }),
+                TradingFeeUpdate::from_input(&["TRADING_FEE", "KRAKEN", "0.26"])
+            );
+            assert_eq!(
+                Ok(TradingFeeUpdate {
+                    exchange: "KRAKEN".to_owned(),
+                    percent_fee: 0.1,
+                    pair: Some(("BTC".to_owned(), "USD".to_owned())),
+                }),
+                TradingFeeUpdate::from_input(&["TRADING_FEE", "KRAKEN", "0.1", "BTC", "USD"])
+            );
+        }
+
+        #[test]
+        fn trading_fee_update_wrong_input() {
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                TradingFeeUpdate::from_input(&["TRADING_FEE", "KRAKEN", "0.1", "BTC"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::FeeRange)),
+                TradingFeeUpdate::from_input(&["TRADING_FEE", "KRAKEN", "120"])
+            );
+        }
+    }
+
+    mod edge_limit_update {
+        use super::*;
+
+        #[test]
+        fn edge_limit_update_input() {
+            assert_eq!(
+                Ok(EdgeLimitUpdate {
+                    from_exchange: "KRAKEN".into(),
+                    from_currency: "BTC".into(),
+                    to_exchange: "GDAX".into(),
+                    to_currency: "BTC".into(),
+                    limit: 2.5,
+                }),
+                EdgeLimitUpdate::from_input(&["EDGE_LIMIT", "KRAKEN", "BTC", "GDAX", "BTC", "2.5"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::FloatParsing)),
+                EdgeLimitUpdate::from_input(&["EDGE_LIMIT", "KRAKEN", "BTC", "GDAX", "BTC", "-1"])
+            );
+
+            // the names are interned once the limit is set, not when it's parsed
+            EdgeLimitUpdate::from_input(&[
+                "EDGE_LIMIT",
+                "LimitedExchange",
+                "BTC",
+                "GDAX",
+                "BTC",
+                "1",
+            ])
+            .unwrap();
+            assert_eq!(None, ExchangeId::get("LimitedExchange"));
+        }
+    }
+
+    mod remove_updates {
+        use super::*;
+
+        #[test]
+        fn remove_pair_and_exchange_input() {
+            assert_eq!(
+                Ok(RemovePairUpdate {
+                    exchange: "KRAKEN".into(),
+                    currency: "BTC".into(),
+                    other_currency: "USD".into(),
+                    timestamp: None,
+                }),
+                RemovePairUpdate::from_input(&["REMOVE_PAIR", "KRAKEN", "BTC", "USD"])
+            );
+            assert_eq!(
+                Ok(Some(
+                    "2017-11-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap()
+                )),
+                RemovePairUpdate::from_input(&[
+                    "REMOVE_PAIR",
+                    "KRAKEN",
+                    "BTC",
+                    "USD",
+                    "2017-11-01T10:00:00Z"
+                ])
+                .map(|remove_pair_update| remove_pair_update.timestamp)
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::TimestampParsing)),
+                RemoveExchangeUpdate::from_input(&["REMOVE_EXCHANGE", "KRAKEN", "today"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                RemovePairUpdate::from_input(&["REMOVE_PAIR", "KRAKEN", "BTC"])
+            );
+            assert_eq!(
+                Ok(RemoveExchangeUpdate {
+                    exchange: "KRAKEN".into(),
+                    timestamp: None,
+                }),
+                RemoveExchangeUpdate::from_input(&["REMOVE_EXCHANGE", "KRAKEN"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                RemoveExchangeUpdate::from_input(&["REMOVE_EXCHANGE"])
+            );
+
+            // removing an unknown exchange doesn't intern it
+            assert!(
+                RemoveExchangeUpdate::from_input(&["REMOVE_EXCHANGE", "RemovedExchange"]).is_ok()
+            );
+            assert_eq!(None, ExchangeId::get("RemovedExchange"));
+        }
+    }
+
+    mod graph_stats_request {
+        use super::*;
+
+        #[test]
+        fn from_input() {
+            assert_eq!(
+                Ok(GraphStatsRequest { top_exchanges: 5 }),
+                GraphStatsRequest::from_input(&["GRAPH_STATS"])
+            );
+            assert_eq!(
+                Ok(GraphStatsRequest { top_exchanges: 10 }),
+                GraphStatsRequest::from_input(&["GRAPH_STATS", "10"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::IntegerParsing)),
+                GraphStatsRequest::from_input(&["GRAPH_STATS", "-1"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                GraphStatsRequest::from_input(&["GRAPH_STATS", "10", "KRAKEN"])
+            );
+        }
+    }
+
+    mod export_dot_request {
+        use super::*;
+
+        #[test]
+        fn from_input() {
+            let request = ExchangeRequest {
+                source_exchange: "KRAKEN".into(),
+                source_currency: "BTC".into(),
+                destination_exchange: "GDAX".into(),
+                destination_currency: "USD".into(),
+                ..ExchangeRequest::default()
+            };
+
+            assert_eq!(
+                Ok(ExportDotRequest {
+                    path: None,
+                    highlight: None,
+                }),
+                ExportDotRequest::from_input(&["EXPORT_DOT"])
+            );
+            assert_eq!(
+                Ok(ExportDotRequest {
+                    path: Some(PathBuf::from("graph.dot")),
+                    highlight: None,
+                }),
+                ExportDotRequest::from_input(&["EXPORT_DOT", "graph.dot"])
+            );
+            assert_eq!(
+                Ok(ExportDotRequest {
+                    path: Some(PathBuf::from("graph.dot")),
+                    highlight: Some(request),
+                }),
+                ExportDotRequest::from_input(&[
+                    "EXPORT_DOT",
+                    "graph.dot",
+                    "KRAKEN",
+                    "BTC",
+                    "GDAX",
+                    "USD"
+                ])
+            );
+            assert_eq!(
+                Ok(None),
+                ExportDotRequest::from_input(&["EXPORT_DOT", "KRAKEN", "BTC", "GDAX", "USD"])
+                    .map(|export_dot_request| export_dot_request.path)
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                ExportDotRequest::from_input(&["EXPORT_DOT", "KRAKEN", "BTC", "GDAX"])
+            );
+        }
+    }
+
+    mod snapshot_request {
+        use super::*;
+
+        #[test]
+        fn snapshot_request_input() {
+            assert_eq!(
+                Ok(SnapshotRequest {
+                    action: SnapshotAction::Save,
+                    path: PathBuf::from("/tmp/graph.snapshot"),
+                }),
+                SnapshotRequest::from_input(&["SNAPSHOT", "SAVE", "/tmp/graph.snapshot"])
+            );
+            assert_eq!(
+                Ok(SnapshotAction::Load),
+                SnapshotRequest::from_input(&["SNAPSHOT", "LOAD", "graph.snapshot"])
+                    .map(|snapshot_request| snapshot_request.action)
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::StringParsing)),
+                SnapshotRequest::from_input(&["SNAPSHOT", "DELETE", "graph.snapshot"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                SnapshotRequest::from_input(&["SNAPSHOT", "SAVE"])
+            );
+        }
+    }
+
+    mod validation_policy_update {
+        use super::*;
+
+        #[test]
+        fn validation_policy_update_input() {
+            assert_eq!(
+                Ok(ValidationPolicyUpdate {
+                    policy: ValidationPolicy::Clamp
+                }),
+                ValidationPolicyUpdate::from_input(&["VALIDATION_POLICY", "CLAMP"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::StringParsing)),
+                ValidationPolicyUpdate::from_input(&["VALIDATION_POLICY", "IGNORE"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                ValidationPolicyUpdate::from_input(&["VALIDATION_POLICY"])
+            );
+        }
+    }
+
+    mod max_age_update {
+        use super::*;
+
+        #[test]
+        fn max_age_update_input() {
+            assert_eq!(
+                Ok(MaxAgeUpdate {
+                    max_age: Duration::seconds(60),
+                    exchange: None,
+                }),
+                MaxAgeUpdate::from_input(&["MAX_AGE", "60"])
+            );
+            assert_eq!(
+                Ok(MaxAgeUpdate {
+                    max_age: Duration::seconds(300),
+                    exchange: Some("KRAKEN".to_owned()),
+                }),
+                MaxAgeUpdate::from_input(&["MAX_AGE", "300", "KRAKEN"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::IntegerParsing)),
+                MaxAgeUpdate::from_input(&["MAX_AGE", "-1"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                MaxAgeUpdate::from_input(&["MAX_AGE"])
+            );
+        }
+    }
+
+    mod withdrawal_fee_update {
+        use super::*;
+
+        #[test]
+        fn withdrawal_fee_update_input() {
+            assert_eq!(
+                Ok(WithdrawalFeeUpdate {
+                    exchange: "KRAKEN".to_owned(),
+                    currency: "BTC".to_owned(),
+                    fee: 0.0005,
+                }),
+                WithdrawalFeeUpdate::from_input(&["WITHDRAWAL_FEE", "KRAKEN", "BTC", "0.0005"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::FeeRange)),
+                WithdrawalFeeUpdate::from_input(&["WITHDRAWAL_FEE", "KRAKEN", "BTC", "-1"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                WithdrawalFeeUpdate::from_input(&["WITHDRAWAL_FEE", "KRAKEN", "BTC"])
+            );
+        }
+    }
+
+    mod order_book_update {
+        use super::*;
+
+        #[test]
+        fn order_book_update_valid_input() {
+            let order_book = OrderBookUpdate::from_input(&[
+                OrderBookUpdate::COMMAND_PREFIX,
+                "2017-11-01T09:42:23+00:00",
+                "KRAKEN",
+                "BTC",
+                "USD",
+                "990@2,1000@0.5",
+                "1010@1.5,1005@0.1",
+            ])
+            .unwrap();
+
+            // the best levels first
+            assert_eq!(
+                vec![
+                    BookLevel {
+                        price: 1000.0,
+                        quantity: 0.5,
+                    },
+                    BookLevel {
+                        price: 990.0,
+                        quantity: 2.0,
+                    },
+                ],
+                order_book.bids
+            );
+            assert_eq!(1005.0, order_book.asks[0].price);
+
+            let price_update = order_book.price_update();
+            assert_eq!(1000.0, price_update.forward_factor);
+            assert_eq!(1.0 / 1005.0, price_update.backward_factor);
+        }
+
+        #[test]
+        fn order_book_update_wrong_levels() {
+            let input = |bids| {
+                OrderBookUpdate::from_input(&[
+                    OrderBookUpdate::COMMAND_PREFIX,
+                    "2017-11-01T09:42:23+00:00",
+                    "KRAKEN",
+                    "BTC",
+                    "USD",
+                    bids,
+                    "1010@1.5",
+                ])
+            };
+            let level_error = Err(ParseCommandError(ParseErrorKind::BookLevelParsing));
+
+            assert_eq!(level_error, input(""));
+            assert_eq!(level_error, input("990"));
+            assert_eq!(level_error, input("990@"));
+            assert_eq!(level_error, input("990@1,-5@1"));
+            assert_eq!(level_error, input("990@0"));
+        }
+    }
+
+    mod arbitrage_request {
+        use super::*;
+
+        #[test]
+        fn arbitrage_request_valid_input() {
+            assert_eq!(
+                Ok(ArbitrageRequest::default()),
+                ArbitrageRequest::from_input(&[ArbitrageRequest::COMMAND_PREFIX])
+            );
+            assert_eq!(
+                Ok(ArbitrageRequest {
+                    min_profit: 0.05,
+                    max_length: ArbitrageRequest::DEFAULT_MAX_LENGTH,
+                }),
+                ArbitrageRequest::from_input(&[ArbitrageRequest::COMMAND_PREFIX, "0.05"])
+            );
+            assert_eq!(
+                Ok(ArbitrageRequest {
+                    min_profit: 0.05,
+                    max_length: 10,
+                }),
+                ArbitrageRequest::from_input(&[ArbitrageRequest::COMMAND_PREFIX, "0.05", "10"])
+            );
+        }
+
+        #[test]
+        fn arbitrage_request_wrong_input() {
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::FloatParsing)),
+                ArbitrageRequest::from_input(&[ArbitrageRequest::COMMAND_PREFIX, "much"])
+            );
+            assert_eq!(
+                Err(ParseCommandError(ParseErrorKind::RequiredArgumentsCount)),
+                ArbitrageRequest::from_input(&[ArbitrageRequest::COMMAND_PREFIX, "0.1", "4", "5"])
+            );
+            for max_length in &["0", "0.2"] {
+                assert_eq!(
+                    Err(ParseCommandError(ParseErrorKind::IntegerParsing)),
+                    ArbitrageRequest::from_input(&[
+                        ArbitrageRequest::COMMAND_PREFIX,
+                        "0.1",
+                        max_length
+                    ])
+                );
+            }
+
+            let profit_error = Err(ParseCommandError(ParseErrorKind::ProfitRange));
+            for min_profit in &["-0.05", "NaN", "inf"] {
+                assert_eq!(
+                    profit_error,
+                    ArbitrageRequest::from_input(&[ArbitrageRequest::COMMAND_PREFIX, min_profit])
+                );
+            }
         }
     }
 }
@@ -1,11 +1,29 @@
 use std::fmt;
 
-use crate::command::{ExchangeRequest, PriceUpdate};
+use crate::command::{
+    ArbitrageRequest, EdgeLimitUpdate, ExchangeRequest, ExportDotRequest, GraphStatsRequest,
+    MaxAgeUpdate, OrderBookUpdate, PriceUpdate, RemoveExchangeUpdate, RemovePairUpdate,
+    SnapshotRequest, TradingFeeUpdate, TransferCostUpdate, ValidationPolicyUpdate,
+    WithdrawalFeeUpdate,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum TryParseCommand {
     PriceUpdate,
     ExchangeRequest,
+    ArbitrageRequest,
+    TransferCostUpdate,
+    TradingFeeUpdate,
+    OrderBookUpdate,
+    WithdrawalFeeUpdate,
+    EdgeLimitUpdate,
+    MaxAgeUpdate,
+    ValidationPolicyUpdate,
+    RemovePairUpdate,
+    RemoveExchangeUpdate,
+    SnapshotRequest,
+    ExportDotRequest,
+    GraphStatsRequest,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
@@ -14,7 +32,20 @@ pub enum ParseErrorKind {
     RequiredArgumentsCount,
     TimestampParsing,
     FloatParsing,
+    IntegerParsing,
     StringParsing,
+    FeeRange,
+    BookLevelParsing,
+    NegativeFactor,
+    ZeroFactor,
+    NaNFactor,
+    InfiniteFactor,
+    /// Exchanging forward and then backward on the pair makes money
+    ProfitableRoundTrip,
+    /// Options of the command which can't be used together
+    ConflictingOptions,
+    /// The minimum profit of an arbitrage request is negative or not finite
+    ProfitRange,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
@@ -27,7 +58,17 @@ impl fmt::Display for ParseCommandError {
             ParseErrorKind::RequiredArgumentsCount => "Invalid number of arguments provided",
             ParseErrorKind::TimestampParsing => "Timestamp format",
             ParseErrorKind::FloatParsing => "Invalid float",
+            ParseErrorKind::IntegerParsing => "Invalid positive integer",
             ParseErrorKind::StringParsing => "Parsing argument failed",
+            ParseErrorKind::FeeRange => "Fee out of range",
+            ParseErrorKind::BookLevelParsing => "Invalid order book level",
+            ParseErrorKind::NegativeFactor => "Negative factor",
+            ParseErrorKind::ZeroFactor => "Zero factor",
+            ParseErrorKind::NaNFactor => "Factor is not a number",
+            ParseErrorKind::InfiniteFactor => "Infinite factor",
+            ParseErrorKind::ProfitableRoundTrip => "Profitable round trip on the pair",
+            ParseErrorKind::ConflictingOptions => "Options which can't be used together",
+            ParseErrorKind::ProfitRange => "Minimum profit out of range",
         };
 
         error_description.fmt(f)
@@ -38,15 +79,28 @@ impl fmt::Display for ParseCommandError {
 pub enum ParsedLine {
     PriceUpdate(PriceUpdate),
     ExchangeRequest(ExchangeRequest),
+    ArbitrageRequest(ArbitrageRequest),
+    TransferCostUpdate(TransferCostUpdate),
+    TradingFeeUpdate(TradingFeeUpdate),
+    OrderBookUpdate(OrderBookUpdate),
+    WithdrawalFeeUpdate(WithdrawalFeeUpdate),
+    EdgeLimitUpdate(EdgeLimitUpdate),
+    MaxAgeUpdate(MaxAgeUpdate),
+    ValidationPolicyUpdate(ValidationPolicyUpdate),
+    RemovePairUpdate(RemovePairUpdate),
+    RemoveExchangeUpdate(RemoveExchangeUpdate),
+    SnapshotRequest(SnapshotRequest),
+    ExportDotRequest(ExportDotRequest),
+    GraphStatsRequest(GraphStatsRequest),
 }
 
 pub fn parse_line(input_str: &str) -> Result<ParsedLine, ParseCommandError> {
     let no_input = ParseCommandError(ParseErrorKind::NoInput);
 
     let line = input_str.lines().next().ok_or(no_input)?;
-    let input: Vec<&str> = line.trim().split_whitespace().collect();
+    let input: Vec<&str> = line.split_whitespace().collect();
 
-    let first_argument = input.get(0).ok_or(no_input)?;
+    let first_argument = input.first().ok_or(no_input)?;
     let try_to_parse_command = which_try_to_parse_command(first_argument);
 
     match try_to_parse_command {
@@ -60,6 +114,84 @@ pub fn parse_line(input_str: &str) -> Result<ParsedLine, ParseCommandError> {
             let exchange_request = ExchangeRequest::from_input(&input)?;
             let parsed_line = ParsedLine::ExchangeRequest(exchange_request);
 
+            Ok(parsed_line)
+        }
+        TryParseCommand::ArbitrageRequest => {
+            let arbitrage_request = ArbitrageRequest::from_input(&input)?;
+            let parsed_line = ParsedLine::ArbitrageRequest(arbitrage_request);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::TransferCostUpdate => {
+            let transfer_cost_update = TransferCostUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::TransferCostUpdate(transfer_cost_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::TradingFeeUpdate => {
+            let trading_fee_update = TradingFeeUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::TradingFeeUpdate(trading_fee_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::OrderBookUpdate => {
+            let order_book_update = OrderBookUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::OrderBookUpdate(order_book_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::WithdrawalFeeUpdate => {
+            let withdrawal_fee_update = WithdrawalFeeUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::WithdrawalFeeUpdate(withdrawal_fee_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::EdgeLimitUpdate => {
+            let edge_limit_update = EdgeLimitUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::EdgeLimitUpdate(edge_limit_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::MaxAgeUpdate => {
+            let max_age_update = MaxAgeUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::MaxAgeUpdate(max_age_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::ValidationPolicyUpdate => {
+            let validation_policy_update = ValidationPolicyUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::ValidationPolicyUpdate(validation_policy_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::RemovePairUpdate => {
+            let remove_pair_update = RemovePairUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::RemovePairUpdate(remove_pair_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::RemoveExchangeUpdate => {
+            let remove_exchange_update = RemoveExchangeUpdate::from_input(&input)?;
+            let parsed_line = ParsedLine::RemoveExchangeUpdate(remove_exchange_update);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::SnapshotRequest => {
+            let snapshot_request = SnapshotRequest::from_input(&input)?;
+            let parsed_line = ParsedLine::SnapshotRequest(snapshot_request);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::ExportDotRequest => {
+            let export_dot_request = ExportDotRequest::from_input(&input)?;
+            let parsed_line = ParsedLine::ExportDotRequest(export_dot_request);
+
+            Ok(parsed_line)
+        }
+        TryParseCommand::GraphStatsRequest => {
+            let graph_stats_request = GraphStatsRequest::from_input(&input)?;
+            let parsed_line = ParsedLine::GraphStatsRequest(graph_stats_request);
+
             Ok(parsed_line)
         }
     }
@@ -68,6 +200,32 @@ pub fn parse_line(input_str: &str) -> Result<ParsedLine, ParseCommandError> {
 fn which_try_to_parse_command(candidate: &str) -> TryParseCommand {
     if candidate == ExchangeRequest::COMMAND_PREFIX {
         TryParseCommand::ExchangeRequest
+    } else if candidate == ArbitrageRequest::COMMAND_PREFIX {
+        TryParseCommand::ArbitrageRequest
+    } else if candidate == TransferCostUpdate::COMMAND_PREFIX {
+        TryParseCommand::TransferCostUpdate
+    } else if candidate == TradingFeeUpdate::COMMAND_PREFIX {
+        TryParseCommand::TradingFeeUpdate
+    } else if candidate == OrderBookUpdate::COMMAND_PREFIX {
+        TryParseCommand::OrderBookUpdate
+    } else if candidate == WithdrawalFeeUpdate::COMMAND_PREFIX {
+        TryParseCommand::WithdrawalFeeUpdate
+    } else if candidate == EdgeLimitUpdate::COMMAND_PREFIX {
+        TryParseCommand::EdgeLimitUpdate
+    } else if candidate == MaxAgeUpdate::COMMAND_PREFIX {
+        TryParseCommand::MaxAgeUpdate
+    } else if candidate == ValidationPolicyUpdate::COMMAND_PREFIX {
+        TryParseCommand::ValidationPolicyUpdate
+    } else if candidate == RemovePairUpdate::COMMAND_PREFIX {
+        TryParseCommand::RemovePairUpdate
+    } else if candidate == RemoveExchangeUpdate::COMMAND_PREFIX {
+        TryParseCommand::RemoveExchangeUpdate
+    } else if candidate == SnapshotRequest::COMMAND_PREFIX {
+        TryParseCommand::SnapshotRequest
+    } else if candidate == ExportDotRequest::COMMAND_PREFIX {
+        TryParseCommand::ExportDotRequest
+    } else if candidate == GraphStatsRequest::COMMAND_PREFIX {
+        TryParseCommand::GraphStatsRequest
     } else {
         TryParseCommand::PriceUpdate
     }
@@ -97,7 +255,7 @@ mod test {
     fn parse_line_parses_single_line_only() {
         let line = "  2017-11-01T09:42:23+00:00 KRAKEN BTC USD 1000.0 0.0009  \nanother line";
 
-        assert_eq!(true, parse_line(line).is_ok());
+        assert!(parse_line(line).is_ok());
     }
 
     #[test]
@@ -108,9 +266,9 @@ mod test {
             timestamp: "2017-11-01T09:42:23+00:00"
                 .parse::<DateTime<Utc>>()
                 .unwrap(),
-            exchange: "KRAKEN".to_owned(),
-            source_currency: "BTC".to_owned(),
-            destination_currency: "USD".to_owned(),
+            exchange: "KRAKEN".into(),
+            source_currency: "BTC".into(),
+            destination_currency: "USD".into(),
             forward_factor: 1000.0,
             backward_factor: 0.0009,
         };
@@ -124,10 +282,11 @@ mod test {
         let line = "EXCHANGE_RATE_REQUEST LACHO BTC KRAKEN USD";
 
         let exchange_request_expected = ExchangeRequest {
-            source_exchange: "LACHO".to_owned(),
-            source_currency: "BTC".to_owned(),
-            destination_exchange: "KRAKEN".to_owned(),
-            destination_currency: "USD".to_owned(),
+            source_exchange: "LACHO".into(),
+            source_currency: "BTC".into(),
+            destination_exchange: "KRAKEN".into(),
+            destination_currency: "USD".into(),
+            ..ExchangeRequest::default()
         };
         let parsed_line_expected = ParsedLine::ExchangeRequest(exchange_request_expected);
 
@@ -135,12 +294,75 @@ mod test {
     }
 
     #[test]
-    fn which_try_to_command_handles_both_commands() {
-        // only the &str literal "EXCHANGE_RATE_REQUEST" should end up in ExchangeRequest Enum
+    fn parse_line_parses_arbitrage_request_line() {
+        assert_eq!(
+            Ok(ParsedLine::ArbitrageRequest(ArbitrageRequest {
+                min_profit: 0.01,
+                ..ArbitrageRequest::default()
+            })),
+            parse_line("ARBITRAGE_REQUEST 0.01")
+        );
+    }
+
+    #[test]
+    fn which_try_to_command_handles_all_commands() {
+        // only the command prefixes should end up in their Enums, everything else is a PriceUpdate
         assert_eq!(
             TryParseCommand::ExchangeRequest,
             which_try_to_parse_command(ExchangeRequest::COMMAND_PREFIX)
         );
+        assert_eq!(
+            TryParseCommand::ArbitrageRequest,
+            which_try_to_parse_command(ArbitrageRequest::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::TransferCostUpdate,
+            which_try_to_parse_command(TransferCostUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::TradingFeeUpdate,
+            which_try_to_parse_command(TradingFeeUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::OrderBookUpdate,
+            which_try_to_parse_command(OrderBookUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::WithdrawalFeeUpdate,
+            which_try_to_parse_command(WithdrawalFeeUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::EdgeLimitUpdate,
+            which_try_to_parse_command(EdgeLimitUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::MaxAgeUpdate,
+            which_try_to_parse_command(MaxAgeUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::ValidationPolicyUpdate,
+            which_try_to_parse_command(ValidationPolicyUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::RemovePairUpdate,
+            which_try_to_parse_command(RemovePairUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::RemoveExchangeUpdate,
+            which_try_to_parse_command(RemoveExchangeUpdate::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::SnapshotRequest,
+            which_try_to_parse_command(SnapshotRequest::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::ExportDotRequest,
+            which_try_to_parse_command(ExportDotRequest::COMMAND_PREFIX)
+        );
+        assert_eq!(
+            TryParseCommand::GraphStatsRequest,
+            which_try_to_parse_command(GraphStatsRequest::COMMAND_PREFIX)
+        );
         assert_eq!(
             TryParseCommand::PriceUpdate,
             which_try_to_parse_command("does not matter")
@@ -0,0 +1,154 @@
+use crate::command::PriceUpdate;
+use crate::parse::parsing::{ParseCommandError, ParseErrorKind};
+
+/// Tolerance for the rounding of the quotes, the same as the one of the rates,
+/// e.g. quotes of `1500` and `0.000666667` shouldn't look profitable
+const ROUND_TRIP_EPSILON: f64 = 1e-6;
+
+/// What to do with the price updates which are not valid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    Reject,
+    /// Scales down the factors of a profitable round trip, so it neither makes nor loses money
+    Clamp,
+    /// Accepts the profitable round trips as they are, as they have always been
+    #[default]
+    Warn,
+}
+
+/// The result of validating a price update with the policy.
+/// Factors which are not finite and positive can't be the weights of the graph,
+/// so they are always rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    Valid,
+    Clamped(ParseCommandError),
+    Warned(ParseCommandError),
+    Rejected(ParseCommandError),
+}
+
+impl ValidationPolicy {
+    pub fn apply(self, price_update: &mut PriceUpdate) -> Validation {
+        let error = match validate(price_update) {
+            Ok(()) => return Validation::Valid,
+            Err(error) => error,
+        };
+
+        match (error.0, self) {
+            (ParseErrorKind::ProfitableRoundTrip, ValidationPolicy::Clamp) => {
+                let scale = (price_update.forward_factor * price_update.backward_factor).sqrt();
+                price_update.forward_factor /= scale;
+                price_update.backward_factor /= scale;
+
+                Validation::Clamped(error)
+            }
+            (ParseErrorKind::ProfitableRoundTrip, ValidationPolicy::Warn) => {
+                Validation::Warned(error)
+            }
+            _ => Validation::Rejected(error),
+        }
+    }
+}
+
+/// Both factors should be finite and positive,
+/// and exchanging forward and then backward should never make money
+pub fn validate(price_update: &PriceUpdate) -> Result<(), ParseCommandError> {
+    validate_factor(price_update.forward_factor)?;
+    validate_factor(price_update.backward_factor)?;
+
+    if price_update.forward_factor * price_update.backward_factor > 1.0 + ROUND_TRIP_EPSILON {
+        return Err(ParseCommandError(ParseErrorKind::ProfitableRoundTrip));
+    }
+
+    Ok(())
+}
+
+fn validate_factor(factor: f64) -> Result<(), ParseCommandError> {
+    let error_kind = if factor.is_nan() {
+        ParseErrorKind::NaNFactor
+    } else if factor.is_infinite() {
+        ParseErrorKind::InfiniteFactor
+    } else if factor < 0.0 {
+        ParseErrorKind::NegativeFactor
+    } else if factor == 0.0 {
+        ParseErrorKind::ZeroFactor
+    } else {
+        return Ok(());
+    };
+
+    Err(ParseCommandError(error_kind))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn price_update(forward_factor: f64, backward_factor: f64) -> PriceUpdate {
+        PriceUpdate::new(
+            Utc::now(),
+            "KRAKEN",
+            "BTC",
+            "USD",
+            forward_factor,
+            backward_factor,
+        )
+    }
+
+    #[test]
+    fn it_has_a_distinct_error_for_each_invalid_factor() {
+        let error = |forward_factor, backward_factor| {
+            validate(&price_update(forward_factor, backward_factor)).map_err(|error| error.0)
+        };
+
+        assert_eq!(Ok(()), error(1000.0, 0.0009));
+        assert_eq!(Ok(()), error(1000.0, 0.001));
+        assert_eq!(Err(ParseErrorKind::NegativeFactor), error(-1000.0, 0.0009));
+        assert_eq!(Err(ParseErrorKind::ZeroFactor), error(1000.0, 0.0));
+        assert_eq!(Err(ParseErrorKind::NaNFactor), error(f64::NAN, 0.0009));
+        assert_eq!(
+            Err(ParseErrorKind::InfiniteFactor),
+            error(1000.0, f64::NEG_INFINITY)
+        );
+        assert_eq!(
+            Err(ParseErrorKind::ProfitableRoundTrip),
+            error(1000.0, 0.002)
+        );
+    }
+
+    #[test]
+    fn the_policy_decides_about_the_profitable_round_trips() {
+        let round_trip = ParseCommandError(ParseErrorKind::ProfitableRoundTrip);
+
+        let mut rejected = price_update(1000.0, 0.004);
+        assert_eq!(
+            Validation::Rejected(round_trip),
+            ValidationPolicy::Reject.apply(&mut rejected)
+        );
+
+        let mut warned = price_update(1000.0, 0.004);
+        assert_eq!(
+            Validation::Warned(round_trip),
+            ValidationPolicy::Warn.apply(&mut warned)
+        );
+        assert_eq!(0.004, warned.backward_factor);
+
+        let mut clamped = price_update(1000.0, 0.004);
+        assert_eq!(
+            Validation::Clamped(round_trip),
+            ValidationPolicy::Clamp.apply(&mut clamped)
+        );
+        assert_eq!(
+            (500.0, 0.002),
+            (clamped.forward_factor, clamped.backward_factor)
+        );
+
+        // the invalid factors are rejected whatever the policy
+        let mut negative = price_update(-1000.0, 0.0009);
+        assert_eq!(
+            Validation::Rejected(ParseCommandError(ParseErrorKind::NegativeFactor)),
+            ValidationPolicy::Clamp.apply(&mut negative)
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+/// The names of the exchanges and the currencies, each name is stored once
+/// and lives for the whole program. Only the names which enter the state are interned,
+/// the requests look the names up without interning them
+#[derive(Debug)]
+struct Interner {
+    symbols: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        // the empty name is the default symbol
+        Self {
+            symbols: [("", 0)].iter().cloned().collect(),
+            names: vec![""],
+        }
+    }
+
+    fn global() -> &'static RwLock<Interner> {
+        static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+
+        INTERNER.get_or_init(|| RwLock::new(Interner::new()))
+    }
+
+    fn get(name: &str) -> Option<Symbol> {
+        Self::global()
+            .read()
+            .unwrap()
+            .symbols
+            .get(name)
+            .map(|&symbol| Symbol(symbol))
+    }
+
+    fn intern(name: &str) -> Symbol {
+        let interner = Self::global();
+
+        if let Some(&symbol) = interner.read().unwrap().symbols.get(name) {
+            return Symbol(symbol);
+        }
+
+        let mut interner = interner.write().unwrap();
+        // it might have been interned while waiting for the lock
+        if let Some(&symbol) = interner.symbols.get(name) {
+            return Symbol(symbol);
+        }
+
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let symbol = interner.names.len() as u32;
+        interner.names.push(name);
+        interner.symbols.insert(name, symbol);
+
+        Symbol(symbol)
+    }
+
+    fn resolve(symbol: Symbol) -> &'static str {
+        Self::global().read().unwrap().names[symbol.0 as usize]
+    }
+}
+
+/// An interned name, equal names have the same symbol
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+struct Symbol(u32);
+
+macro_rules! symbol_type {
+    ($(#[$attribute:meta])* $name:ident) => {
+        $(#[$attribute])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+        pub struct $name(Symbol);
+
+        impl $name {
+            pub fn new(name: &str) -> Self {
+                $name(Interner::intern(name))
+            }
+
+            /// The symbol of an interned name, `None` for a name which was never interned
+            pub fn get(name: &str) -> Option<Self> {
+                Interner::get(name).map($name)
+            }
+
+            pub fn as_str(&self) -> &'static str {
+                Interner::resolve(self.0)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(name: &str) -> Self {
+                Self::new(name)
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.as_str() == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.as_str() == *other
+            }
+        }
+
+        impl PartialEq<$name> for &str {
+            fn eq(&self, other: &$name) -> bool {
+                *self == other.as_str()
+            }
+        }
+
+        /// Ordered by the names, not by the order of interning
+        impl Ord for $name {
+            fn cmp(&self, other: &Self) -> Ordering {
+                if self.0 == other.0 {
+                    return Ordering::Equal;
+                }
+
+                self.as_str().cmp(other.as_str())
+            }
+        }
+
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({:?})", stringify!($name), self.as_str())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+    };
+}
+
+symbol_type!(
+    /// The interned name of an exchange
+    ExchangeId
+);
+
+symbol_type!(
+    /// The interned name of a currency
+    CurrencyId
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn equal_names_are_the_same_symbol() {
+        let kraken = ExchangeId::new("KRAKEN");
+
+        assert_eq!(kraken, ExchangeId::from("KRAKEN"));
+        assert_ne!(kraken, ExchangeId::new("GDAX"));
+        assert_eq!("KRAKEN", kraken.as_str());
+        assert_eq!("KRAKEN", kraken.to_string());
+        assert!(kraken == "KRAKEN");
+        assert_eq!("", CurrencyId::default().as_str());
+
+        // the names are ordered alphabetically whenever they were interned
+        let (usd, btc) = (CurrencyId::new("USD"), CurrencyId::new("BTC"));
+        assert!(btc < usd);
+
+        // looking a name up doesn't intern it
+        assert_eq!(Some(usd), CurrencyId::get("USD"));
+        assert_eq!(None, CurrencyId::get("NEVER_INTERNED"));
+        assert_eq!(None, CurrencyId::get("NEVER_INTERNED"));
+    }
+}